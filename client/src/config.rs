@@ -0,0 +1,286 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{unix, Command, Error, Metrics};
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use reqwest::{Method, RequestBuilder, Response, Url};
+use serde::Deserialize;
+use structopt::StructOpt;
+
+/// A saved server target, as written in the configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+struct Saved {
+    url: Option<String>,
+    token: Option<String>,
+    region: Option<String>,
+}
+
+/// The client configuration file.
+///
+/// ```json
+/// {
+///   "default": "dev",
+///   "profiles": {
+///     "dev": { "url": "http://localhost:3030/" },
+///     "prod": { "url": "https://enarx.example.com/", "token": "...", "region": "eu" }
+///   }
+/// }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    default: Option<String>,
+
+    #[serde(default)]
+    profiles: BTreeMap<String, Saved>,
+}
+
+impl Config {
+    /// The configuration file used when none is given.
+    pub fn path() -> Option<PathBuf> {
+        let home = std::env::var_os("HOME")?;
+        Some(Path::new(&home).join(".config/enarx/client.json"))
+    }
+
+    /// Loads the configuration file, which need not exist.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::path() {
+                Some(path) => path,
+                None => return Ok(Self::default()),
+            },
+        };
+
+        match std::fs::File::open(&path) {
+            Ok(file) => serde_json::from_reader(file).map_err(Error::Config),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    /// Selects the named profile, or the default one.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, Error> {
+        let name = match name.or(self.default.as_deref()) {
+            Some(name) => name,
+            None => return Ok(Profile::default()),
+        };
+
+        let saved = match self.profiles.get(name) {
+            Some(saved) => saved,
+            None => return Err(Error::UnknownProfile(name.into())),
+        };
+
+        let url = match saved.url {
+            Some(ref url) => Some(url.parse()?),
+            None => None,
+        };
+
+        Ok(Profile {
+            url,
+            token: saved.token.clone(),
+            region: saved.region.clone(),
+            client: reqwest::Client::new(),
+            dry_run: false,
+            raw_status: false,
+            quiet: false,
+            metrics: None,
+            unix_socket: None,
+        })
+    }
+}
+
+/// The server target selected for a command.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    url: Option<Url>,
+    token: Option<String>,
+    region: Option<String>,
+    client: reqwest::Client,
+    dry_run: bool,
+    raw_status: bool,
+    quiet: bool,
+    metrics: Option<Arc<Metrics>>,
+    unix_socket: Option<PathBuf>,
+}
+
+impl Profile {
+    /// Prints mutating requests instead of sending them.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Prints the status line and headers of the first response, in place of
+    /// the command's own output.
+    pub fn raw_status(mut self, raw_status: bool) -> Self {
+        self.raw_status = raw_status;
+        self
+    }
+
+    /// Prints only the essential values, such as UUIDs, for use in scripts.
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Whether informational output should be left out.
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Records the timing of every request sent.
+    pub fn metrics(mut self, metrics: Option<Arc<Metrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Sends requests to a server listening on this Unix socket.
+    ///
+    /// Only the path and query of request URLs are used, and commands need
+    /// no server URL.
+    pub fn unix_socket(mut self, path: Option<PathBuf>) -> Self {
+        if path.is_some() {
+            self.unix_socket = path;
+        }
+
+        self
+    }
+
+    /// Uses the server for commands which do not name one.
+    pub fn default_url(mut self, url: Option<Url>) -> Self {
+        if url.is_some() {
+            self.url = url;
+        }
+
+        self
+    }
+
+    /// Prefers contracts of the region, overriding the saved one.
+    pub fn default_region(mut self, region: Option<String>) -> Self {
+        if region.is_some() {
+            self.region = region;
+        }
+
+        self
+    }
+
+    /// The region whose contracts are preferred, if any.
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    /// Resolves the server base URL, preferring one given on the command line.
+    pub fn url(&self, explicit: Option<Url>) -> Result<Url, Error> {
+        if let Some(url) = explicit.or_else(|| self.url.clone()) {
+            return Ok(url);
+        }
+
+        match self.unix_socket {
+            Some(..) => Ok(Url::parse("http://localhost/")?),
+            None => Err(Error::MissingUrl),
+        }
+    }
+
+    /// Starts a request which carries the profile's credentials.
+    pub fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match self.token {
+            Some(ref token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Sends a request, returning once the response headers arrive.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let request = request.build()?;
+        let url = request.url().clone();
+
+        let start = Instant::now();
+        let response = match self.unix_socket {
+            Some(ref path) => unix::send(path, request).await?,
+            None => self.client.execute(request).await?,
+        };
+        if let Some(ref metrics) = self.metrics {
+            let url = match self.unix_socket {
+                Some(..) => None,
+                None => Some(url),
+            };
+            metrics.record(url, start.elapsed());
+        }
+
+        if self.raw_status {
+            println!("{:?} {}", response.version(), response.status());
+            for (name, value) in response.headers() {
+                println!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+            }
+
+            return Err(Error::RawStatus);
+        }
+
+        Ok(response)
+    }
+
+    /// Sends a request which changes server state.
+    ///
+    /// During a dry run the request is printed instead and nothing is
+    /// returned.
+    pub async fn mutate(&self, request: RequestBuilder) -> Result<Option<Response>, Error> {
+        if !self.dry_run {
+            return Ok(Some(self.send(request).await?));
+        }
+
+        let request = request.build()?;
+        println!("{} {}", request.method(), request.url());
+        if let Some(body) = request.body().and_then(|b| b.as_bytes()) {
+            println!("{}", String::from_utf8_lossy(body));
+        }
+
+        Ok(None)
+    }
+}
+
+#[derive(StructOpt)]
+pub struct ListProfiles {}
+
+#[async_trait::async_trait]
+impl Command for ListProfiles {
+    async fn run(self, config: &Config, profile: &Profile) -> Result<(), Error> {
+        for (name, saved) in &config.profiles {
+            if profile.is_quiet() {
+                println!("{}", name);
+                continue;
+            }
+
+            let mark = if config.default.as_ref() == Some(name) {
+                "*"
+            } else {
+                " "
+            };
+
+            let url = saved.url.as_deref().unwrap_or("-");
+            println!("{} {} {}", mark, name, url);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+pub enum Configure {
+    ListProfiles(ListProfiles),
+}
+
+#[async_trait::async_trait]
+impl Command for Configure {
+    async fn run(self, config: &Config, profile: &Profile) -> Result<(), Error> {
+        match self {
+            Self::ListProfiles(cmd) => cmd.run(config, profile).await,
+        }
+    }
+}
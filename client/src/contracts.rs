@@ -1,10 +1,16 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{Command, Error};
+use super::{Command, Config, Error, Profile};
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use ciborium::de::from_reader;
-use koine::Contract;
-use reqwest::header::CONTENT_TYPE;
+use koine::{Backend, Contract};
+use reqwest::header::{HeaderValue, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use reqwest::{Method, StatusCode};
 use structopt::StructOpt;
 use uuid::Uuid;
 
@@ -12,20 +18,109 @@ use uuid::Uuid;
 pub struct List {
     /// The server base URL
     #[structopt(short, long, env = "ENARX_SERVER")]
-    url: reqwest::Url,
+    url: Option<reqwest::Url>,
+
+    /// Mark each contract with a symbol for its backend
+    #[structopt(long)]
+    hints: bool,
+
+    /// Only use ASCII characters for the hints
+    #[structopt(long, requires = "hints")]
+    ascii: bool,
+
+    /// Order the contracts by a field (uuid, backend or cost)
+    #[structopt(long, possible_values = &["uuid", "backend", "cost"])]
+    sort: Option<String>,
+
+    /// Print the contracts under a heading for each backend
+    #[structopt(long, possible_values = &["backend"])]
+    group_by: Option<String>,
+}
+
+impl List {
+    /// Prints one contract on a line, after `indent`.
+    fn print(&self, contract: &Contract, indent: &str, profile: &Profile) {
+        if profile.is_quiet() {
+            println!("{}", contract.uuid);
+            return;
+        }
+
+        let hint = match (self.hints, self.ascii) {
+            (false, _) => String::new(),
+            (true, false) => format!("{} ", contract.backend.display_hint()),
+            (true, true) => format!("{} ", contract.backend.ascii_hint()),
+        };
+
+        match contract.cost {
+            Some(cost) => println!(
+                "{}{}{} ({}, cost {})",
+                indent,
+                hint,
+                contract.uuid,
+                contract.backend.as_str(),
+                cost
+            ),
+            None => println!(
+                "{}{}{} ({})",
+                indent,
+                hint,
+                contract.uuid,
+                contract.backend.as_str()
+            ),
+        }
+    }
 }
 
 #[async_trait::async_trait]
 impl Command for List {
-    async fn run(self) -> Result<(), Error> {
-        let url = self.url.join("contracts")?;
-        let response = reqwest::get(url).await?;
+    async fn run(self, _: &Config, profile: &Profile) -> Result<(), Error> {
+        let mut url = profile.url(self.url.clone())?.join("contracts")?;
+        if let Some(region) = profile.region() {
+            url.query_pairs_mut().append_pair("region", region);
+        }
+
+        let response = profile.send(profile.request(Method::GET, url)).await?;
         let response = response.error_for_status()?;
         let response = Error::check_header(response, CONTENT_TYPE, "application/cbor")?;
 
-        let contracts: Vec<Contract> = response.decode(|bytes| from_reader(bytes)).await?;
-        for contract in contracts {
-            println!("{} ({})", contract.uuid, contract.backend.as_str());
+        let mut contracts: Vec<Contract> = response.decode(|bytes| from_reader(bytes)).await?;
+        match self.sort.as_deref() {
+            Some("uuid") => contracts.sort_by_key(|c| c.uuid),
+            Some("backend") => contracts.sort_by(|a, b| a.backend.as_str().cmp(b.backend.as_str())),
+            Some("cost") => contracts.sort_by_key(|c| c.cost_order()),
+            _ => (),
+        }
+
+        if self.group_by.is_none() {
+            for contract in &contracts {
+                self.print(contract, "", profile);
+            }
+
+            return Ok(());
+        }
+
+        // Groups are in backend order and, unless asked otherwise, their
+        // contracts in UUID order.
+        if self.sort.is_none() {
+            contracts.sort_by_key(|c| c.uuid);
+        }
+
+        let mut groups: BTreeMap<&str, Vec<&Contract>> = BTreeMap::new();
+        for contract in &contracts {
+            groups
+                .entry(contract.backend.as_str())
+                .or_default()
+                .push(contract);
+        }
+
+        for (backend, group) in groups {
+            if !profile.is_quiet() {
+                println!("{}:", backend);
+            }
+
+            for contract in group {
+                self.print(contract, "  ", profile);
+            }
         }
 
         Ok(())
@@ -36,7 +131,7 @@ impl Command for List {
 pub struct Show {
     /// The server base URL
     #[structopt(short, long, env = "ENARX_SERVER")]
-    url: reqwest::Url,
+    url: Option<reqwest::Url>,
 
     /// The contract UUID
     uuid: Uuid,
@@ -44,10 +139,10 @@ pub struct Show {
 
 #[async_trait::async_trait]
 impl Command for Show {
-    async fn run(self) -> Result<(), Error> {
+    async fn run(self, _: &Config, profile: &Profile) -> Result<(), Error> {
         let uuid = self.uuid.to_hyphenated().to_string();
-        let url = self.url.join("contracts/")?.join(&uuid)?;
-        let response = reqwest::get(url).await?;
+        let url = profile.url(self.url)?.join("contracts/")?.join(&uuid)?;
+        let response = profile.send(profile.request(Method::GET, url)).await?;
         let response = response.error_for_status()?;
         let response = Error::check_header(response, CONTENT_TYPE, "application/cbor")?;
 
@@ -57,18 +152,216 @@ impl Command for Show {
     }
 }
 
+pub fn parse_backend(name: &str) -> Result<Backend, String> {
+    name.parse()
+        .map_err(|_| format!("unknown backend: {}", name))
+}
+
+#[derive(StructOpt)]
+pub struct NewUuid {
+    /// The number of UUIDs to generate
+    #[structopt(short = "n", long, default_value = "1")]
+    count: usize,
+
+    /// Print contracts for this backend, ready for a contracts file
+    #[structopt(long, parse(try_from_str = parse_backend))]
+    backend: Option<Backend>,
+
+    /// Print the contracts as CBOR rather than JSON
+    #[structopt(long, requires = "backend")]
+    cbor: bool,
+}
+
+#[async_trait::async_trait]
+impl Command for NewUuid {
+    async fn run(self, _: &Config, _: &Profile) -> Result<(), Error> {
+        let uuids = (0..self.count).map(|_| Uuid::new_v4());
+
+        let backend = match self.backend {
+            Some(backend) => backend,
+            None => {
+                uuids.for_each(|uuid| println!("{}", uuid));
+                return Ok(());
+            }
+        };
+
+        let contracts: Vec<_> = uuids
+            .map(|uuid| Contract::new(uuid, backend.clone()))
+            .collect();
+
+        let mut stdout = std::io::stdout();
+        if self.cbor {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(&contracts, &mut bytes).unwrap();
+            stdout.write_all(&bytes).map_err(Error::Io)?;
+        } else {
+            let json = serde_json::to_string_pretty(&contracts).unwrap();
+            writeln!(stdout, "{}", json).map_err(Error::Io)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+pub struct Validate {
+    /// The contracts file, as CBOR if it ends in `.cbor`, JSON5 if it ends in
+    /// `.json5` and JSON otherwise
+    file: PathBuf,
+
+    /// Parse a JSON contracts file as JSON5, as contractmgr does with --relaxed
+    #[structopt(long)]
+    relaxed: bool,
+}
+
+#[async_trait::async_trait]
+impl Command for Validate {
+    async fn run(self, _: &Config, profile: &Profile) -> Result<(), Error> {
+        let file = std::fs::File::open(&self.file).map_err(Error::Io)?;
+        let json5 = || -> Result<Vec<Contract>, String> {
+            let text = std::fs::read_to_string(&self.file).map_err(|e| e.to_string())?;
+            json5::from_str(&text).map_err(|e| e.to_string())
+        };
+
+        let parsed: Result<Vec<Contract>, String> = match self.file.extension() {
+            Some(ext) if ext == "cbor" => from_reader(file).map_err(|e| e.to_string()),
+            Some(ext) if ext == "json5" => json5(),
+            _ if self.relaxed => json5(),
+            _ => serde_json::from_reader(file).map_err(|e| e.to_string()),
+        };
+
+        let contracts = match parsed {
+            Ok(contracts) => contracts,
+            Err(e) => {
+                eprintln!("{}: {}", self.file.display(), e);
+                return Err(Error::InvalidContracts(1));
+            }
+        };
+
+        let mut problems = 0;
+        let mut seen = HashMap::new();
+        for (index, contract) in contracts.iter().enumerate() {
+            let mut reasons = Vec::new();
+
+            if let Some(first) = seen.insert(contract.uuid, index) {
+                seen.insert(contract.uuid, first);
+                reasons.push(format!("duplicates contract {}", first));
+            }
+
+            if let Err(invalid) = contract.validate() {
+                reasons.extend(invalid.iter().map(|i| i.to_string()));
+            }
+
+            for reason in &reasons {
+                eprintln!("contract {} ({}): {}", index, contract.uuid, reason);
+            }
+
+            problems += reasons.len();
+        }
+
+        if problems > 0 {
+            return Err(Error::InvalidContracts(problems));
+        }
+
+        if !profile.is_quiet() {
+            println!("{}: {} contracts ok", self.file.display(), contracts.len());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+pub struct Watch {
+    /// The server base URL
+    #[structopt(short, long, env = "ENARX_SERVER")]
+    url: Option<reqwest::Url>,
+
+    /// The number of milliseconds between polls
+    #[structopt(long, default_value = "5000")]
+    interval: u64,
+}
+
+impl Watch {
+    /// Prints how the contracts changed, as `+` for those added, `-` for
+    /// those removed and `~` for those whose terms changed.
+    fn diff(old: &[Contract], new: &[Contract]) {
+        let before: HashMap<Uuid, &Contract> = old.iter().map(|c| (c.uuid, c)).collect();
+        let after: HashMap<Uuid, &Contract> = new.iter().map(|c| (c.uuid, c)).collect();
+
+        for contract in old.iter().filter(|c| !after.contains_key(&c.uuid)) {
+            println!("- {} ({})", contract.uuid, contract.backend.as_str());
+        }
+
+        for contract in new {
+            match before.get(&contract.uuid) {
+                None => println!("+ {} ({})", contract.uuid, contract.backend.as_str()),
+                Some(old) if *old != contract => {
+                    println!("~ {} ({})", contract.uuid, contract.backend.as_str())
+                }
+                Some(..) => (),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Watch {
+    async fn run(self, _: &Config, profile: &Profile) -> Result<(), Error> {
+        let url = profile.url(self.url)?.join("contracts")?;
+        let mut etag: Option<HeaderValue> = None;
+        let mut contracts: Vec<Contract> = Vec::new();
+
+        loop {
+            let mut request = profile.request(Method::GET, url.clone());
+            if let Some(ref etag) = etag {
+                request = request.header(IF_NONE_MATCH, etag.clone());
+            }
+
+            // The server may well restart during a rollout, so keep trying.
+            let response = match profile.send(request).await {
+                Err(Error::Reqwest(e)) if e.is_connect() || e.is_timeout() => {
+                    eprintln!("error: {}", e);
+                    None
+                }
+                response => Some(response?),
+            };
+
+            if let Some(response) = response {
+                if response.status() != StatusCode::NOT_MODIFIED {
+                    let response = response.error_for_status()?;
+                    let response = Error::check_header(response, CONTENT_TYPE, "application/cbor")?;
+                    etag = response.headers().get(ETAG).cloned();
+
+                    let listed: Vec<Contract> = response.decode(|bytes| from_reader(bytes)).await?;
+                    Self::diff(&contracts, &listed);
+                    contracts = listed;
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(self.interval)).await;
+        }
+    }
+}
+
 #[derive(StructOpt)]
 pub enum Contracts {
     List(List),
     Show(Show),
+    NewUuid(NewUuid),
+    Validate(Validate),
+    Watch(Watch),
 }
 
 #[async_trait::async_trait]
 impl Command for Contracts {
-    async fn run(self) -> Result<(), Error> {
+    async fn run(self, config: &Config, profile: &Profile) -> Result<(), Error> {
         match self {
-            Self::List(cmd) => cmd.run().await,
-            Self::Show(cmd) => cmd.run().await,
+            Self::List(cmd) => cmd.run(config, profile).await,
+            Self::Show(cmd) => cmd.run(config, profile).await,
+            Self::NewUuid(cmd) => cmd.run(config, profile).await,
+            Self::Validate(cmd) => cmd.run(config, profile).await,
+            Self::Watch(cmd) => cmd.run(config, profile).await,
         }
     }
 }
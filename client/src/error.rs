@@ -5,7 +5,23 @@ use reqwest::Response;
 pub enum Error {
     Reqwest(reqwest::Error),
     Url(url::ParseError),
+    Io(std::io::Error),
+
+    /// The server's Unix socket couldn't be reached or answered badly.
+    Socket(std::io::Error),
+    Config(serde_json::Error),
+    UnknownProfile(String),
+    MissingUrl,
     InvalidHeaderValue,
+
+    /// No contract of the requested backend is offered.
+    NoContract,
+
+    /// A contracts file had this many problems, which were reported.
+    InvalidContracts(usize),
+
+    /// The response was printed raw, so the command stopped short.
+    RawStatus,
 }
 
 impl From<reqwest::Error> for Error {
@@ -21,6 +37,27 @@ impl From<url::ParseError> for Error {
 }
 
 impl Error {
+    /// The process exit code for the error, so scripts can tell failures apart.
+    ///
+    /// | Code | Meaning                                                          |
+    /// |------|------------------------------------------------------------------|
+    /// | 1    | Anything else, such as a local I/O failure                       |
+    /// | 2    | Bad usage: an invalid or missing URL, profile, config or file    |
+    /// | 3    | Network: the server couldn't be reached or timed out             |
+    /// | 4    | Protocol: an error status or an unexpected response body         |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Url(..) | Error::Config(..) | Error::UnknownProfile(..) | Error::MissingUrl => 2,
+            Error::InvalidContracts(..) => 2,
+            Error::Reqwest(e) if e.is_builder() => 2,
+            Error::Reqwest(e) if e.is_status() || e.is_decode() => 4,
+            Error::Reqwest(..) | Error::Socket(..) => 3,
+            Error::InvalidHeaderValue | Error::NoContract => 4,
+            Error::Io(..) => 1,
+            Error::RawStatus => 0,
+        }
+    }
+
     pub fn check_header(
         response: Response,
         key: impl AsHeaderName,
@@ -0,0 +1,417 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::contracts::parse_backend;
+use super::{Command, Config, Error, Profile};
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ciborium::de::from_reader;
+use franca::Keep;
+use koine::{Backend, Contract};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Method, StatusCode, Url};
+use structopt::StructOpt;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+#[derive(StructOpt)]
+pub struct Create {
+    /// The server base URL
+    #[structopt(short, long, env = "ENARX_SERVER")]
+    url: Option<reqwest::Url>,
+
+    /// The UUID of the contract to claim
+    #[structopt(required_unless = "backend")]
+    contract: Option<Uuid>,
+
+    /// Claim a contract of this backend, preferring one in the region
+    #[structopt(long, conflicts_with = "contract", parse(try_from_str = parse_backend))]
+    backend: Option<Backend>,
+
+    /// Retry while the server has no room for another keep
+    #[structopt(long)]
+    retry_on_conflict: bool,
+
+    /// The maximum number of attempts when retrying
+    #[structopt(long, default_value = "10")]
+    max_attempts: u32,
+
+    /// The number of milliseconds to wait between attempts
+    #[structopt(long, default_value = "1000")]
+    retry_interval: u64,
+}
+
+impl Create {
+    /// Picks a contract of the backend, preferring one in the profile's
+    /// region and otherwise the first offered.
+    async fn choose(base: &Url, backend: &Backend, profile: &Profile) -> Result<Uuid, Error> {
+        let url = base.join("contracts")?;
+        let response = profile.send(profile.request(Method::GET, url)).await?;
+        let response = response.error_for_status()?;
+        let response = Error::check_header(response, CONTENT_TYPE, "application/cbor")?;
+
+        let contracts: Vec<Contract> = response.decode(|bytes| from_reader(bytes)).await?;
+        let mut offered = contracts.iter().filter(|c| c.backend == *backend);
+        let regional = match profile.region() {
+            Some(region) => offered
+                .clone()
+                .find(|c| c.region.as_deref() == Some(region)),
+            None => None,
+        };
+
+        match regional.or_else(|| offered.next()) {
+            Some(contract) => Ok(contract.uuid),
+            None => Err(Error::NoContract),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Create {
+    async fn run(self, _: &Config, profile: &Profile) -> Result<(), Error> {
+        let base = profile.url(self.url)?;
+        let contract = match (self.contract, self.backend) {
+            (Some(contract), _) => contract,
+            (None, Some(backend)) => Self::choose(&base, &backend, profile).await?,
+            (None, None) => unreachable!(),
+        };
+
+        let uuid = contract.to_hyphenated().to_string();
+        let url = base.join("contracts/")?.join(&uuid)?;
+
+        let mut attempts = 1;
+        let response = loop {
+            let request = profile.request(Method::POST, url.clone());
+            let response = match profile.mutate(request).await? {
+                Some(response) => response,
+                None => return Ok(()),
+            };
+
+            // The server is at capacity, so wait for a keep to go away.
+            let full = matches!(
+                response.status(),
+                StatusCode::CONFLICT | StatusCode::TOO_MANY_REQUESTS
+            );
+
+            if !full || !self.retry_on_conflict || attempts >= self.max_attempts {
+                break response;
+            }
+
+            tokio::time::sleep(Duration::from_millis(self.retry_interval)).await;
+            attempts += 1;
+        };
+
+        let response = response.error_for_status()?;
+        let response = Error::check_header(response, CONTENT_TYPE, "application/cbor")?;
+
+        let keep: Keep = response.decode(|bytes| from_reader(bytes)).await?;
+        match profile.is_quiet() {
+            true => println!("{}", keep.uuid),
+            false => println!("{:#?}", keep),
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+pub struct Delete {
+    /// The server base URL
+    #[structopt(short, long, env = "ENARX_SERVER")]
+    url: Option<reqwest::Url>,
+
+    /// The keep UUID
+    #[structopt(required_unless = "from-file")]
+    uuid: Option<Uuid>,
+
+    /// Delete every keep listed in a file, one UUID per line
+    #[structopt(long, conflicts_with = "uuid")]
+    from_file: Option<PathBuf>,
+
+    /// The maximum number of deletions in flight at once
+    #[structopt(long, default_value = "4")]
+    concurrency: usize,
+}
+
+/// What became of one line of a bulk deletion.
+enum Outcome {
+    Invalid(usize, String),
+    Sent(Uuid, JoinHandle<Result<Option<StatusCode>, Error>>),
+}
+
+impl Delete {
+    async fn bulk(self, path: PathBuf, base: Url, profile: &Profile) -> Result<(), Error> {
+        let lines = std::fs::read_to_string(path).map_err(Error::Io)?;
+        let permits = Arc::new(Semaphore::new(self.concurrency.max(1)));
+
+        let mut outcomes = Vec::new();
+        for (index, line) in lines.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let uuid: Uuid = match line.parse() {
+                Ok(uuid) => uuid,
+                Err(..) => {
+                    outcomes.push(Outcome::Invalid(index + 1, line.into()));
+                    continue;
+                }
+            };
+
+            let url = base.join(&uuid.to_hyphenated().to_string())?;
+            let request = profile.request(Method::DELETE, url);
+            let profile = profile.clone();
+            let permits = permits.clone();
+            let task = tokio::spawn(async move {
+                let _permit = permits.acquire().await.unwrap();
+                let response = profile.mutate(request).await?;
+                Ok(response.map(|r| r.status()))
+            });
+
+            outcomes.push(Outcome::Sent(uuid, task));
+        }
+
+        // When quiet, only the failures are reported, and on stderr.
+        let report = |line: String, failure: bool| match (profile.is_quiet(), failure) {
+            (false, _) => println!("{}", line),
+            (true, true) => eprintln!("{}", line),
+            (true, false) => (),
+        };
+
+        let (mut deleted, mut failed, mut invalid) = (0, 0, 0);
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Invalid(number, line) => {
+                    report(format!("line {}: invalid UUID: {}", number, line), true);
+                    invalid += 1;
+                }
+
+                Outcome::Sent(uuid, task) => match task.await.unwrap() {
+                    Ok(None) => (),
+                    Ok(Some(status)) if status.is_success() => {
+                        report(format!("{}: deleted", uuid), false);
+                        deleted += 1;
+                    }
+                    Ok(Some(status)) => {
+                        report(format!("{}: failed: {}", uuid, status), true);
+                        failed += 1;
+                    }
+                    Err(e) => {
+                        report(format!("{}: failed: {:?}", uuid, e), true);
+                        failed += 1;
+                    }
+                },
+            }
+        }
+
+        if profile.is_quiet() {
+            return Ok(());
+        }
+
+        println!(
+            "{} deleted, {} failed, {} invalid",
+            deleted, failed, invalid
+        );
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Delete {
+    async fn run(mut self, _: &Config, profile: &Profile) -> Result<(), Error> {
+        let base = profile.url(self.url.take())?.join("keeps/")?;
+
+        let uuid = match (self.uuid, self.from_file.take()) {
+            (_, Some(path)) => return self.bulk(path, base, profile).await,
+            (Some(uuid), None) => uuid.to_hyphenated().to_string(),
+            (None, None) => unreachable!(),
+        };
+
+        let request = profile.request(Method::DELETE, base.join(&uuid)?);
+        if let Some(response) = profile.mutate(request).await? {
+            response.error_for_status()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses an age such as `90s`, `30m`, `12h` or `7d`; bare numbers are seconds.
+fn parse_age(age: &str) -> Result<Duration, String> {
+    let (number, unit) = match age.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => age.split_at(index),
+        None => (age, "s"),
+    };
+
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit in age: {}", age)),
+    };
+
+    match number.parse::<u64>() {
+        Ok(number) => Ok(Duration::from_secs(number * scale)),
+        Err(..) => Err(format!("invalid age: {}", age)),
+    }
+}
+
+#[derive(StructOpt)]
+pub struct Prune {
+    /// The server base URL
+    #[structopt(short, long, env = "ENARX_SERVER")]
+    url: Option<reqwest::Url>,
+
+    /// Delete keeps created longer ago than this (e.g. 90s, 30m, 12h or 7d)
+    #[structopt(long, parse(try_from_str = parse_age))]
+    older_than: Duration,
+
+    /// Delete the keeps without asking first
+    #[structopt(short, long)]
+    yes: bool,
+}
+
+impl Prune {
+    /// Asks on stderr whether to go ahead, defaulting to no.
+    async fn confirm(count: usize) -> Result<bool, Error> {
+        eprint!("Delete {} keeps? [y/N] ", count);
+        std::io::stderr().flush().map_err(Error::Io)?;
+
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        let answer = lines.next_line().await.map_err(Error::Io)?;
+        Ok(matches!(
+            answer.as_deref().map(str::trim),
+            Some("y") | Some("yes")
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Prune {
+    async fn run(self, _: &Config, profile: &Profile) -> Result<(), Error> {
+        let root = profile.url(self.url)?;
+        let base = root.join("keeps/")?;
+        let response = profile
+            .send(profile.request(Method::GET, root.join("keeps")?))
+            .await?;
+        let response = response.error_for_status()?;
+        let response = Error::check_header(response, CONTENT_TYPE, "application/cbor")?;
+        let keeps: Vec<Keep> = response.decode(|bytes| from_reader(bytes)).await?;
+
+        // Keeps of unknown age are never pruned.
+        let now = SystemTime::now();
+        let cutoff = now.checked_sub(self.older_than).unwrap_or(UNIX_EPOCH);
+        let cutoff = cutoff
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let old: Vec<&Keep> = keeps
+            .iter()
+            .filter(|k| matches!(k.created, Some(created) if created.secs() < cutoff))
+            .collect();
+
+        if old.is_empty() {
+            if !profile.is_quiet() {
+                println!("0 deleted, 0 failed, {} kept", keeps.len());
+            }
+            return Ok(());
+        }
+
+        if !self.yes {
+            for keep in &old {
+                eprintln!("{} ({})", keep.uuid, keep.contract.backend.as_str());
+            }
+
+            if !Self::confirm(old.len()).await? {
+                eprintln!("nothing deleted");
+                return Ok(());
+            }
+        }
+
+        let (mut deleted, mut failed) = (0, 0);
+        for keep in &old {
+            let url = base.join(&keep.uuid.to_hyphenated().to_string())?;
+            let status = match profile.mutate(profile.request(Method::DELETE, url)).await {
+                Ok(None) => continue,
+                Ok(Some(response)) => response.status(),
+                Err(e) => {
+                    eprintln!("{}: failed: {:?}", keep.uuid, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            if status.is_success() {
+                if !profile.is_quiet() {
+                    println!("{}: deleted", keep.uuid);
+                }
+                deleted += 1;
+            } else {
+                eprintln!("{}: failed: {}", keep.uuid, status);
+                failed += 1;
+            }
+        }
+
+        if !profile.is_quiet() {
+            println!(
+                "{} deleted, {} failed, {} kept",
+                deleted,
+                failed,
+                keeps.len() - old.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+pub struct Show {
+    /// The server base URL
+    #[structopt(short, long, env = "ENARX_SERVER")]
+    url: Option<reqwest::Url>,
+
+    /// The keep UUID
+    uuid: Uuid,
+}
+
+#[async_trait::async_trait]
+impl Command for Show {
+    async fn run(self, _: &Config, profile: &Profile) -> Result<(), Error> {
+        let uuid = self.uuid.to_hyphenated().to_string();
+        let url = profile.url(self.url)?.join("keeps/")?.join(&uuid)?;
+        let response = profile.send(profile.request(Method::GET, url)).await?;
+        let response = response.error_for_status()?;
+        let response = Error::check_header(response, CONTENT_TYPE, "application/cbor")?;
+
+        let keep: Keep = response.decode(|bytes| from_reader(bytes)).await?;
+        println!("{:#?}", keep);
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+pub enum Keeps {
+    Create(Create),
+    Delete(Delete),
+    Prune(Prune),
+    Show(Show),
+}
+
+#[async_trait::async_trait]
+impl Command for Keeps {
+    async fn run(self, config: &Config, profile: &Profile) -> Result<(), Error> {
+        match self {
+            Self::Create(cmd) => cmd.run(config, profile).await,
+            Self::Delete(cmd) => cmd.run(config, profile).await,
+            Self::Prune(cmd) => cmd.run(config, profile).await,
+            Self::Show(cmd) => cmd.run(config, profile).await,
+        }
+    }
+}
@@ -3,26 +3,122 @@
 #![deny(clippy::all)]
 #![allow(clippy::redundant_closure)]
 
+mod config;
 mod contracts;
 mod error;
+mod keeps;
+mod metrics;
+mod repl;
+mod unix;
 
+use config::{Config, Profile};
 use error::Error;
+use metrics::Metrics;
+
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use structopt::StructOpt;
 
 #[async_trait::async_trait]
 trait Command: StructOpt {
-    async fn run(self) -> Result<(), Error>;
+    async fn run(self, config: &Config, profile: &Profile) -> Result<(), Error>;
 }
 
 #[derive(StructOpt)]
 pub enum Commands {
+    Config(config::Configure),
     Contracts(contracts::Contracts),
+    Keeps(keeps::Keeps),
+    Repl(repl::Repl),
+}
+
+#[async_trait::async_trait]
+impl Command for Commands {
+    async fn run(self, config: &Config, profile: &Profile) -> Result<(), Error> {
+        match self {
+            Self::Config(cmd) => cmd.run(config, profile).await,
+            Self::Contracts(cmd) => cmd.run(config, profile).await,
+            Self::Keeps(cmd) => cmd.run(config, profile).await,
+            Self::Repl(cmd) => cmd.run(config, profile).await,
+        }
+    }
 }
 
+#[derive(StructOpt)]
+struct Options {
+    /// The configuration file (default: ~/.config/enarx/client.json)
+    #[structopt(long, global = true, env = "ENARX_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// The saved server target to use
+    #[structopt(long, global = true, env = "ENARX_PROFILE")]
+    profile: Option<String>,
+
+    /// Print the requests that would change anything instead of sending them
+    #[structopt(long, global = true)]
+    dry_run: bool,
+
+    /// Print the response status line and headers instead of the output
+    #[structopt(long, global = true)]
+    raw_status: bool,
+
+    /// Print only the essential values, such as UUIDs, one per line
+    #[structopt(short, long, global = true)]
+    quiet: bool,
+
+    /// Prefer contracts of this region, listing only those
+    #[structopt(long, global = true, env = "ENARX_REGION")]
+    region: Option<String>,
+
+    /// Print request timings to stderr when the command completes
+    #[structopt(long, global = true)]
+    metrics: bool,
+
+    /// Reach the server through this Unix socket rather than over TCP
+    #[structopt(long, global = true, env = "ENARX_UNIX_SOCKET")]
+    unix_socket: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Commands,
+}
+
+async fn run(options: Options, metrics: Option<Arc<Metrics>>) -> Result<(), Error> {
+    let config = Config::load(options.config.as_deref())?;
+    let profile = config.profile(options.profile.as_deref())?;
+    let profile = profile
+        .dry_run(options.dry_run)
+        .raw_status(options.raw_status)
+        .quiet(options.quiet)
+        .default_region(options.region)
+        .unix_socket(options.unix_socket)
+        .metrics(metrics);
+
+    options.command.run(&config, &profile).await
+}
+
+/// Runs the command, exiting with the code of any error.
+///
+/// See `Error::exit_code()` for the meaning of each code.
 #[tokio::main]
-async fn main() -> Result<(), Error> {
-    match Commands::from_args() {
-        Commands::Contracts(cmd) => cmd.run().await,
+async fn main() {
+    let options = Options::from_args();
+    let metrics = match options.metrics {
+        true => Some(Arc::new(Metrics::default())),
+        false => None,
+    };
+
+    let result = run(options, metrics.clone()).await;
+
+    if let Some(metrics) = metrics {
+        metrics.report().await;
+    }
+
+    match result {
+        Ok(()) | Err(Error::RawStatus) => (),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(e.exit_code());
+        }
     }
 }
@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+use tokio::net::{lookup_host, TcpStream};
+
+/// Timings of the requests a command makes.
+///
+/// reqwest does not report how long name resolution or connecting took, so
+/// those can't be given for the command's own requests. Instead, a separate
+/// connection to the server is timed once the command is done, and reported
+/// apart as a probe.
+#[derive(Debug)]
+pub struct Metrics {
+    start: Instant,
+    requests: Mutex<Vec<(Option<Url>, Duration)>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            requests: Mutex::default(),
+        }
+    }
+}
+
+/// Formats an optional duration for the report.
+fn show(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!("{:.3?}", duration),
+        None => "n/a".into(),
+    }
+}
+
+impl Metrics {
+    /// Records how long a request took to get its response headers.
+    ///
+    /// The `url` is that of a request made over TCP, which can be probed
+    /// afterwards; a request over a Unix socket has none.
+    pub fn record(&self, url: Option<Url>, first_byte: Duration) {
+        self.requests.lock().unwrap().push((url, first_byte));
+    }
+
+    /// Times resolving and connecting to the server.
+    async fn probe(url: &Url) -> (Option<Duration>, Option<Duration>) {
+        let (host, port) = match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => (host.trim_matches(|c| c == '[' || c == ']'), port),
+            _ => return (None, None),
+        };
+
+        let start = Instant::now();
+        let addrs = lookup_host((host, port)).await;
+        let dns = start.elapsed();
+
+        let addr = match addrs.ok().and_then(|mut a| a.next()) {
+            Some(addr) => addr,
+            None => return (None, None),
+        };
+
+        let start = Instant::now();
+        let connect = TcpStream::connect(addr).await.ok().map(|_| start.elapsed());
+        (Some(dns), connect)
+    }
+
+    /// Prints the report to stderr.
+    pub async fn report(&self) {
+        let total = self.start.elapsed();
+        let requests = self.requests.lock().unwrap().clone();
+
+        let first_byte = match requests.is_empty() {
+            true => show(None),
+            false => requests
+                .iter()
+                .map(|r| show(Some(r.1)))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+
+        eprintln!("metrics:");
+        eprintln!("  requests:   {}", requests.len());
+        eprintln!("  first-byte: {}", first_byte);
+        eprintln!("  total:      {}", show(Some(total)));
+
+        if let Some((Some(url), _)) = requests.first() {
+            let (dns, connect) = Self::probe(url).await;
+            eprintln!("  probed afterwards, on a new connection:");
+            eprintln!("    dns:      {}", show(dns));
+            eprintln!("    connect:  {}", show(connect));
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{Command, Commands, Config, Error, Profile};
+
+use std::io::Write;
+
+use structopt::clap::ErrorKind;
+use structopt::StructOpt;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// Runs commands typed at a prompt against one server.
+///
+/// Besides the usual commands, `history` lists the lines entered so far,
+/// `!N` runs line N again and `exit` leaves.
+#[derive(StructOpt)]
+pub struct Repl {
+    /// The server base URL
+    #[structopt(short, long, env = "ENARX_SERVER")]
+    url: Option<reqwest::Url>,
+}
+
+impl Repl {
+    fn prompt() {
+        eprint!("> ");
+        std::io::stderr().flush().unwrap();
+    }
+
+    /// Parses and runs a single line.
+    async fn line(line: &str, config: &Config, profile: &Profile) {
+        let words = std::iter::once("client").chain(line.split_whitespace());
+        let command = match Commands::from_iter_safe(words) {
+            Ok(Commands::Repl(..)) => return eprintln!("already in a repl"),
+            Ok(command) => command,
+            Err(e) if e.kind == ErrorKind::HelpDisplayed => return println!("{}", e.message),
+            Err(e) => return eprintln!("{}", e.message),
+        };
+
+        match command.run(config, profile).await {
+            Ok(()) | Err(Error::RawStatus) => (),
+            Err(e) => eprintln!("error: {:?}", e),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Repl {
+    async fn run(self, config: &Config, profile: &Profile) -> Result<(), Error> {
+        let profile = profile.clone().default_url(self.url);
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        let mut history: Vec<String> = Vec::new();
+
+        Self::prompt();
+        while let Some(line) = lines.next_line().await.map_err(Error::Io)? {
+            let mut line = line.trim().to_string();
+
+            if let Some(number) = line.strip_prefix('!') {
+                let number = number.parse::<usize>().ok().and_then(|n| n.checked_sub(1));
+                match number.and_then(|n| history.get(n)) {
+                    Some(previous) => line = previous.clone(),
+                    None => eprintln!("{}: event not found", line),
+                }
+            }
+
+            match line.as_str() {
+                "" => (),
+                "exit" | "quit" => break,
+                "history" => {
+                    for (number, line) in history.iter().enumerate() {
+                        println!("{:5}  {}", number + 1, line);
+                    }
+                }
+                _ if line.starts_with('!') => (),
+                _ => {
+                    Self::line(&line, config, &profile).await;
+                    history.push(line);
+                }
+            }
+
+            Self::prompt();
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::Error;
+
+use std::path::Path;
+
+use reqwest::header::HOST;
+use reqwest::{Request, Response};
+use tokio::net::UnixStream;
+
+/// Turns a failure of the HTTP exchange into one of the socket.
+fn broken(e: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Error::Socket(std::io::Error::new(std::io::ErrorKind::Other, e))
+}
+
+/// Sends a request to a server listening on the Unix socket at `path`.
+///
+/// Only the path and query of the request's URL are used. The response is
+/// read in full before it is returned.
+pub async fn send(path: &Path, request: Request) -> Result<Response, Error> {
+    let stream = UnixStream::connect(path).await.map_err(Error::Socket)?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(broken)?;
+    tokio::spawn(connection);
+
+    let url = request.url();
+    let target = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+
+    let mut builder = hyper::Request::builder()
+        .method(request.method().clone())
+        .uri(target)
+        .header(HOST, "localhost");
+    for (name, value) in request.headers() {
+        builder = builder.header(name, value);
+    }
+
+    let body = request
+        .body()
+        .and_then(|b| b.as_bytes())
+        .unwrap_or_default();
+    let request = builder
+        .body(hyper::Body::from(body.to_vec()))
+        .map_err(broken)?;
+
+    let response = sender.send_request(request).await.map_err(broken)?;
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await.map_err(broken)?;
+    Ok(hyper::Response::from_parts(parts, body.to_vec()).into())
+}
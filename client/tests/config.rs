@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{serve, AppState, Contracts};
+use franca::KeepStore;
+
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio_stream::wrappers::TcpListenerStream;
+
+const BIN: &str = env!("CARGO_BIN_EXE_client");
+
+async fn spawn(state: AppState) -> String {
+    let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let listen = TcpListener::from_std(socket).unwrap();
+    tokio::spawn(serve(TcpListenerStream::new(listen), state));
+    format!("http://{}/", addr)
+}
+
+#[tokio::test]
+async fn profiles() {
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new());
+    let url = spawn(state.clone()).await;
+
+    // Nothing listens on the dev profile's port
+    let dead = {
+        let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/", socket.local_addr().unwrap())
+    };
+
+    let config = serde_json::json!({
+        "default": "dev",
+        "profiles": {
+            "dev": { "url": dead },
+            "prod": { "url": url },
+        }
+    });
+    let path = std::env::temp_dir().join(format!("client-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, config.to_string()).unwrap();
+
+    let run = |args: &[&str]| {
+        Command::new(BIN)
+            .env_remove("ENARX_SERVER")
+            .env_remove("ENARX_PROFILE")
+            .arg("--config")
+            .arg(&path)
+            .args(args)
+            .output()
+    };
+
+    let output = run(&["config", "list-profiles"]).await.unwrap();
+    assert!(output.status.success());
+    let listing = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(listing, format!("* dev {}\n  prod {}\n", dead, url));
+
+    // The default profile points nowhere
+    let output = run(&["contracts", "list"]).await.unwrap();
+    assert!(!output.status.success());
+
+    // The selected profile's URL is used
+    let output = run(&["--profile", "prod", "contracts", "list"])
+        .await
+        .unwrap();
+    assert!(output.status.success());
+    let listing = String::from_utf8(output.stdout).unwrap();
+    for contract in state.contracts.get().iter() {
+        assert!(listing.contains(&contract.uuid.to_string()));
+    }
+
+    let output = run(&["--profile", "qa", "contracts", "list"])
+        .await
+        .unwrap();
+    assert!(!output.status.success());
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,426 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{serve, AppState, Contracts};
+use franca::{Backend, Contract, KeepStore};
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader, Lines};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::process::Command;
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use uuid::Uuid;
+
+const BIN: &str = env!("CARGO_BIN_EXE_client");
+
+async fn spawn(state: AppState) -> String {
+    let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let listen = TcpListener::from_std(socket).unwrap();
+    tokio::spawn(serve(TcpListenerStream::new(listen), state));
+    format!("http://{}/", addr)
+}
+
+#[tokio::test]
+async fn list_sort_cost() {
+    let priced = |cost| Contract {
+        cost,
+        ..Contract::new(Uuid::new_v4(), Backend::Kvm)
+    };
+
+    let contracts = [priced(Some(5)), priced(None), priced(Some(1))];
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&contracts).unwrap()).unwrap();
+    let loaded = Contracts::load(Some(path.clone())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let url = spawn(AppState::new(loaded, KeepStore::new())).await;
+    let output = Command::new(BIN)
+        .arg("contracts")
+        .arg("list")
+        .arg("--url")
+        .arg(&url)
+        .arg("--sort")
+        .arg("cost")
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            format!("{} (kvm, cost 1)", contracts[2].uuid),
+            format!("{} (kvm, cost 5)", contracts[0].uuid),
+            format!("{} (kvm)", contracts[1].uuid),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn list_unix_socket() {
+    let path = std::env::temp_dir().join(format!("contractmgr-{}.sock", Uuid::new_v4()));
+    let listen = UnixListener::bind(&path).unwrap();
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new());
+    tokio::spawn(serve(UnixListenerStream::new(listen), state.clone()));
+
+    let output = Command::new(BIN)
+        .arg("--unix-socket")
+        .arg(&path)
+        .arg("contracts")
+        .arg("list")
+        .arg("--quiet")
+        .output()
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let listed: Vec<Uuid> = stdout.lines().map(|l| l.parse().unwrap()).collect();
+    let offered: Vec<Uuid> = state.contracts.get().iter().map(|c| c.uuid).collect();
+    assert_eq!(listed, offered);
+}
+
+#[tokio::test]
+async fn new_uuid() {
+    let output = Command::new(BIN)
+        .arg("contracts")
+        .arg("new-uuid")
+        .arg("--count")
+        .arg("3")
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let uuids: Vec<Uuid> = stdout.lines().map(|l| l.parse().unwrap()).collect();
+    assert_eq!(uuids.len(), 3);
+    assert_ne!(uuids[0], uuids[1]);
+
+    // With a backend, the output can be pasted straight into a contracts file
+    let output = Command::new(BIN)
+        .arg("contracts")
+        .arg("new-uuid")
+        .arg("-n")
+        .arg("2")
+        .arg("--backend")
+        .arg("sgx")
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    let contracts: Vec<Contract> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(contracts.len(), 2);
+    assert!(contracts.iter().all(|c| c.backend == Backend::Sgx));
+
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, &output.stdout).unwrap();
+    let loaded = Contracts::load(Some(path.clone())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.get().len(), 2);
+}
+
+#[tokio::test]
+async fn list_raw_status() {
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new());
+    let url = spawn(state.clone()).await;
+
+    let output = Command::new(BIN)
+        .arg("contracts")
+        .arg("list")
+        .arg("--url")
+        .arg(&url)
+        .arg("--raw-status")
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+    assert!(lines.any(|l| l == "content-type: application/cbor"));
+
+    // The contracts themselves are not shown
+    for contract in state.contracts.get().iter() {
+        assert!(!stdout.contains(&contract.uuid.to_string()));
+    }
+}
+
+#[tokio::test]
+async fn show_attestation_policy() {
+    let contract = Contract {
+        attestation_policy: Some(vec![0; 48]),
+        ..Contract::new(Uuid::new_v4(), Backend::Sev)
+    };
+
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&[&contract]).unwrap()).unwrap();
+    let loaded = Contracts::load(Some(path.clone())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let url = spawn(AppState::new(loaded, KeepStore::new())).await;
+    let output = Command::new(BIN)
+        .arg("contracts")
+        .arg("show")
+        .arg("--url")
+        .arg(&url)
+        .arg(contract.uuid.to_string())
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    // Only the size of the policy is shown
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("attestation_policy: Some(\n        48 bytes,\n    ),"));
+}
+
+#[tokio::test]
+async fn show_params() {
+    let uuid = Uuid::new_v4();
+    let json = format!(
+        r#"[{{"uuid": "{}", "backend": "sev", "params": {{"policy": 48}}}}]"#,
+        uuid
+    );
+
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, json).unwrap();
+    let loaded = Contracts::load(Some(path.clone())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let url = spawn(AppState::new(loaded, KeepStore::new())).await;
+    let output = Command::new(BIN)
+        .arg("contracts")
+        .arg("show")
+        .arg("--url")
+        .arg(&url)
+        .arg(uuid.to_string())
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    // The params survive the trip through the server
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("params: Some("));
+    assert!(stdout.contains("\"policy\""));
+}
+
+#[tokio::test]
+async fn list_quiet() {
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new());
+    let url = spawn(state.clone()).await;
+
+    let output = Command::new(BIN)
+        .arg("contracts")
+        .arg("list")
+        .arg("-q")
+        .arg("--url")
+        .arg(&url)
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    // Nothing but the UUIDs, one per line
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let uuids: Vec<Uuid> = stdout.lines().map(|l| l.parse().unwrap()).collect();
+    let expected: Vec<Uuid> = state.contracts.get().iter().map(|c| c.uuid).collect();
+    assert_eq!(uuids, expected);
+}
+
+#[tokio::test]
+async fn list_group_by_backend() {
+    let contract = |backend| Contract::new(Uuid::new_v4(), backend);
+
+    let contracts = [
+        contract(Backend::Sgx),
+        contract(Backend::Kvm),
+        contract(Backend::Sgx),
+        contract(Backend::Kvm),
+    ];
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&contracts).unwrap()).unwrap();
+    let loaded = Contracts::load(Some(path.clone())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let url = spawn(AppState::new(loaded, KeepStore::new())).await;
+    let output = Command::new(BIN)
+        .arg("contracts")
+        .arg("list")
+        .arg("--hints")
+        .arg("--ascii")
+        .arg("--url")
+        .arg(&url)
+        .arg("--group-by")
+        .arg("backend")
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    // Each backend heading is followed by its contracts in UUID order
+    let group = |backend: &str, hint: &str, a: &Contract, b: &Contract| {
+        let (a, b) = if a.uuid < b.uuid { (a, b) } else { (b, a) };
+        vec![
+            format!("{}:", backend),
+            format!("  {} {} ({})", hint, a.uuid, backend),
+            format!("  {} {} ({})", hint, b.uuid, backend),
+        ]
+    };
+
+    let mut expected = group("kvm", "o", &contracts[1], &contracts[3]);
+    expected.extend(group("sgx", "#", &contracts[0], &contracts[2]));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines, expected);
+}
+
+#[tokio::test]
+async fn validate() {
+    let dir = std::env::temp_dir();
+    let validate = |path: std::path::PathBuf| async move {
+        let output = Command::new(BIN)
+            .arg("contracts")
+            .arg("validate")
+            .arg(&path)
+            .output()
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        output
+    };
+
+    let contract =
+        |backend: &str| serde_json::json!({ "uuid": Uuid::new_v4(), "backend": backend });
+
+    // A valid file, in either encoding
+    let contracts = vec![contract("nil"), contract("sev")];
+    let path = dir.join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&contracts).unwrap()).unwrap();
+    let output = validate(path.clone()).await;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, format!("{}: 2 contracts ok\n", path.display()));
+
+    let path = dir.join(format!("contracts-{}.cbor", Uuid::new_v4()));
+    let decoded: Vec<Contract> = serde_json::from_value(contracts.clone().into()).unwrap();
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(&decoded, &mut bytes).unwrap();
+    std::fs::write(&path, bytes).unwrap();
+    assert!(validate(path).await.status.success());
+
+    // JSON5 is read from a .json5 file, and from a JSON one only if relaxed
+    let relaxed = format!(
+        "[\n  // a comment\n  {{ uuid: \"{}\", backend: \"nil\" }},\n]\n",
+        Uuid::new_v4()
+    );
+    let path = dir.join(format!("contracts-{}.json5", Uuid::new_v4()));
+    std::fs::write(&path, &relaxed).unwrap();
+    assert!(validate(path).await.status.success());
+
+    let path = dir.join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, &relaxed).unwrap();
+    let output = Command::new(BIN)
+        .arg("contracts")
+        .arg("validate")
+        .arg("--relaxed")
+        .arg(&path)
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(validate(path).await.status.code(), Some(2));
+
+    // Every problem is reported against its contract
+    let mut duplicate = contract("kvm");
+    duplicate["uuid"] = contracts[0]["uuid"].clone();
+    let mut window = contract("sgx");
+    window["not_before"] = "2030-01-01T00:00:00Z".into();
+    window["not_after"] = "2020-01-01T00:00:00Z".into();
+    let invalid = vec![contracts[0].clone(), duplicate, window, contract("tdx")];
+
+    let path = dir.join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&invalid).unwrap()).unwrap();
+    let output = validate(path).await;
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let uuid = |index: usize| invalid[index]["uuid"].as_str().unwrap().to_string();
+    assert!(stderr.contains(&format!("contract 1 ({}): duplicates contract 0", uuid(1))));
+    assert!(stderr.contains(&format!(
+        "contract 2 ({}): not_after is before not_before",
+        uuid(2)
+    )));
+    assert!(stderr.contains(&format!("contract 3 ({}): unknown backend: tdx", uuid(3))));
+
+    // A file which cannot be decoded points at where it went wrong
+    let path = dir.join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, "[\n  { \"uuid\": 7 }\n]\n").unwrap();
+    let output = validate(path).await;
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("line 2"));
+}
+
+async fn next<R: AsyncBufRead + Unpin>(lines: &mut Lines<R>) -> String {
+    let line = tokio::time::timeout(Duration::from_secs(10), lines.next_line());
+    line.await.unwrap().unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn watch() {
+    let contract = |backend| Contract::new(Uuid::new_v4(), backend);
+
+    let (kvm, sev, sgx) = (
+        contract(Backend::Kvm),
+        contract(Backend::Sev),
+        contract(Backend::Sgx),
+    );
+
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&[&kvm, &sev]).unwrap()).unwrap();
+    let state = AppState::new(
+        Contracts::load(Some(path.clone())).unwrap(),
+        KeepStore::new(),
+    );
+    let url = spawn(state.clone()).await;
+
+    let mut child = Command::new(BIN)
+        .arg("contracts")
+        .arg("watch")
+        .arg("--url")
+        .arg(&url)
+        .arg("--interval")
+        .arg("50")
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+
+    // The first listing shows every contract as added
+    assert_eq!(next(&mut lines).await, format!("+ {} (kvm)", kvm.uuid));
+    assert_eq!(next(&mut lines).await, format!("+ {} (sev)", sev.uuid));
+
+    // Then only the changes are shown
+    std::fs::write(&path, serde_json::to_vec(&[&kvm, &sgx]).unwrap()).unwrap();
+    state.contracts.reload().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(next(&mut lines).await, format!("- {} (sev)", sev.uuid));
+    assert_eq!(next(&mut lines).await, format!("+ {} (sgx)", sgx.uuid));
+
+    child.kill().await.unwrap();
+}
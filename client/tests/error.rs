@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{serve, AppState, Contracts};
+use franca::KeepStore;
+
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio_stream::wrappers::TcpListenerStream;
+use uuid::Uuid;
+
+const BIN: &str = env!("CARGO_BIN_EXE_client");
+
+async fn spawn(state: AppState) -> String {
+    let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let listen = TcpListener::from_std(socket).unwrap();
+    tokio::spawn(serve(TcpListenerStream::new(listen), state));
+    format!("http://{}/", addr)
+}
+
+/// Runs the client without any saved configuration, returning its exit code.
+async fn exit_code(args: &[&str]) -> i32 {
+    let config = std::env::temp_dir().join(format!("client-{}.json", Uuid::new_v4()));
+
+    let status = Command::new(BIN)
+        .args(args)
+        .arg("--config")
+        .arg(&config)
+        .env_remove("ENARX_SERVER")
+        .env_remove("ENARX_PROFILE")
+        .status()
+        .await
+        .unwrap();
+
+    status.code().unwrap()
+}
+
+#[tokio::test]
+async fn usage() {
+    assert_eq!(exit_code(&["contracts", "list"]).await, 2);
+    assert_eq!(
+        exit_code(&["contracts", "list", "--profile", "nope"]).await,
+        2
+    );
+}
+
+#[tokio::test]
+async fn network() {
+    let args = ["contracts", "list", "--url", "http://127.0.0.1:1/"];
+    assert_eq!(exit_code(&args).await, 3);
+}
+
+#[tokio::test]
+async fn protocol() {
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new());
+    let url = spawn(state).await;
+
+    let uuid = Uuid::new_v4().to_string();
+    let args = ["contracts", "show", "--url", &url, &uuid];
+    assert_eq!(exit_code(&args).await, 4);
+}
+
+#[tokio::test]
+async fn success() {
+    assert_eq!(exit_code(&["contracts", "new-uuid"]).await, 0);
+}
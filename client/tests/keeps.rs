@@ -0,0 +1,308 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{serve, AppState, Contracts};
+use franca::{Backend, Conflict, Contract, KeepStore};
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio_stream::wrappers::TcpListenerStream;
+
+const BIN: &str = env!("CARGO_BIN_EXE_client");
+
+async fn spawn(state: AppState) -> String {
+    let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let listen = TcpListener::from_std(socket).unwrap();
+    tokio::spawn(serve(TcpListenerStream::new(listen), state));
+    format!("http://{}/", addr)
+}
+
+#[tokio::test]
+async fn create_retry_on_conflict() {
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new().capacity(1));
+    let url = spawn(state.clone()).await;
+
+    // Fill the only slot
+    let contract = state.contracts.get()[0].clone();
+    let blocker = state.keeps.create(&contract).unwrap();
+    let uuid = contract.uuid.to_string();
+
+    // Without retries, the client gives up immediately
+    let output = Command::new(BIN)
+        .arg("keeps")
+        .arg("create")
+        .arg("--url")
+        .arg(&url)
+        .arg(&uuid)
+        .output()
+        .await
+        .unwrap();
+    assert!(!output.status.success());
+
+    // With retries, the client waits until the slot is freed
+    let child = Command::new(BIN)
+        .arg("keeps")
+        .arg("create")
+        .arg("--url")
+        .arg(&url)
+        .arg(&uuid)
+        .arg("--retry-on-conflict")
+        .arg("--max-attempts")
+        .arg("50")
+        .arg("--retry-interval")
+        .arg("50")
+        .output();
+    let child = tokio::spawn(child);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    state.keeps.delete(&blocker.uuid).unwrap();
+
+    let output = child.await.unwrap().unwrap();
+    assert!(output.status.success());
+
+    let keeps = state.keeps.list();
+    assert_eq!(keeps.len(), 1);
+    assert_ne!(keeps[0].uuid, blocker.uuid);
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&keeps[0].uuid.to_string()));
+}
+
+#[tokio::test]
+async fn dry_run() {
+    // Nothing listens here, so any request would fail
+    let url = {
+        let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/", socket.local_addr().unwrap())
+    };
+
+    let uuid = uuid::Uuid::new_v4().to_string();
+    let output = Command::new(BIN)
+        .arg("--dry-run")
+        .arg("keeps")
+        .arg("delete")
+        .arg("--url")
+        .arg(&url)
+        .arg(&uuid)
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, format!("DELETE {}keeps/{}\n", url, uuid));
+
+    // Without it, the request is sent and fails
+    let output = Command::new(BIN)
+        .arg("keeps")
+        .arg("delete")
+        .arg("--url")
+        .arg(&url)
+        .arg(&uuid)
+        .output()
+        .await
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[tokio::test]
+async fn delete_from_file() {
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new());
+    let url = spawn(state.clone()).await;
+
+    let contract = state.contracts.get()[0].clone();
+    let one = state.keeps.create(&contract).unwrap().uuid;
+    let two = state.keeps.create(&contract).unwrap().uuid;
+    let unknown = uuid::Uuid::new_v4();
+
+    let path = std::env::temp_dir().join(format!("delete-{}.txt", uuid::Uuid::new_v4()));
+    let lines = format!("{}\nnot-a-uuid\n\n{}\n{}\n", one, unknown, two);
+    std::fs::write(&path, lines).unwrap();
+
+    let output = Command::new(BIN)
+        .arg("keeps")
+        .arg("delete")
+        .arg("--url")
+        .arg(&url)
+        .arg("--from-file")
+        .arg(&path)
+        .arg("--concurrency")
+        .arg("2")
+        .output()
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            format!("{}: deleted", one),
+            "line 2: invalid UUID: not-a-uuid".into(),
+            format!("{}: failed: 404 Not Found", unknown),
+            format!("{}: deleted", two),
+            "2 deleted, 1 failed, 1 invalid".into(),
+        ]
+    );
+
+    assert!(state.keeps.list().is_empty());
+}
+
+#[tokio::test]
+async fn create_region() {
+    let located = |backend, region: Option<&str>| Contract {
+        region: region.map(Into::into),
+        ..Contract::new(uuid::Uuid::new_v4(), backend)
+    };
+
+    let contracts = [
+        located(Backend::Kvm, None),
+        located(Backend::Nil, Some("eu")),
+        located(Backend::Kvm, Some("eu")),
+    ];
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&contracts).unwrap()).unwrap();
+    let loaded = Contracts::load(Some(path.clone())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let state = AppState::new(loaded, KeepStore::new());
+    let url = spawn(state.clone()).await;
+
+    let claim = |region: Option<&'static str>| {
+        let url = url.clone();
+        async move {
+            let mut command = Command::new(BIN);
+            if let Some(region) = region {
+                command.arg("--region").arg(region);
+            }
+
+            command
+                .arg("--quiet")
+                .arg("keeps")
+                .arg("create")
+                .arg("--url")
+                .arg(&url)
+                .arg("--backend")
+                .arg("kvm")
+                .output()
+                .await
+                .unwrap()
+        }
+    };
+
+    let claimed = |output: std::process::Output| {
+        assert!(output.status.success());
+        let uuid: uuid::Uuid = String::from_utf8(output.stdout)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        state.keeps.get(&uuid).unwrap().contract.uuid
+    };
+
+    // A contract in the region is preferred over those listed before it
+    assert_eq!(claimed(claim(Some("eu")).await), contracts[2].uuid);
+
+    // Otherwise the first of the backend is claimed
+    assert_eq!(claimed(claim(Some("ap")).await), contracts[0].uuid);
+    assert_eq!(claimed(claim(None).await), contracts[0].uuid);
+
+    // Listings only show the region's contracts
+    let output = Command::new(BIN)
+        .arg("--region")
+        .arg("eu")
+        .arg("--quiet")
+        .arg("contracts")
+        .arg("list")
+        .arg("--url")
+        .arg(&url)
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+    let expected = format!("{}\n{}\n", contracts[1].uuid, contracts[2].uuid);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    // Nothing is claimed when the backend has no contracts
+    let output = Command::new(BIN)
+        .arg("keeps")
+        .arg("create")
+        .arg("--url")
+        .arg(&url)
+        .arg("--backend")
+        .arg("sgx")
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4));
+}
+
+#[tokio::test]
+async fn prune() {
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new());
+    let url = spawn(state.clone()).await;
+
+    let contract = state.contracts.get()[0].clone();
+    let new = state.keeps.create(&contract).unwrap().uuid;
+    let week = state.keeps.create(&contract).unwrap().uuid;
+    let hours = state.keeps.create(&contract).unwrap().uuid;
+
+    // Backdate two of the keeps
+    let mut export = state.keeps.export();
+    for exported in &mut export.keeps {
+        if exported.keep.uuid == week {
+            exported.created -= 7 * 24 * 60 * 60;
+        } else if exported.keep.uuid == hours {
+            exported.created -= 3 * 60 * 60;
+        }
+    }
+    state.keeps.import(export.keeps, Conflict::Replace).unwrap();
+
+    let prune = |yes: bool| {
+        let mut command = Command::new(BIN);
+        command
+            .arg("keeps")
+            .arg("prune")
+            .arg("--url")
+            .arg(&url)
+            .arg("--older-than")
+            .arg("1d")
+            .stdin(Stdio::null());
+        if yes {
+            command.arg("--yes");
+        }
+        command.output()
+    };
+
+    // Without an answer to the prompt, nothing is deleted
+    let output = prune(false).await.unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&week.to_string()));
+    assert_eq!(state.keeps.list().len(), 3);
+
+    let output = prune(true).await.unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            format!("{}: deleted", week),
+            "1 deleted, 0 failed, 2 kept".into(),
+        ]
+    );
+
+    let mut left: Vec<_> = state.keeps.list().iter().map(|k| k.uuid).collect();
+    left.sort();
+    let mut kept = vec![new, hours];
+    kept.sort();
+    assert_eq!(left, kept);
+}
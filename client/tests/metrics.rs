@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{serve, AppState, Contracts};
+use franca::KeepStore;
+
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio_stream::wrappers::TcpListenerStream;
+
+const BIN: &str = env!("CARGO_BIN_EXE_client");
+
+async fn spawn(state: AppState) -> String {
+    let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let listen = TcpListener::from_std(socket).unwrap();
+    tokio::spawn(serve(TcpListenerStream::new(listen), state));
+    format!("http://{}/", addr)
+}
+
+async fn list(url: &str, metrics: bool) -> String {
+    let mut command = Command::new(BIN);
+    command.arg("contracts").arg("list").arg("--url").arg(url);
+    if metrics {
+        command.arg("--metrics");
+    }
+
+    let output = command.output().await.unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 4);
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[tokio::test]
+async fn metrics() {
+    let url = spawn(AppState::new(
+        Contracts::load(None).unwrap(),
+        KeepStore::new(),
+    ))
+    .await;
+
+    // Nothing is reported unless asked for
+    assert!(!list(&url, false).await.contains("metrics:"));
+
+    let stderr = list(&url, true).await;
+    let lines: Vec<_> = stderr.lines().skip_while(|l| *l != "metrics:").collect();
+    assert_eq!(lines.len(), 7);
+    assert!(lines[1].contains("requests:   1"));
+    for (line, name) in
+        lines[2..]
+            .iter()
+            .zip(&["first-byte", "total", "probed afterwards", "dns", "connect"])
+    {
+        assert!(line.trim_start().starts_with(name));
+    }
+
+    for line in &lines[2..] {
+        assert!(!line.ends_with("n/a"));
+    }
+}
+
+#[tokio::test]
+async fn metrics_unix_socket() {
+    use tokio::net::UnixListener;
+    use tokio_stream::wrappers::UnixListenerStream;
+
+    let path = std::env::temp_dir().join(format!("contractmgr-{}.sock", uuid::Uuid::new_v4()));
+    let listen = UnixListener::bind(&path).unwrap();
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new());
+    tokio::spawn(serve(UnixListenerStream::new(listen), state));
+
+    let output = Command::new(BIN)
+        .arg("--unix-socket")
+        .arg(&path)
+        .arg("--metrics")
+        .arg("contracts")
+        .arg("list")
+        .output()
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    // There is no TCP connection to probe
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<_> = stderr.lines().skip_while(|l| *l != "metrics:").collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[3].trim_start().starts_with("total:"));
+}
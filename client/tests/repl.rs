@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{serve, AppState, Contracts};
+use franca::KeepStore;
+
+use std::process::Stdio;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio_stream::wrappers::TcpListenerStream;
+
+const BIN: &str = env!("CARGO_BIN_EXE_client");
+
+async fn spawn(state: AppState) -> String {
+    let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let listen = TcpListener::from_std(socket).unwrap();
+    tokio::spawn(serve(TcpListenerStream::new(listen), state));
+    format!("http://{}/", addr)
+}
+
+#[tokio::test]
+async fn scripted() {
+    let state = AppState::new(Contracts::load(None).unwrap(), KeepStore::new());
+    let url = spawn(state.clone()).await;
+    let contract = state.contracts.get()[0].clone();
+
+    let script = format!(
+        "contracts list --hints --ascii\nbogus\n\nkeeps create {}\nhistory\n!1\n!9\nexit\ncontracts list\n",
+        contract.uuid
+    );
+
+    let mut child = Command::new(BIN)
+        .arg("repl")
+        .arg("--url")
+        .arg(&url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(script.as_bytes()).await.unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().await.unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    let listing: Vec<_> = state
+        .contracts
+        .get()
+        .iter()
+        .map(|c| format!("{} {} ({})", c.backend.ascii_hint(), c.uuid, c.backend))
+        .collect();
+
+    // The listing, the keep, the history and the listing again; nothing
+    // after `exit` runs
+    assert_eq!(lines[..4], listing[..]);
+    let keeps = state.keeps.list();
+    assert_eq!(keeps.len(), 1);
+    assert!(stdout.contains(&keeps[0].uuid.to_string()));
+
+    let history = lines
+        .iter()
+        .position(|l| l.contains("1  contracts list"))
+        .unwrap();
+    assert_eq!(lines[history + 1], "    2  bogus");
+    assert_eq!(
+        lines[history + 2],
+        format!("    3  keeps create {}", contract.uuid)
+    );
+    assert_eq!(lines[history + 3..], listing[..]);
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("bogus"));
+    assert!(stderr.contains("!9: event not found"));
+}
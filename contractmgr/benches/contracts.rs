@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::Offered;
+use franca::{Backend, Contract};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use uuid::Uuid;
+
+const SIZES: &[usize] = &[4, 64, 1024, 16384];
+
+fn offered(size: usize) -> Vec<Contract> {
+    let backends = [Backend::Nil, Backend::Kvm, Backend::Sev, Backend::Sgx];
+
+    (0..size)
+        .map(|i| Contract::new(Uuid::new_v4(), backends[i % backends.len()].clone()))
+        .collect()
+}
+
+/// Compares scanning the list with using its index, looking up the last
+/// contract as the worst case for a scan.
+fn lookups(c: &mut Criterion) {
+    let mut uuid = c.benchmark_group("contract by uuid");
+    for size in SIZES {
+        let list = offered(*size);
+        let last = list.last().unwrap().uuid;
+        let indexed = Offered::from(list.clone());
+
+        uuid.bench_with_input(BenchmarkId::new("scan", size), &last, |b, last| {
+            b.iter(|| list.iter().find(|c| c.uuid == *last).unwrap())
+        });
+        uuid.bench_with_input(BenchmarkId::new("index", size), &last, |b, last| {
+            b.iter(|| indexed.find(last).unwrap())
+        });
+    }
+    uuid.finish();
+
+    let mut backend = c.benchmark_group("contracts by backend");
+    for size in SIZES {
+        let list = offered(*size);
+        let indexed = Offered::from(list.clone());
+
+        backend.bench_with_input(BenchmarkId::new("scan", size), &Backend::Sgx, |b, sgx| {
+            b.iter(|| list.iter().filter(|c| c.backend == *sgx).count())
+        });
+        backend.bench_with_input(BenchmarkId::new("index", size), &Backend::Sgx, |b, sgx| {
+            b.iter(|| indexed.backend(sgx).count())
+        });
+    }
+    backend.finish();
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
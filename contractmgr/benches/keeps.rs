@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use franca::{Backend, Contract, Keep};
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dashmap::DashMap;
+use uuid::Uuid;
+
+const THREADS: usize = 4;
+const OPERATIONS: usize = 1024;
+const PRELOAD: usize = 256;
+
+/// The operations the server performs on its keep map.
+trait KeepMap: Send + Sync + 'static {
+    fn create(&self, keep: Keep);
+    fn get(&self, uuid: &Uuid) -> Option<Keep>;
+    fn delete(&self, uuid: &Uuid) -> Option<Keep>;
+}
+
+impl KeepMap for RwLock<HashMap<Uuid, Keep>> {
+    fn create(&self, keep: Keep) {
+        self.write().unwrap().insert(keep.uuid, keep);
+    }
+
+    fn get(&self, uuid: &Uuid) -> Option<Keep> {
+        self.read().unwrap().get(uuid).cloned()
+    }
+
+    fn delete(&self, uuid: &Uuid) -> Option<Keep> {
+        self.write().unwrap().remove(uuid)
+    }
+}
+
+impl KeepMap for DashMap<Uuid, Keep> {
+    fn create(&self, keep: Keep) {
+        self.insert(keep.uuid, keep);
+    }
+
+    fn get(&self, uuid: &Uuid) -> Option<Keep> {
+        DashMap::get(self, uuid).map(|k| k.value().clone())
+    }
+
+    fn delete(&self, uuid: &Uuid) -> Option<Keep> {
+        self.remove(uuid).map(|(_, k)| k)
+    }
+}
+
+fn keep() -> Keep {
+    Keep {
+        uuid: Uuid::new_v4(),
+        contract: Contract::new(
+            Uuid::from_u128(0xe6234733_513a_4883_981a_bfa972fa706b),
+            Backend::Nil,
+        ),
+        owner: None,
+        labels: Default::default(),
+        created: None,
+        handle: None,
+        links: None,
+    }
+}
+
+/// Runs `OPERATIONS` operations on each of `THREADS` threads.
+///
+/// Out of every ten operations, `reads` are lookups of existing keeps and the
+/// remainder are a create followed by a delete of a fresh keep.
+fn contend<M: KeepMap>(map: &Arc<M>, known: &Arc<Vec<Uuid>>, reads: usize) {
+    let threads: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let map = map.clone();
+            let known = known.clone();
+
+            thread::spawn(move || {
+                for i in 0..OPERATIONS {
+                    if i % 10 < reads {
+                        let uuid = &known[(i * THREADS + t) % known.len()];
+                        assert!(map.get(uuid).is_some());
+                    } else {
+                        let keep = keep();
+                        let uuid = keep.uuid;
+                        map.create(keep);
+                        assert!(map.delete(&uuid).is_some());
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+fn bench<M: KeepMap>(c: &mut Criterion, name: &str, map: M) {
+    let map = Arc::new(map);
+    let known: Vec<Uuid> = (0..PRELOAD)
+        .map(|_| {
+            let keep = keep();
+            let uuid = keep.uuid;
+            map.create(keep);
+            uuid
+        })
+        .collect();
+    let known = Arc::new(known);
+
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements((THREADS * OPERATIONS) as u64));
+    for (mix, reads) in &[("read-heavy", 9), ("write-heavy", 1)] {
+        group.bench_with_input(BenchmarkId::from_parameter(mix), reads, |b, reads| {
+            b.iter(|| contend(&map, &known, *reads))
+        });
+    }
+    group.finish();
+}
+
+fn keeps(c: &mut Criterion) {
+    bench(c, "RwLock<HashMap>", RwLock::new(HashMap::new()));
+    bench(c, "DashMap", DashMap::new());
+}
+
+criterion_group!(benches, keeps);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::Infallible;
+use std::time::Instant;
+
+use uuid::Uuid;
+use warp::filters::path::FullPath;
+use warp::http::{HeaderMap, Method};
+use warp::reply::Response;
+use warp::Filter;
+
+/// A request as it arrived, to be logged once it has been answered.
+#[derive(Debug)]
+pub struct Access {
+    method: Method,
+    path: FullPath,
+    id: String,
+    started: Instant,
+}
+
+impl Access {
+    /// Logs the request along with its response.
+    pub fn log(self, response: &Response) {
+        tracing::info!(
+            method = %self.method,
+            path = self.path.as_str(),
+            status = response.status().as_u16(),
+            latency_us = self.started.elapsed().as_micros() as u64,
+            request_id = %self.id,
+            "request answered"
+        );
+    }
+}
+
+/// Notes each request for the access log.
+///
+/// A request is known by its `X-Request-Id`, or by a random ID if it has
+/// none, so that its log line can be matched with the client's.
+pub fn start() -> impl Filter<Extract = (Access,), Error = Infallible> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .map(|method, path, headers: HeaderMap| {
+            let id = headers
+                .get("x-request-id")
+                .and_then(|id| id.to_str().ok())
+                .map(String::from)
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+            Access {
+                method,
+                path,
+                id,
+                started: Instant::now(),
+            }
+        })
+}
@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::Infallible;
+
+use serde_json::Value;
+use warp::filters::path::FullPath;
+use warp::http::{HeaderMap, Method, Request, Response};
+use warp::hyper::body::{Body, Bytes};
+use warp::hyper::service::Service;
+use warp::{Filter, Rejection, Reply};
+
+/// The most characters of a body written to the log.
+const LIMIT: usize = 1024;
+
+/// Object keys whose values are never written to the log.
+const SECRETS: &[&str] = &["authorization", "password", "secret", "token"];
+
+/// Replaces the values of secret keys, wherever they are nested.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SECRETS.contains(&key.to_lowercase().as_str()) {
+                    *value = Value::String("[redacted]".into());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Renders a body for the log.
+///
+/// JSON and CBOR bodies are shown as redacted JSON; anything else as hex.
+fn describe(body: &[u8]) -> String {
+    if body.is_empty() {
+        return "(empty)".into();
+    }
+
+    let value = match serde_json::from_slice(body) {
+        Ok(value) => Some(value),
+        Err(..) => ciborium::de::from_reader(body).ok(),
+    };
+
+    let mut text = match value {
+        Some(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        None => body
+            .iter()
+            .take(LIMIT)
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    };
+
+    if text.len() > LIMIT {
+        let mut end = LIMIT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        text.truncate(end);
+        text.push_str("...");
+    }
+
+    format!("{} ({} bytes)", text, body.len())
+}
+
+/// Passes one request through the wrapped routes, logging both bodies.
+async fn exchange<S>(
+    mut service: S,
+    method: Method,
+    path: FullPath,
+    query: String,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response<Body>, Rejection>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible> + Send + 'static,
+    S::Future: Send,
+{
+    let path = path.as_str();
+    tracing::trace!(%method, path, body = %describe(&body), "request");
+
+    let uri = match query.as_str() {
+        "" => path.to_string(),
+        query => format!("{}?{}", path, query),
+    };
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::from(body))
+        .unwrap();
+    *request.headers_mut() = headers;
+
+    // warp can't run one filter inside another on the same task, so the
+    // wrapped routes get a task of their own.
+    let task = tokio::spawn(async move { service.call(request).await });
+    let response = match task.await.unwrap() {
+        Ok(response) => response,
+        Err(never) => match never {},
+    };
+
+    let (parts, body) = response.into_parts();
+    let body = warp::hyper::body::to_bytes(body).await.unwrap_or_default();
+    let status = parts.status.as_u16();
+    tracing::trace!(status, body = %describe(&body), "response");
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Wraps the routes so that request and response bodies are logged at `TRACE`.
+///
+/// Both bodies are buffered in full, so responses are no longer streamed.
+pub fn log_bodies<F>(
+    routes: F,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone
+where
+    F: Filter<Error = Infallible> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+    F::Future: Send,
+{
+    let service = warp::service(routes);
+    let service = warp::any().map(move || service.clone());
+    let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+
+    service
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(query)
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and_then(exchange)
+}
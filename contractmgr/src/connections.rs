@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::watch;
+use warp::{Filter, Rejection};
+
+/// Tracks the requests in flight, so that a draining server can be watched.
+#[derive(Debug)]
+pub struct Connections {
+    active: AtomicUsize,
+    draining: watch::Sender<bool>,
+
+    // Held so that the channel stays open however many servers are waiting.
+    drained: watch::Receiver<bool>,
+}
+
+impl Default for Connections {
+    fn default() -> Self {
+        let (draining, drained) = watch::channel(false);
+        Self {
+            active: AtomicUsize::new(0),
+            draining,
+            drained,
+        }
+    }
+}
+
+/// The requests in flight, reported by `GET /status/connections`.
+#[derive(Debug, Serialize)]
+pub struct Status {
+    /// The number of requests being handled, including this one
+    active: usize,
+
+    /// Whether the server has stopped accepting connections
+    draining: bool,
+}
+
+/// The server is draining, so new requests are refused.
+#[derive(Debug)]
+pub struct ShuttingDown(pub u64);
+
+impl warp::reject::Reject for ShuttingDown {}
+
+/// A request in flight, which is no longer counted once dropped.
+pub struct Active(Arc<Connections>);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Connections {
+    /// The current number of requests in flight.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            active: self.active(),
+            draining: self.is_draining(),
+        }
+    }
+
+    /// Whether the servers have started draining.
+    pub fn is_draining(&self) -> bool {
+        *self.drained.borrow()
+    }
+
+    /// Stops the servers from accepting connections.
+    ///
+    /// Requests in flight are completed before the servers return.
+    pub fn drain(&self) {
+        let _ = self.draining.send(true);
+    }
+
+    /// Resolves once the servers should start draining.
+    pub async fn draining(&self) {
+        let mut drained = self.drained.clone();
+        while !*drained.borrow() {
+            if drained.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Counts a request in flight until the extracted guard is dropped.
+pub fn track(
+    connections: Arc<Connections>,
+) -> impl Filter<Extract = (Active,), Error = Infallible> + Clone {
+    warp::any().map(move || {
+        connections.active.fetch_add(1, Ordering::SeqCst);
+        Active(connections.clone())
+    })
+}
+
+/// Refuses requests which arrive once draining has begun, asking the client
+/// to retry after `retry_after` seconds.
+///
+/// Requests already past this filter are left to complete.
+pub fn accepting(
+    connections: Arc<Connections>,
+    retry_after: u64,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let draining = connections.is_draining();
+            async move {
+                match draining {
+                    true => Err(warp::reject::custom(ShuttingDown(retry_after))),
+                    false => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
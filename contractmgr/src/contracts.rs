@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use franca::{Backend, Contract, Probe};
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Result};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use arc_swap::{ArcSwap, Guard};
+use uuid::Uuid;
+use warp::{Filter, Rejection};
+
+/// The contracts offered when no contracts file is given.
+const BUILTIN: &[Contract] = &[
+    Contract::new(
+        Uuid::from_u128(0xe6234733_513a_4883_981a_bfa972fa706b),
+        Backend::Nil,
+    ),
+    Contract::new(
+        Uuid::from_u128(0x0afa438e_acaa_4158_9518_ad59256def34),
+        Backend::Kvm,
+    ),
+    Contract::new(
+        Uuid::from_u128(0x31a41b53_cb9e_447b_bfa2_bfb8e6e42ff9),
+        Backend::Sev,
+    ),
+    Contract::new(
+        Uuid::from_u128(0xea392851_3435_42d3_a4ad_c4e5e5c6c4c6),
+        Backend::Sgx,
+    ),
+];
+
+/// The namespace of the UUIDs of contracts generated from backends.
+const NAMESPACE: Uuid = Uuid::from_u128(0x8c1f52e4_6d0b_4a7e_9b35_f2a1c7d90e68);
+
+/// Reads and validates a contracts file.
+///
+/// Files ending in `.cbor` are decoded as CBOR and files ending in `.json5`
+/// as JSON5. All others are strict JSON, unless `relaxed` is set, in which
+/// case they are JSON5 too.
+fn read(path: &Path, relaxed: bool) -> Result<Vec<Contract>> {
+    let json5 = |path| {
+        json5::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+    };
+
+    let contracts: Vec<Contract> = match path.extension() {
+        Some(ext) if ext == "cbor" => ciborium::de::from_reader(std::fs::File::open(path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?,
+        Some(ext) if ext == "json5" => json5(path)?,
+        _ if relaxed => json5(path)?,
+        _ => serde_json::from_reader(std::fs::File::open(path)?)?,
+    };
+
+    let mut uuids = HashSet::new();
+    for (index, contract) in contracts.iter().enumerate() {
+        if let Err(invalid) = contract.validate() {
+            let invalid: Vec<_> = invalid.iter().map(ToString::to_string).collect();
+            let msg = format!("invalid contract {}: {}", index, invalid.join(", "));
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+
+        if !uuids.insert(contract.uuid) {
+            let msg = format!("duplicate contract: {}", contract.uuid);
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+    }
+
+    Ok(contracts)
+}
+
+/// A list of contracts, indexed by UUID and by backend.
+///
+/// It dereferences to the list itself, in the order the contracts were given.
+#[derive(Debug, Default)]
+pub struct Offered {
+    list: Vec<Contract>,
+    uuids: HashMap<Uuid, usize>,
+    backends: HashMap<Backend, Vec<Uuid>>,
+}
+
+impl From<Vec<Contract>> for Offered {
+    fn from(list: Vec<Contract>) -> Self {
+        let mut uuids = HashMap::with_capacity(list.len());
+        let mut backends: HashMap<Backend, Vec<Uuid>> = HashMap::new();
+
+        for (index, contract) in list.iter().enumerate() {
+            uuids.insert(contract.uuid, index);
+            backends
+                .entry(contract.backend.clone())
+                .or_default()
+                .push(contract.uuid);
+        }
+
+        Self {
+            list,
+            uuids,
+            backends,
+        }
+    }
+}
+
+impl Deref for Offered {
+    type Target = Vec<Contract>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.list
+    }
+}
+
+impl PartialEq<Vec<Contract>> for Offered {
+    fn eq(&self, other: &Vec<Contract>) -> bool {
+        self.list == *other
+    }
+}
+
+impl Offered {
+    /// Finds a contract by its UUID.
+    pub fn find(&self, uuid: &Uuid) -> Option<&Contract> {
+        self.uuids.get(uuid).map(|index| &self.list[*index])
+    }
+
+    /// Lists the contracts of a backend, in the order they were given.
+    pub fn backend<'a>(&'a self, backend: &Backend) -> impl Iterator<Item = &'a Contract> + Clone {
+        self.backends
+            .get(backend)
+            .into_iter()
+            .flatten()
+            .filter_map(move |uuid| self.find(uuid))
+    }
+}
+
+/// The set of contracts currently offered.
+///
+/// Readers get a snapshot of the contracts without taking a lock. Reloads
+/// replace the whole list atomically, so readers never wait on a reload or
+/// see a partial list.
+#[derive(Debug)]
+pub struct Contracts {
+    path: Option<PathBuf>,
+    relaxed: bool,
+    loaded: AtomicBool,
+    list: ArcSwap<Offered>,
+}
+
+impl Contracts {
+    /// Loads the contracts from `path`, or the built-in contracts.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        Self::load_with(path, false)
+    }
+
+    /// Loads the contracts like [`Contracts::load`], but parses a JSON file
+    /// as JSON5 if `relaxed` is set, here and on every reload.
+    pub fn load_with(path: Option<PathBuf>, relaxed: bool) -> Result<Self> {
+        let list = match &path {
+            Some(path) => read(path, relaxed)?,
+            None => BUILTIN.to_vec(),
+        };
+
+        Ok(Self {
+            path,
+            relaxed,
+            loaded: AtomicBool::new(true),
+            list: ArcSwap::from_pointee(list.into()),
+        })
+    }
+
+    /// Offers no contracts until the first successful [`Contracts::reload`]
+    /// reads them from `path`.
+    ///
+    /// This lets a large contracts file be read while the server starts.
+    pub fn pending(path: PathBuf, relaxed: bool) -> Self {
+        Self {
+            path: Some(path),
+            relaxed,
+            loaded: AtomicBool::new(false),
+            list: ArcSwap::default(),
+        }
+    }
+
+    /// Indicates whether the contracts have been read at least once.
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.load(Ordering::SeqCst)
+    }
+
+    /// Offers one contract for each backend the host supports.
+    ///
+    /// The UUIDs are derived from the backend names, so they are the same on
+    /// every host and across restarts.
+    pub fn from_backends(probe: &dyn Probe) -> Self {
+        let list = Backend::all()
+            .iter()
+            .filter(|backend| probe.supports(backend))
+            .map(|backend| {
+                Contract::new(
+                    Uuid::new_v5(&NAMESPACE, backend.as_str().as_bytes()),
+                    backend.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            path: None,
+            relaxed: false,
+            loaded: AtomicBool::new(true),
+            list: ArcSwap::from_pointee(list.into()),
+        }
+    }
+
+    /// Gets a snapshot of the current contracts.
+    pub fn get(&self) -> Guard<Arc<Offered>> {
+        self.list.load()
+    }
+
+    /// Re-reads the contracts file.
+    ///
+    /// On failure, the current contracts are left in place.
+    pub fn reload(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let list = read(path, self.relaxed)?;
+            self.list.store(Arc::new(list.into()));
+        }
+
+        self.loaded.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// The contracts have not been loaded yet; retry after this many seconds.
+#[derive(Debug)]
+pub struct Loading(pub u64);
+
+impl warp::reject::Reject for Loading {}
+
+/// Refuses requests until the contracts are first loaded, asking the client
+/// to retry after `retry_after` seconds.
+pub fn loaded(
+    contracts: Arc<Contracts>,
+    retry_after: u64,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let loaded = contracts.is_loaded();
+            async move {
+                match loaded {
+                    true => Ok(()),
+                    false => Err(warp::reject::custom(Loading(retry_after))),
+                }
+            }
+        })
+        .untuple_one()
+}
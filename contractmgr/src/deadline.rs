@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::future::Future;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio_stream::{Stream, StreamExt};
+use warp::http::StatusCode;
+use warp::hyper::body::{Buf, Bytes};
+use warp::{Filter, Rejection};
+
+/// Rejects a request whose `X-Deadline` has passed or can't be read, or
+/// whose body didn't arrive in full before it.
+#[derive(Debug)]
+struct Expired(StatusCode);
+
+impl warp::reject::Reject for Expired {}
+
+/// Reads a deadline in RFC 3339 format.
+fn parse(deadline: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(deadline)
+        .ok()
+        .map(|deadline| deadline.with_timezone(&Utc))
+}
+
+/// Turns away requests which arrive after their `X-Deadline`.
+///
+/// Only waiting on a body can hold up a request which got past this, so the
+/// routes taking one read it with [`bytes`] or [`within`].
+pub fn check() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional("x-deadline")
+        .and_then(|deadline: Option<String>| async move {
+            let deadline = match deadline {
+                None => return Ok(()),
+                Some(deadline) => deadline,
+            };
+
+            match parse(&deadline) {
+                None => Err(warp::reject::custom(Expired(StatusCode::BAD_REQUEST))),
+                Some(at) if at <= Utc::now() => {
+                    Err(warp::reject::custom(Expired(StatusCode::REQUEST_TIMEOUT)))
+                }
+                Some(..) => Ok(()),
+            }
+        })
+        .untuple_one()
+}
+
+/// Finds the status code of a request turned away for its deadline.
+pub fn expired(rejection: &Rejection) -> Option<StatusCode> {
+    rejection.find::<Expired>().map(|expired| expired.0)
+}
+
+/// The time left before a request's `X-Deadline`, if it sent one.
+///
+/// Requests whose deadline is unreadable were already turned away by
+/// [`check`].
+pub fn left() -> impl Filter<Extract = (Option<Duration>,), Error = Rejection> + Clone {
+    warp::header::optional("x-deadline").map(|deadline: Option<String>| {
+        let at = parse(&deadline?)?;
+        Some((at - Utc::now()).to_std().unwrap_or_default())
+    })
+}
+
+/// Does the work, giving up with `408 Request Timeout` once `left` is up.
+pub async fn within<T>(
+    left: Option<Duration>,
+    work: impl Future<Output = T>,
+) -> Result<T, StatusCode> {
+    let left = match left {
+        None => return Ok(work.await),
+        Some(left) => left,
+    };
+
+    tokio::time::timeout(left, work)
+        .await
+        .map_err(|_| StatusCode::REQUEST_TIMEOUT)
+}
+
+async fn read<S, B>(body: S) -> Result<Bytes, warp::Error>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    tokio::pin!(body);
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk?;
+        bytes.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+    }
+
+    Ok(bytes.into())
+}
+
+/// Reads the whole body, like `warp::body::bytes()`, but only until the
+/// request's `X-Deadline`.
+pub fn bytes() -> impl Filter<Extract = (Bytes,), Error = Rejection> + Clone {
+    left()
+        .and(warp::body::stream())
+        .and_then(|left, body| async move {
+            match within(left, read(body)).await {
+                Ok(Ok(bytes)) => Ok(bytes),
+                Ok(Err(..)) => Err(warp::reject::custom(Expired(StatusCode::BAD_REQUEST))),
+                Err(code) => Err(warp::reject::custom(Expired(code))),
+            }
+        })
+}
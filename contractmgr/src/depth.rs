@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::TryFrom;
+
+/// How deeply request bodies may nest by default.
+///
+/// Decoding recurses once per level, so a small body which nests deeply
+/// enough could otherwise exhaust the stack.
+pub const DEFAULT: usize = 64;
+
+/// Checks that a JSON document nests no deeper than `limit`.
+pub fn json(body: &[u8], limit: usize) -> bool {
+    let mut depth = 0usize;
+    let mut string = false;
+    let mut escaped = false;
+
+    for byte in body {
+        if string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => string = false,
+                _ => (),
+            }
+
+            continue;
+        }
+
+        match byte {
+            b'"' => string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > limit {
+                    return false;
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+
+    true
+}
+
+/// Checks that a CBOR item nests no deeper than `limit`.
+///
+/// Malformed items are let through, for the decoder to reject.
+pub fn cbor(body: &[u8], limit: usize) -> bool {
+    scan(body, limit).unwrap_or(true)
+}
+
+/// Walks the CBOR item headers without recursing.
+///
+/// Returns `None` if the item is malformed.
+fn scan(body: &[u8], limit: usize) -> Option<bool> {
+    // The items left in each open container, or `None` until a break.
+    let mut open: Vec<Option<u64>> = vec![Some(1)];
+    let mut at = 0;
+
+    while let Some(&left) = open.last() {
+        match left {
+            Some(0) => {
+                open.pop();
+                continue;
+            }
+
+            Some(n) => *open.last_mut().unwrap() = Some(n - 1),
+
+            None if body.get(at) == Some(&0xff) => {
+                at += 1;
+                open.pop();
+                continue;
+            }
+
+            None => (),
+        }
+
+        let initial = *body.get(at)?;
+        at += 1;
+
+        let argument = match initial & 0x1f {
+            info @ 0..=23 => Some(u64::from(info)),
+            info @ 24..=27 => {
+                let size = 1 << (info - 24);
+                let bytes = body.get(at..at + size)?;
+                at += size;
+                Some(bytes.iter().fold(0, |n, b| n << 8 | u64::from(*b)))
+            }
+            31 => None,
+            _ => return None,
+        };
+
+        match (initial >> 5, argument) {
+            (2, Some(length)) | (3, Some(length)) => {
+                at = at.checked_add(usize::try_from(length).ok()?)?;
+                if at > body.len() {
+                    return None;
+                }
+            }
+            (2, None) | (3, None) | (4, _) => open.push(argument),
+            (5, Some(pairs)) => open.push(Some(pairs.checked_mul(2)?)),
+            (5, None) => open.push(None),
+            (6, Some(..)) => open.push(Some(1)),
+            (0, Some(..)) | (1, Some(..)) | (7, Some(..)) => (),
+            _ => return None,
+        }
+
+        if open.len() - 1 > limit {
+            return Some(false);
+        }
+    }
+
+    Some(true)
+}
@@ -0,0 +1,1355 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+mod access;
+mod bodies;
+mod connections;
+mod contracts;
+mod deadline;
+mod depth;
+mod listings;
+mod metrics;
+mod ndjson;
+mod panics;
+mod peers;
+mod persist;
+mod policy;
+mod rates;
+mod tokens;
+
+pub use bodies::log_bodies;
+pub use connections::Connections;
+pub use contracts::{Contracts, Offered};
+pub use listings::Listings;
+pub use metrics::{push, Requests};
+pub use peers::{serve_peers, Peers};
+pub use persist::StateFile;
+pub use policy::ClaimPolicy;
+pub use rates::Claims;
+pub use tokens::{Role, Tokens};
+
+use connections::ShuttingDown;
+use contracts::Loading;
+use franca::{Backend, Conflict, Contract, Export, Full, Host, Keep, KeepStore, Probe, Tier};
+use metrics::Unencodable;
+use panics::guard;
+use tokens::require;
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::http::header::{
+    HeaderMap, HeaderValue, CONTENT_LOCATION, CONTENT_TYPE, ETAG, LOCATION, RETRY_AFTER, SERVER,
+};
+use warp::http::{Response, StatusCode};
+use warp::hyper::body::{Body, Bytes};
+use warp::path::FullPath;
+use warp::{Filter, Rejection, Reply};
+
+/// Everything the request handlers share.
+///
+/// Each instance is independent, so several servers can run in one process.
+#[derive(Clone, Debug)]
+pub struct AppState {
+    pub contracts: Arc<Contracts>,
+    pub keeps: Arc<KeepStore>,
+
+    /// Pretty-print JSON responses
+    pub pretty: bool,
+
+    /// When set, requests must carry a token with a sufficient role
+    pub tokens: Option<Arc<Tokens>>,
+
+    /// The role of a peer known by its Unix credentials, in place of tokens
+    pub peer: Option<Role>,
+
+    /// Identify the server in a `Server` header on every response
+    pub server_header: bool,
+
+    /// Leave contracts which can no longer be claimed out of listings
+    pub hide_expired: bool,
+
+    /// Decides which contracts this host could run
+    pub probe: Arc<dyn Probe>,
+
+    /// Refuse to claim contracts which this host can't run
+    pub require_supported: bool,
+
+    /// How a claim by backend chooses between the contracts offering it
+    pub claim_policy: ClaimPolicy,
+
+    /// Log request and response bodies at `TRACE`
+    pub log_bodies: bool,
+
+    /// Where keeps are saved, if anywhere
+    pub state_file: Option<StateFile>,
+
+    /// How deeply request bodies may nest
+    pub max_depth: usize,
+
+    /// The longest request path and query, in bytes
+    pub max_uri_length: usize,
+
+    /// Recent keep claims for each contract
+    pub claims: Arc<Claims>,
+
+    /// Encoded listings of all the contracts, reused between requests
+    pub listings: Arc<Listings>,
+
+    /// The requests in flight, and whether the server is draining
+    pub connections: Arc<Connections>,
+
+    /// The seconds a client refused while loading or draining is asked to wait
+    pub retry_after: u64,
+
+    /// The requests answered so far
+    pub requests: Arc<Requests>,
+}
+
+impl AppState {
+    /// Creates a state with the default settings.
+    pub fn new(contracts: Contracts, keeps: KeepStore) -> Self {
+        Self {
+            contracts: Arc::new(contracts),
+            keeps: Arc::new(keeps),
+            pretty: false,
+            tokens: None,
+            peer: None,
+            server_header: true,
+            hide_expired: false,
+            probe: Arc::new(Host),
+            require_supported: false,
+            claim_policy: ClaimPolicy::First,
+            log_bodies: false,
+            state_file: None,
+            max_depth: depth::DEFAULT,
+            max_uri_length: MAX_URI_LENGTH,
+            claims: Arc::default(),
+            listings: Arc::default(),
+            connections: Arc::default(),
+            retry_after: 5,
+            requests: Arc::default(),
+        }
+    }
+}
+
+/// The largest request body accepted.
+const MAX_BODY: u64 = 16 * 1024 * 1024;
+
+/// The longest request path and query accepted by default.
+const MAX_URI_LENGTH: usize = 2048;
+
+#[derive(Debug, Deserialize)]
+struct ContractsQuery {
+    fields: Option<String>,
+    sort: Option<String>,
+    supported: Option<bool>,
+    region: Option<String>,
+    include_disabled: Option<String>,
+}
+
+/// A contract reduced to the fields a client asked for.
+#[derive(Debug, Default, Serialize)]
+struct Projection<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid: Option<&'a Uuid>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backend: Option<&'a Backend>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    not_before: Option<&'a DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    not_after: Option<&'a DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attestation_policy: Option<&'a Vec<u8>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<&'a String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: Option<&'a ciborium::value::Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    claim_cooldown: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+}
+
+impl<'a> Projection<'a> {
+    fn new(contract: &'a Contract, fields: &str) -> Result<Self, StatusCode> {
+        let mut projection = Self::default();
+
+        for field in fields.split(',') {
+            match field {
+                "uuid" => projection.uuid = Some(&contract.uuid),
+                "backend" => projection.backend = Some(&contract.backend),
+                "not_before" => projection.not_before = contract.not_before.as_ref(),
+                "not_after" => projection.not_after = contract.not_after.as_ref(),
+                "cost" => projection.cost = contract.cost,
+                "attestation_policy" => {
+                    projection.attestation_policy = contract.attestation_policy.as_ref()
+                }
+                "region" => projection.region = contract.region.as_ref(),
+                "params" => projection.params = contract.params.as_ref(),
+                "claim_cooldown" => projection.claim_cooldown = contract.claim_cooldown,
+                "enabled" => projection.enabled = Some(contract.enabled),
+                _ => return Err(StatusCode::BAD_REQUEST),
+            }
+        }
+
+        Ok(projection)
+    }
+}
+
+impl ContractsQuery {
+    /// Whether the query asks for all the contracts, as they are offered.
+    fn is_plain(&self) -> bool {
+        self.fields.is_none()
+            && self.sort.is_none()
+            && self.supported.is_none()
+            && self.region.is_none()
+            && self.include_disabled.is_none()
+    }
+
+    /// Orders and projects the contracts as requested.
+    fn reply(&self, contracts: &[Contract], enc: Encoding) -> Response<Vec<u8>> {
+        let mut contracts: Vec<&Contract> = contracts.iter().collect();
+
+        // Sorting is stable, so ties keep their configured order.
+        match self.sort.as_deref() {
+            None => (),
+            Some("uuid") => contracts.sort_by_key(|c| c.uuid),
+            Some("backend") => contracts.sort_by(|a, b| a.backend.as_str().cmp(b.backend.as_str())),
+            Some("cost") => contracts.sort_by_key(|c| c.cost_order()),
+            Some(..) => return error(StatusCode::BAD_REQUEST),
+        }
+
+        let fields = match self.fields {
+            None => return enc.reply(StatusCode::OK, &contracts),
+            Some(ref fields) => fields,
+        };
+
+        let projected: Result<Vec<_>, _> = contracts
+            .into_iter()
+            .map(|c| Projection::new(c, fields))
+            .collect();
+
+        match projected {
+            Err(code) => error(code),
+            Ok(projected) => enc.reply(StatusCode::OK, &projected),
+        }
+    }
+}
+
+/// What is known about a backend, and whether this host can run it.
+#[derive(Debug, Serialize)]
+struct BackendInfo {
+    name: &'static str,
+    aliases: Vec<&'static str>,
+    hint: &'static str,
+    device: Option<&'static str>,
+    tier: Option<Tier>,
+    supported: bool,
+    capacity: Option<usize>,
+}
+
+impl BackendInfo {
+    fn new(backend: &'static Backend, probe: &dyn Probe) -> Self {
+        Self {
+            name: backend.as_str(),
+            aliases: backend.aliases().collect(),
+            hint: backend.display_hint(),
+            device: backend.device(),
+            tier: backend.tier(),
+            supported: probe.supports(backend),
+            capacity: probe.capacity(backend),
+        }
+    }
+}
+
+/// The features and limits of a running server.
+#[derive(Debug, Serialize)]
+struct Capabilities {
+    encodings: &'static [&'static str],
+    auth: bool,
+    max_body: u64,
+    max_keeps: Option<usize>,
+    max_keeps_per_owner: Option<usize>,
+    keep_ttl: Option<u64>,
+    soft_delete_retention: Option<u64>,
+    hide_expired: bool,
+    require_supported: bool,
+    claim_policy: &'static str,
+
+    /// The cooldown in seconds of each contract which has one
+    claim_cooldowns: BTreeMap<Uuid, u64>,
+}
+
+impl From<&AppState> for Capabilities {
+    fn from(state: &AppState) -> Self {
+        Self {
+            encodings: Encoding::SUPPORTED,
+            auth: state.tokens.is_some() || state.peer.is_some(),
+            max_body: MAX_BODY,
+            max_keeps: state.keeps.max_keeps(),
+            max_keeps_per_owner: state.keeps.max_keeps_per_owner(),
+            keep_ttl: state.keeps.max_age().map(|ttl| ttl.as_secs()),
+            soft_delete_retention: state.keeps.max_retention().map(|r| r.as_secs()),
+            hide_expired: state.hide_expired,
+            require_supported: state.require_supported,
+            claim_policy: state.claim_policy.as_str(),
+            claim_cooldowns: state
+                .contracts
+                .get()
+                .iter()
+                .filter_map(|c| Some((c.uuid, c.claim_cooldown?)))
+                .collect(),
+        }
+    }
+}
+
+/// Recent activity on the server.
+#[derive(Debug, Serialize)]
+struct Stats {
+    /// The seconds over which claims are counted
+    window: u64,
+
+    /// The keeps claimed under each contract in the window
+    claims: BTreeMap<Uuid, usize>,
+}
+
+impl From<&AppState> for Stats {
+    fn from(state: &AppState) -> Self {
+        Self {
+            window: rates::WINDOW.as_secs(),
+            claims: state.claims.counts(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HealthQuery {
+    deep: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EvictQuery {
+    backend: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Evicted {
+    evicted: usize,
+}
+
+/// Details for a new keep, which may be given in the body of a claim.
+#[derive(Debug, Default, Deserialize)]
+struct NewKeep {
+    owner: Option<String>,
+
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+impl NewKeep {
+    /// Decodes the details; an empty body leaves them all unset.
+    fn decode(kind: Option<String>, body: &[u8], depth: usize) -> Result<Self, StatusCode> {
+        if body.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let kind = Encoding::of(kind).map_err(|_| StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+        kind.decode(body, depth).ok_or(StatusCode::BAD_REQUEST)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportQuery {
+    conflict: Option<Conflict>,
+}
+
+#[derive(Debug, Serialize)]
+struct Imported {
+    imported: usize,
+
+    /// The lines of a streamed import which couldn't be imported
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<ndjson::LineError>,
+}
+
+/// How a keep's copy of its contract differs from the contract now offered.
+#[derive(Debug, Serialize)]
+struct Drift {
+    /// The contract is no longer offered at all, or its UUID now names a
+    /// different contract
+    gone: bool,
+
+    /// The fields whose values have changed since the keep was claimed
+    fields: Vec<&'static str>,
+}
+
+impl Drift {
+    fn new(claimed: &Contract, offered: Option<&Contract>) -> Self {
+        let offered = match offered {
+            Some(offered) if claimed.core_eq(offered) => offered,
+            _ => {
+                return Self {
+                    gone: true,
+                    fields: Vec::new(),
+                }
+            }
+        };
+
+        let mut fields = Vec::new();
+        if claimed.not_before != offered.not_before {
+            fields.push("not_before");
+        }
+        if claimed.not_after != offered.not_after {
+            fields.push("not_after");
+        }
+        if claimed.cost != offered.cost {
+            fields.push("cost");
+        }
+        if claimed.attestation_policy != offered.attestation_policy {
+            fields.push("attestation_policy");
+        }
+        if claimed.region != offered.region {
+            fields.push("region");
+        }
+        if claimed.params != offered.params {
+            fields.push("params");
+        }
+        if claimed.claim_cooldown != offered.claim_cooldown {
+            fields.push("claim_cooldown");
+        }
+        if claimed.enabled != offered.enabled {
+            fields.push("enabled");
+        }
+
+        Self {
+            gone: false,
+            fields,
+        }
+    }
+}
+
+/// Rejects a request which accepts none of the supported types.
+#[derive(Debug)]
+struct NotAcceptable;
+
+impl warp::reject::Reject for NotAcceptable {}
+
+/// Rejects a request body which is of none of the supported types.
+#[derive(Debug)]
+struct UnsupportedBody;
+
+impl warp::reject::Reject for UnsupportedBody {}
+
+/// Rejects a request body which is larger than `MAX_BODY`.
+#[derive(Debug)]
+struct Oversized;
+
+impl warp::reject::Reject for Oversized {}
+
+/// Rejects a request whose path and query are longer than allowed.
+#[derive(Debug)]
+struct UriTooLong;
+
+impl warp::reject::Reject for UriTooLong {}
+
+/// The negotiated encoding of a response body.
+#[derive(Copy, Clone, Debug)]
+enum Encoding {
+    Cbor,
+    Json { pretty: bool },
+}
+
+impl Encoding {
+    /// The media types that responses can be encoded as.
+    const SUPPORTED: &'static [&'static str] = &["application/cbor", "application/json"];
+
+    /// Picks the first supported type in the `Accept` header.
+    ///
+    /// Requests without a preference get CBOR.
+    fn negotiate(accept: Option<String>, pretty: bool) -> Result<Self, NotAcceptable> {
+        let accept = match accept {
+            Some(accept) if !accept.trim().is_empty() => accept,
+            _ => return Ok(Self::Cbor),
+        };
+
+        for media in accept.split(',') {
+            match media.split(';').next().unwrap().trim() {
+                "application/cbor" | "application/*" | "*/*" => return Ok(Self::Cbor),
+                "application/json" => return Ok(Self::Json { pretty }),
+                _ => continue,
+            }
+        }
+
+        Err(NotAcceptable)
+    }
+
+    /// Identifies the encoding of a request body from its `Content-Type`.
+    fn of(content_type: Option<String>) -> Result<Self, UnsupportedBody> {
+        let content_type = content_type.ok_or(UnsupportedBody)?;
+        match content_type.split(';').next().unwrap().trim() {
+            "application/cbor" => Ok(Self::Cbor),
+            "application/json" => Ok(Self::Json { pretty: false }),
+            _ => Err(UnsupportedBody),
+        }
+    }
+
+    /// Decodes a request body, refusing any which nest deeper than `depth`.
+    fn decode<T: DeserializeOwned>(self, body: &[u8], depth: usize) -> Option<T> {
+        match self {
+            Self::Cbor if depth::cbor(body, depth) => ciborium::de::from_reader(body).ok(),
+            Self::Json { .. } if depth::json(body, depth) => serde_json::from_slice(body).ok(),
+            _ => None,
+        }
+    }
+
+    /// The media type of the encoding.
+    fn kind(self) -> &'static str {
+        match self {
+            Self::Cbor => "application/cbor",
+            Self::Json { .. } => "application/json",
+        }
+    }
+
+    /// Encodes the item, logging why if it can't be.
+    fn encode<T: Serialize>(self, item: &T) -> Option<Vec<u8>> {
+        let encoded = match self {
+            Self::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::ser::into_writer(item, &mut buffer)
+                    .map(|()| buffer)
+                    .map_err(|e| format!("{:?}", e))
+            }
+            Self::Json { pretty: false } => serde_json::to_vec(item).map_err(|e| e.to_string()),
+            Self::Json { pretty: true } => {
+                serde_json::to_vec_pretty(item).map_err(|e| e.to_string())
+            }
+        };
+
+        encoded
+            .map_err(|e| tracing::error!("failed to encode response: {}", e))
+            .ok()
+    }
+
+    fn reply<T: Serialize>(self, status: StatusCode, item: &T) -> Response<Vec<u8>> {
+        match self.encode(item) {
+            None => unencodable(),
+            Some(body) => Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, self.kind())
+                .body(body)
+                .unwrap(),
+        }
+    }
+
+    /// Like `reply()`, but serializes the item while the body is being sent.
+    ///
+    /// This avoids holding a second, fully encoded copy of large items.
+    ///
+    /// Failures other than the client going away are counted in `requests`.
+    fn stream<T>(self, status: StatusCode, item: T, requests: Arc<Requests>) -> Response<Body>
+    where
+        T: Serialize + Send + 'static,
+    {
+        let (sender, body) = Body::channel();
+        let handle = tokio::runtime::Handle::current();
+        let kind = self.kind();
+
+        tokio::task::spawn_blocking(move || {
+            let mut writer = BodyWriter {
+                buffer: Vec::with_capacity(BodyWriter::CHUNK),
+                sender,
+                handle,
+            };
+
+            // Writing fails with an I/O error if the client has gone away.
+            let json = |e: serde_json::Error| match e.is_io() {
+                true => None,
+                false => Some(e.to_string()),
+            };
+            let encoded = match self {
+                Self::Cbor => ciborium::ser::into_writer(&item, &mut writer).map_err(|e| match e {
+                    ciborium::ser::Error::Io(..) => None,
+                    e => Some(format!("{:?}", e)),
+                }),
+                Self::Json { pretty: false } => {
+                    serde_json::to_writer(&mut writer, &item).map_err(json)
+                }
+                Self::Json { pretty: true } => {
+                    serde_json::to_writer_pretty(&mut writer, &item).map_err(json)
+                }
+            };
+
+            match encoded {
+                Ok(()) => {
+                    let _ = writer.flush();
+                }
+                Err(None) => (),
+                Err(Some(e)) => {
+                    tracing::error!("failed to encode response: {}", e);
+                    requests.failed_encode();
+                }
+            }
+        });
+
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, kind)
+            .body(body)
+            .unwrap()
+    }
+}
+
+/// Sends everything written to it as chunks of a response body.
+struct BodyWriter {
+    buffer: Vec<u8>,
+    sender: warp::hyper::body::Sender,
+    handle: tokio::runtime::Handle,
+}
+
+impl BodyWriter {
+    const CHUNK: usize = 64 * 1024;
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= Self::CHUNK {
+            self.flush()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(Self::CHUNK));
+        self.handle
+            .block_on(self.sender.send_data(chunk.into()))
+            .map_err(|_| std::io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+fn error(code: StatusCode) -> Response<Vec<u8>> {
+    Response::builder().status(code).body(Vec::new()).unwrap()
+}
+
+/// Answers a bare 500 for a response whose body couldn't be encoded.
+fn unencodable() -> Response<Vec<u8>> {
+    let mut response = error(StatusCode::INTERNAL_SERVER_ERROR);
+    response.extensions_mut().insert(Unencodable);
+    response
+}
+
+/// Creates a keep from the contract.
+fn claim(app: &AppState, contract: &Contract, new: NewKeep, enc: Encoding) -> Response<Vec<u8>> {
+    if !contract.enabled {
+        return error(StatusCode::CONFLICT);
+    }
+
+    if !contract.is_valid_at(Utc::now()) {
+        return error(StatusCode::FORBIDDEN);
+    }
+
+    if app.require_supported && !app.probe.supports(&contract.backend) {
+        return error(StatusCode::CONFLICT);
+    }
+
+    if let Some(cooldown) = contract.claim_cooldown.map(Duration::from_secs) {
+        if let Err(left) = app.claims.begin(&contract.uuid, cooldown) {
+            // Round up, so that a client waiting as asked is not refused.
+            let retry_after = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+            let mut response = error(StatusCode::TOO_MANY_REQUESTS);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, retry_after.into());
+            return response;
+        }
+    }
+
+    let created = app.keeps.create_with(contract, |keep| {
+        keep.owner = new.owner;
+        keep.labels = new.labels;
+    });
+
+    if created.is_err() {
+        app.claims.abandon(&contract.uuid);
+    }
+
+    match created {
+        Err(Full::Store) => error(StatusCode::CONFLICT),
+        Err(Full::Owner) => error(StatusCode::TOO_MANY_REQUESTS),
+        Ok(keep) => {
+            app.claims.record(&contract.uuid);
+
+            let path: HeaderValue = Keep::path(&keep.uuid).parse().unwrap();
+            let mut response = enc.reply(StatusCode::CREATED, &keep);
+            response.headers_mut().insert(LOCATION, path.clone());
+            response.headers_mut().insert(CONTENT_LOCATION, path);
+            tag(&mut response, &app.keeps, &keep.uuid);
+            response
+        }
+    }
+}
+
+/// Answers requests which no route accepted.
+///
+/// Doing this here, rather than leaving it to warp, means that error
+/// responses pass through the same wrapping filters as any other.
+async fn recover(rejection: Rejection) -> Result<Response<Vec<u8>>, Infallible> {
+    use warp::reject::*;
+
+    if let Some(code) = deadline::expired(&rejection) {
+        return Ok(error(code));
+    }
+
+    if let Some(code) = tokens::denied(&rejection) {
+        return Ok(error(code));
+    }
+
+    if let Some(ShuttingDown(retry_after)) = rejection.find() {
+        return Ok(Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(CONTENT_TYPE, "application/json")
+            .header(RETRY_AFTER, retry_after.to_string())
+            .body(br#"{"detail":"server shutting down"}"#.to_vec())
+            .unwrap());
+    }
+
+    if let Some(Loading(retry_after)) = rejection.find() {
+        return Ok(Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(CONTENT_TYPE, "application/json")
+            .header(RETRY_AFTER, retry_after.to_string())
+            .body(br#"{"detail":"contracts loading"}"#.to_vec())
+            .unwrap());
+    }
+
+    if rejection.find::<NotAcceptable>().is_some() {
+        let mut body = Encoding::SUPPORTED.join("\n");
+        body.push('\n');
+
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_ACCEPTABLE)
+            .header(CONTENT_TYPE, "text/plain")
+            .body(body.into_bytes())
+            .unwrap());
+    }
+
+    Ok(error(if rejection.is_not_found() {
+        StatusCode::NOT_FOUND
+    } else if rejection.find::<MethodNotAllowed>().is_some() {
+        StatusCode::METHOD_NOT_ALLOWED
+    } else if rejection.find::<PayloadTooLarge>().is_some()
+        || rejection.find::<Oversized>().is_some()
+    {
+        StatusCode::PAYLOAD_TOO_LARGE
+    } else if rejection.find::<UriTooLong>().is_some() {
+        StatusCode::URI_TOO_LONG
+    } else if rejection.find::<LengthRequired>().is_some() {
+        StatusCode::LENGTH_REQUIRED
+    } else if rejection.find::<UnsupportedMediaType>().is_some()
+        || rejection.find::<UnsupportedBody>().is_some()
+    {
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    } else if rejection.find::<InvalidQuery>().is_some()
+        || rejection.find::<InvalidHeader>().is_some()
+        || rejection.find::<MissingHeader>().is_some()
+    {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }))
+}
+
+/// Adds the keep's entity tag to the response.
+fn tag(response: &mut Response<Vec<u8>>, keeps: &KeepStore, uuid: &Uuid) {
+    if let Some(etag) = keeps.etag(uuid) {
+        response.headers_mut().insert(ETAG, etag.parse().unwrap());
+    }
+}
+
+/// Indicates whether an `If-Match` header accepts the entity tag.
+fn matches(if_match: &str, etag: &str) -> bool {
+    if_match
+        .split(',')
+        .map(str::trim)
+        .any(|t| t == "*" || t == etag)
+}
+
+/// Distinguishes revoked keeps from keeps that never existed.
+fn missing(keeps: &KeepStore, uuid: &Uuid) -> StatusCode {
+    if keeps.is_revoked(uuid) {
+        StatusCode::GONE
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Builds the filter answering every API request.
+pub fn routes(
+    state: AppState,
+) -> impl Filter<Extract = impl warp::Reply, Error = Infallible> + Clone {
+    let mut headers = HeaderMap::new();
+    if state.server_header {
+        let server = concat!("contractmgr/", env!("CARGO_PKG_VERSION"));
+        headers.insert(SERVER, HeaderValue::from_static(server));
+    }
+
+    let pretty = state.pretty;
+    let encoding = warp::header::optional("accept").and_then(move |a| async move {
+        Encoding::negotiate(a, pretty).map_err(warp::reject::custom)
+    });
+    let content_type = warp::header::optional("content-type")
+        .and_then(|t| async move { Encoding::of(t).map_err(warp::reject::custom) });
+    let tokens = state.tokens.clone();
+    let peer = state.peer;
+    let connections = state.connections.clone();
+    let accepting = connections::accepting(connections.clone(), state.retry_after);
+    let loaded = contracts::loaded(state.contracts.clone(), state.retry_after);
+    let requests = state.requests.clone();
+
+    // Overlong requests are refused before any route looks at them.
+    let max_uri_length = state.max_uri_length;
+    let short_uri = warp::path::full()
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and_then(move |path: FullPath, query: String| async move {
+            match path.as_str().len() + query.len() > max_uri_length {
+                true => Err(warp::reject::custom(UriTooLong)),
+                false => Ok(()),
+            }
+        })
+        .untuple_one();
+
+    let state = warp::any().map(move || state.clone());
+
+    // Client is discovering what the server supports.
+    let get_capabilities = warp::path!("capabilities")
+        .and(warp::filters::method::get())
+        .and(encoding)
+        .and(state.clone())
+        .map(|enc: Encoding, app: AppState| {
+            guard(|| enc.reply(StatusCode::OK, &Capabilities::from(&app)))
+        });
+
+    // Client is asking what each backend is and whether it can be run here.
+    let get_backends = warp::path!("backends")
+        .and(warp::filters::method::get())
+        .and(encoding)
+        .and(state.clone())
+        .map(|enc: Encoding, app: AppState| {
+            guard(|| {
+                let backends: Vec<_> = Backend::all()
+                    .iter()
+                    .map(|backend| BackendInfo::new(backend, &*app.probe))
+                    .collect();
+                enc.reply(StatusCode::OK, &backends)
+            })
+        });
+
+    // Client is checking whether the server is alive.
+    //
+    // A deep check also makes sure that keeps can still be changed and saved.
+    let get_healthz = warp::path!("healthz")
+        .and(warp::filters::method::get())
+        .and(warp::query::<HealthQuery>())
+        .and(state.clone())
+        .map(|query: HealthQuery, app: AppState| {
+            guard(|| {
+                if !matches!(query.deep.as_deref(), Some("1") | Some("true")) {
+                    return StatusCode::OK;
+                }
+
+                if !app.keeps.is_writable() {
+                    return StatusCode::SERVICE_UNAVAILABLE;
+                }
+
+                if let Some(ref file) = app.state_file {
+                    if let Err(e) = file.check() {
+                        tracing::warn!("state file is not writable: {}", e);
+                        return StatusCode::SERVICE_UNAVAILABLE;
+                    }
+                }
+
+                StatusCode::OK
+            })
+        });
+
+    // Client is checking whether the server is ready for traffic, which it
+    // is once the contracts are loaded and until it starts draining.
+    let get_readyz = warp::path!("readyz")
+        .and(warp::filters::method::get())
+        .and(state.clone())
+        .map(|app: AppState| {
+            guard(
+                || match app.contracts.is_loaded() && !app.connections.is_draining() {
+                    true => StatusCode::OK,
+                    false => StatusCode::SERVICE_UNAVAILABLE,
+                },
+            )
+        });
+
+    // Client is watching the requests in flight, such as while draining.
+    let get_status_connections = warp::path!("status" / "connections")
+        .and(warp::filters::method::get())
+        .and(encoding)
+        .and(state.clone())
+        .map(|enc: Encoding, app: AppState| {
+            guard(|| enc.reply(StatusCode::OK, &app.connections.status()))
+        });
+
+    // Client is looking for which contracts are in demand.
+    let get_stats = warp::path!("stats")
+        .and(warp::filters::method::get())
+        .and(require(tokens.clone(), peer, Role::Reader))
+        .and(encoding)
+        .and(state.clone())
+        .map(|enc: Encoding, app: AppState| {
+            guard(|| enc.reply(StatusCode::OK, &Stats::from(&app)))
+        });
+
+    // Client is requesting details of all contracts.
+    let get_contracts = warp::path!("contracts")
+        .and(warp::filters::method::get())
+        .and(require(tokens.clone(), peer, Role::Reader))
+        .and(warp::query::<ContractsQuery>())
+        .and(warp::header::optional("if-none-match"))
+        .and(encoding)
+        .and(state.clone())
+        .map(
+            |query: ContractsQuery, if_none_match: Option<String>, enc: Encoding, app: AppState| {
+                guard(|| {
+                    let now = Utc::now();
+                    let offered = app.contracts.get();
+                    let supported = query.supported.unwrap_or(false);
+                    let disabled =
+                        matches!(query.include_disabled.as_deref(), Some("1") | Some("true"));
+                    let listed = || -> Vec<Contract> {
+                        offered
+                            .iter()
+                            .filter(|c| disabled || c.enabled)
+                            .filter(|c| !app.hide_expired || !c.is_expired_at(now))
+                            .filter(|c| !supported || app.probe.supports(&c.backend))
+                            .filter(|c| query.region.is_none() || c.region == query.region)
+                            .cloned()
+                            .collect()
+                    };
+
+                    // Most clients list every contract, so that listing is kept.
+                    if !query.is_plain() {
+                        return query.reply(&listed(), enc).into_response();
+                    }
+
+                    // Clients polling for changes can skip unchanged listings.
+                    let listing = app
+                        .listings
+                        .get(enc.kind(), &offered, || enc.encode(&listed()));
+                    let (body, etag) = match listing {
+                        Some(listing) => listing,
+                        None => return unencodable().into_response(),
+                    };
+                    if let Some(ref if_none_match) = if_none_match {
+                        if matches(if_none_match, &etag) {
+                            return Response::builder()
+                                .status(StatusCode::NOT_MODIFIED)
+                                .header(ETAG, etag)
+                                .body(Bytes::new())
+                                .unwrap()
+                                .into_response();
+                        }
+                    }
+
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header(CONTENT_TYPE, enc.kind())
+                        .header(ETAG, etag)
+                        .body(body)
+                        .unwrap()
+                        .into_response()
+                })
+            },
+        );
+
+    // Client is asking which contracts it could claim right now.
+    let get_contracts_claimable = warp::path!("contracts" / "claimable")
+        .and(warp::filters::method::get())
+        .and(require(tokens.clone(), peer, Role::Reader))
+        .and(encoding)
+        .and(state.clone())
+        .map(|enc: Encoding, app: AppState| {
+            guard(|| {
+                let full = match app.keeps.max_keeps() {
+                    Some(max) => app.keeps.list().len() >= max,
+                    None => false,
+                };
+
+                let now = Utc::now();
+                let contracts: Vec<Contract> = app
+                    .contracts
+                    .get()
+                    .iter()
+                    .filter(|_| !full)
+                    .filter(|c| c.enabled && c.is_valid_at(now))
+                    .filter(|c| app.probe.supports(&c.backend))
+                    .cloned()
+                    .collect();
+                enc.reply(StatusCode::OK, &contracts)
+            })
+        });
+
+    // Client is requesting details of a single contract.
+    let get_contracts_uuid = warp::path!("contracts" / Uuid)
+        .and(warp::filters::method::get())
+        .and(require(tokens.clone(), peer, Role::Reader))
+        .and(encoding)
+        .and(state.clone())
+        .map(|cuuid, enc: Encoding, app: AppState| {
+            guard(|| match app.contracts.get().find(&cuuid) {
+                None => error(StatusCode::NOT_FOUND),
+                Some(contract) => enc.reply(StatusCode::OK, contract),
+            })
+        });
+
+    // A claim needn't have a body, so it needn't declare its length either.
+    let new_keep = warp::header::optional::<u64>("content-length")
+        .and_then(|length: Option<u64>| async move {
+            match length {
+                Some(length) if length > MAX_BODY => Err(warp::reject::custom(Oversized)),
+                _ => Ok(()),
+            }
+        })
+        .untuple_one()
+        .and(warp::header::optional("content-type"))
+        .and(deadline::bytes());
+
+    // Client is attempting to claim a contract, perhaps with details for the
+    // new keep.
+    let post_contracts_uuid = warp::path!("contracts" / Uuid)
+        .and(warp::filters::method::post())
+        .and(require(tokens.clone(), peer, Role::Writer))
+        .and(encoding)
+        .and(state.clone())
+        .and(new_keep)
+        .map(
+            |cuuid, enc: Encoding, app: AppState, kind: Option<String>, body: Bytes| {
+                guard(|| {
+                    let new = match NewKeep::decode(kind, &body, app.max_depth) {
+                        Ok(new) => new,
+                        Err(code) => {
+                            if code == StatusCode::BAD_REQUEST {
+                                app.requests.failed_decode();
+                            }
+                            return error(code);
+                        }
+                    };
+
+                    match app.contracts.get().find(&cuuid) {
+                        None => error(StatusCode::NOT_FOUND),
+                        Some(contract) => claim(&app, contract, new, enc),
+                    }
+                })
+            },
+        );
+
+    // Client is attempting to claim any contract of a backend.
+    let post_backends_name = warp::path!("backends" / String)
+        .and(warp::filters::method::post())
+        .and(require(tokens.clone(), peer, Role::Writer))
+        .and(encoding)
+        .and(state.clone())
+        .map(|name: String, enc: Encoding, app: AppState| {
+            guard(|| {
+                let backend = match name.parse::<Backend>() {
+                    Ok(backend) => backend,
+                    Err(..) => return error(StatusCode::NOT_FOUND),
+                };
+
+                // Prefer a contract which can be claimed right now.
+                let now = Utc::now();
+                let contracts = app.contracts.get();
+                let mut offered = contracts.backend(&backend);
+                let valid = offered.clone().filter(|c| c.enabled && c.is_valid_at(now));
+                let chosen = app.claim_policy.choose(valid, &app.keeps);
+                match chosen.or_else(|| offered.next()) {
+                    None => error(StatusCode::NOT_FOUND),
+                    Some(contract) => claim(&app, contract, NewKeep::default(), enc),
+                }
+            })
+        });
+
+    // Client is requesting details for all keeps.
+    let get_keeps = warp::path!("keeps")
+        .and(warp::filters::method::get())
+        .and(require(tokens.clone(), peer, Role::Reader))
+        .and(encoding)
+        .and(state.clone())
+        .map(|enc: Encoding, app: AppState| {
+            guard(|| enc.stream(StatusCode::OK, app.keeps.list(), app.requests.clone()))
+        });
+
+    // Client is evicting every keep of a backend.
+    let delete_keeps = warp::path!("keeps")
+        .and(warp::filters::method::delete())
+        .and(require(tokens.clone(), peer, Role::Admin))
+        .and(warp::query::<EvictQuery>())
+        .and(encoding)
+        .and(state.clone())
+        .map(|query: EvictQuery, enc: Encoding, app: AppState| {
+            guard(|| {
+                // Evicting every keep must be asked for by name.
+                let backend = match query.backend.map(|name| name.parse::<Backend>()) {
+                    Some(Ok(backend)) => backend,
+                    _ => return error(StatusCode::BAD_REQUEST),
+                };
+
+                let evicted = app.keeps.delete_all(|k| k.contract.backend == backend);
+                enc.reply(StatusCode::OK, &Evicted { evicted })
+            })
+        });
+
+    // Client is requesting details of a single keep.
+    let get_keeps_uuid = warp::path!("keeps" / Uuid)
+        .and(warp::filters::method::get())
+        .and(require(tokens.clone(), peer, Role::Reader))
+        .and(encoding)
+        .and(state.clone())
+        .map(|kuuid, enc: Encoding, app: AppState| {
+            guard(|| match app.keeps.get(&kuuid) {
+                None => error(missing(&app.keeps, &kuuid)),
+                Some(keep) => {
+                    let mut response = enc.reply(StatusCode::OK, &keep);
+                    tag(&mut response, &app.keeps, &kuuid);
+                    response
+                }
+            })
+        });
+
+    // Client is checking whether a keep's contract has changed since it was
+    // claimed.
+    let get_keeps_uuid_drift = warp::path!("keeps" / Uuid / "drift")
+        .and(warp::filters::method::get())
+        .and(require(tokens.clone(), peer, Role::Reader))
+        .and(encoding)
+        .and(state.clone())
+        .map(|kuuid, enc: Encoding, app: AppState| {
+            guard(|| match app.keeps.get(&kuuid) {
+                None => error(missing(&app.keeps, &kuuid)),
+                Some(keep) => {
+                    let contracts = app.contracts.get();
+                    let offered = contracts.find(&keep.contract.uuid);
+                    enc.reply(StatusCode::OK, &Drift::new(&keep.contract, offered))
+                }
+            })
+        });
+
+    // Client is requesting destruction of a single keep.
+    let delete_keeps_uuid = warp::path!("keeps" / Uuid)
+        .and(warp::filters::method::delete())
+        .and(require(tokens.clone(), peer, Role::Writer))
+        .and(warp::header::optional("if-match"))
+        .and(state.clone())
+        .map(|kuuid, if_match: Option<String>, app: AppState| {
+            guard(|| {
+                let deleted = match if_match {
+                    None => Ok(app.keeps.delete(&kuuid)),
+                    Some(if_match) => app.keeps.delete_if(&kuuid, |etag| matches(&if_match, etag)),
+                };
+
+                match deleted {
+                    Ok(Some(..)) => StatusCode::OK,
+                    Ok(None) => missing(&app.keeps, &kuuid),
+                    Err(..) => StatusCode::PRECONDITION_FAILED,
+                }
+            })
+        });
+
+    // Client is undoing the deletion of a single keep.
+    let post_keeps_uuid_restore = warp::path!("keeps" / Uuid / "restore")
+        .and(warp::filters::method::post())
+        .and(require(tokens.clone(), peer, Role::Writer))
+        .and(encoding)
+        .and(state.clone())
+        .map(|kuuid, enc: Encoding, app: AppState| {
+            guard(|| match app.keeps.restore(&kuuid) {
+                Err(Full::Store) => error(StatusCode::CONFLICT),
+                Err(Full::Owner) => error(StatusCode::TOO_MANY_REQUESTS),
+                Ok(None) => error(missing(&app.keeps, &kuuid)),
+                Ok(Some(keep)) => {
+                    let mut response = enc.reply(StatusCode::OK, &keep);
+                    tag(&mut response, &app.keeps, &kuuid);
+                    response
+                }
+            })
+        });
+
+    // Client is forcibly revoking a single keep.
+    let post_keeps_uuid_revoke = warp::path!("keeps" / Uuid / "revoke")
+        .and(warp::filters::method::post())
+        .and(require(tokens.clone(), peer, Role::Admin))
+        .and(state.clone())
+        .map(|kuuid, app: AppState| {
+            guard(|| match app.keeps.revoke(&kuuid) {
+                Some(..) => StatusCode::OK,
+                None => missing(&app.keeps, &kuuid),
+            })
+        });
+
+    // Client is requesting a backup of all keeps.
+    let get_keeps_export = warp::path!("keeps:export")
+        .and(warp::filters::method::get())
+        .and(require(tokens.clone(), peer, Role::Admin))
+        .and(encoding)
+        .and(state.clone())
+        .map(|enc: Encoding, app: AppState| {
+            guard(|| enc.stream(StatusCode::OK, app.keeps.export(), app.requests.clone()))
+        });
+
+    // Client is restoring keeps from a backup streamed one keep per line.
+    let post_keeps_import_ndjson = warp::path!("keeps:import")
+        .and(warp::filters::method::post())
+        .and(require(tokens.clone(), peer, Role::Admin))
+        .and(warp::query::<ImportQuery>())
+        .and(ndjson::body())
+        .and(deadline::left())
+        .and(warp::body::stream())
+        .and(encoding)
+        .and(state.clone())
+        .and_then(
+            |query: ImportQuery, left, body, enc: Encoding, app: AppState| async move {
+                let conflict = query.conflict.unwrap_or(Conflict::Fail);
+                let max_line = MAX_BODY as usize;
+                let import = ndjson::import(body, &app.keeps, conflict, max_line, app.max_depth);
+                let (imported, errors) = match deadline::within(left, import).await {
+                    Ok(done) => done,
+                    Err(code) => return Ok(error(code)),
+                };
+                for _ in errors.iter().filter(|e| e.undecodable) {
+                    app.requests.failed_decode();
+                }
+                let reply = enc.reply(StatusCode::OK, &Imported { imported, errors });
+                Ok::<_, Infallible>(reply)
+            },
+        );
+
+    // Client is restoring keeps from a backup.
+    let post_keeps_import = warp::path!("keeps:import")
+        .and(warp::filters::method::post())
+        .and(require(tokens, peer, Role::Admin))
+        .and(warp::query::<ImportQuery>())
+        .and(content_type)
+        .and(warp::body::content_length_limit(MAX_BODY))
+        .and(deadline::bytes())
+        .and(encoding)
+        .and(state)
+        .map(
+            |query: ImportQuery, kind: Encoding, body: Bytes, enc: Encoding, app: AppState| {
+                guard(|| {
+                    let export: Export = match kind.decode(&body, app.max_depth) {
+                        Some(export) => export,
+                        None => {
+                            app.requests.failed_decode();
+                            return error(StatusCode::BAD_REQUEST);
+                        }
+                    };
+
+                    if export.version != Export::VERSION {
+                        return error(StatusCode::BAD_REQUEST);
+                    }
+
+                    let conflict = query.conflict.unwrap_or(Conflict::Fail);
+                    match app.keeps.import(export.keeps, conflict) {
+                        Err(..) => error(StatusCode::CONFLICT),
+                        Ok(imported) => {
+                            let errors = Vec::new();
+                            enc.reply(StatusCode::OK, &Imported { imported, errors })
+                        }
+                    }
+                })
+            },
+        );
+
+    let api = get_capabilities
+        .or(get_backends)
+        .or(get_stats)
+        .or(get_contracts)
+        .or(get_contracts_claimable)
+        .or(get_contracts_uuid)
+        .or(post_contracts_uuid)
+        .or(post_backends_name)
+        .or(get_keeps)
+        .or(delete_keeps)
+        .or(get_keeps_uuid)
+        .or(get_keeps_uuid_drift)
+        .or(delete_keeps_uuid)
+        .or(post_keeps_uuid_restore)
+        .or(post_keeps_uuid_revoke)
+        .or(get_keeps_export)
+        .or(post_keeps_import_ndjson)
+        .or(post_keeps_import);
+
+    // Until the contracts are loaded, only liveness can be checked. Once
+    // draining, only the drain itself can still be watched.
+    let api = get_healthz.or(loaded.and(api));
+    let api = get_status_connections.or(get_readyz).or(accepting.and(api));
+    let handled = deadline::check().and(short_uri).and(api).recover(recover);
+
+    connections::track(connections)
+        .and(access::start())
+        .and(handled)
+        .map(move |active, access: access::Access, reply| {
+            drop(active);
+            let response = requests.record(reply);
+            access.log(&response);
+            response
+        })
+        .with(warp::reply::with::headers(headers))
+}
+
+/// Serves the API on the incoming connections.
+///
+/// Once the state's connections are drained, no more connections are
+/// accepted and this returns when the requests in flight are done.
+pub async fn serve<I>(incoming: I, state: AppState) -> tokio::io::Result<()>
+where
+    I: futures_core::stream::TryStream + Send,
+    I::Ok: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static + Unpin,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let connections = state.connections.clone();
+    let draining = async move { connections.draining().await };
+
+    if state.log_bodies {
+        let logged = log_bodies(routes(state));
+        warp::serve(logged)
+            .serve_incoming_with_graceful_shutdown(incoming, draining)
+            .await;
+    } else {
+        warp::serve(routes(state))
+            .serve_incoming_with_graceful_shutdown(incoming, draining)
+            .await;
+    }
+
+    Ok(())
+}
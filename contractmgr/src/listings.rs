@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::Offered;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use warp::hyper::body::Bytes;
+
+/// How long a listing is reused, so that listings which depend on the time,
+/// such as those hiding expired contracts, are never far behind.
+pub const TTL: Duration = Duration::from_secs(1);
+
+/// An encoded listing and the contracts it was made from.
+#[derive(Debug)]
+struct Listing {
+    offered: Arc<Offered>,
+    made: Instant,
+    body: Bytes,
+    etag: String,
+}
+
+impl Listing {
+    fn new(offered: Arc<Offered>, body: Vec<u8>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+
+        Self {
+            offered,
+            made: Instant::now(),
+            body: body.into(),
+            etag: format!("\"{:x}\"", hasher.finish()),
+        }
+    }
+}
+
+/// Encoded listings of all the contracts, one for each media type.
+///
+/// A listing is made again once it is older than the TTL or the contracts
+/// have been reloaded, which is noticed by the list having been replaced.
+#[derive(Debug, Default)]
+pub struct Listings(Mutex<HashMap<&'static str, Listing>>);
+
+impl Listings {
+    /// Gets the listing of the contracts as `kind`, along with its entity
+    /// tag, calling `make` to encode them if there is no fresh listing.
+    ///
+    /// The tag only changes when the listing does. Nothing is kept if `make`
+    /// fails.
+    pub fn get<F>(
+        &self,
+        kind: &'static str,
+        offered: &Arc<Offered>,
+        make: F,
+    ) -> Option<(Bytes, String)>
+    where
+        F: FnOnce() -> Option<Vec<u8>>,
+    {
+        if let Some(listing) = self.0.lock().unwrap().get(kind) {
+            if Arc::ptr_eq(&listing.offered, offered) && listing.made.elapsed() < TTL {
+                return Some((listing.body.clone(), listing.etag.clone()));
+            }
+        }
+
+        // Encoding happens outside the lock, so a slow one holds up nobody.
+        let listing = Listing::new(offered.clone(), make()?);
+        let got = (listing.body.clone(), listing.etag.clone());
+        self.0.lock().unwrap().insert(kind, listing);
+        Some(got)
+    }
+}
@@ -2,20 +2,23 @@
 
 #![deny(clippy::all)]
 
-use franca::{Backend, Contract, Keep};
+mod selftest;
 
-use std::collections::HashMap;
-use std::sync::RwLock;
+use contractmgr::{
+    push, serve, serve_peers, AppState, ClaimPolicy, Contracts, Peers, StateFile, Tokens,
+};
+use franca::{Host, KeepStore, Scheme};
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use once_cell::sync::Lazy;
 use serde::Serialize;
 use structopt::StructOpt;
 use tokio::net::{TcpListener, UnixListener};
 use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
-use uuid::Uuid;
-use warp::http::header::{CONTENT_TYPE, LOCATION};
-use warp::http::{Response, StatusCode};
-use warp::Filter;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::prelude::*;
 
 #[derive(Debug)]
 enum Listener {
@@ -23,6 +26,21 @@ enum Listener {
     Tcp(std::net::TcpListener),
 }
 
+impl std::fmt::Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Error;
+
+        match self {
+            Listener::Unix(socket) => match socket.local_addr().map_err(|_| Error)?.as_pathname() {
+                Some(path) => write!(f, "{}", path.display()),
+                None => write!(f, "(unnamed)"),
+            },
+
+            Listener::Tcp(socket) => write!(f, "{}", socket.local_addr().map_err(|_| Error)?),
+        }
+    }
+}
+
 impl std::str::FromStr for Listener {
     type Err = std::io::Error;
 
@@ -48,152 +66,511 @@ impl std::str::FromStr for Listener {
     }
 }
 
+/// How log lines are written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum LogFormat {
+    /// Human-readable text.
+    Text,
+
+    /// One JSON object per line, for log aggregators.
+    Json,
+}
+
+impl LogFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        }
+    }
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format: {}", s)),
+        }
+    }
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "contractmgr", about = "Manages contracts for keepmgr.")]
 struct Options {
     /// The listening socket address or fd
-    listen: Listener,
+    #[structopt(required_unless = "selftest")]
+    listen: Option<Listener>,
+
+    /// Run a CRUD cycle against a private instance and exit
+    #[structopt(long)]
+    selftest: bool,
+
+    /// The maximum number of keeps
+    #[structopt(long)]
+    max_keeps: Option<usize>,
+
+    /// The maximum number of keeps each owner may hold
+    #[structopt(long)]
+    max_keeps_per_owner: Option<usize>,
+
+    /// The number of seconds after which keeps expire
+    #[structopt(long)]
+    keep_ttl: Option<u64>,
+
+    /// The number of seconds for which deleted keeps can be restored
+    #[structopt(long)]
+    soft_delete_retention: Option<u64>,
+
+    /// How new keeps are identified: uuidv4 (random) or ulid (time-sortable)
+    #[structopt(long, default_value = "uuidv4")]
+    id_scheme: Scheme,
+
+    /// Pretty-print JSON responses
+    #[structopt(long)]
+    json_pretty: bool,
+
+    /// A JSON (or .json5 or .cbor) file of contracts, reloaded on SIGHUP
+    #[structopt(long)]
+    contracts: Option<PathBuf>,
+
+    /// Parse a JSON contracts file as JSON5, allowing comments and trailing commas
+    #[structopt(long, requires = "contracts")]
+    relaxed: bool,
+
+    /// Offer one contract, with a stable UUID, per backend this host supports
+    #[structopt(long, conflicts_with = "contracts")]
+    contracts_from_backends: bool,
+
+    /// A JSON file mapping bearer tokens to roles (reader, writer or admin)
+    #[structopt(long)]
+    tokens: Option<PathBuf>,
+
+    /// Unix users allowed to change keeps over a Unix socket, in place of tokens
+    #[structopt(long, use_delimiter = true)]
+    peer_uids: Vec<u32>,
+
+    /// Unix groups allowed to change keeps over a Unix socket, in place of tokens
+    #[structopt(long, use_delimiter = true)]
+    peer_gids: Vec<u32>,
+
+    /// Omit the Server header from responses
+    #[structopt(long)]
+    no_server_header: bool,
+
+    /// Leave expired contracts out of contract listings
+    #[structopt(long)]
+    hide_expired: bool,
+
+    /// Refuse to claim contracts for backends this host can't run
+    #[structopt(long)]
+    require_supported: bool,
+
+    /// How a claim by backend chooses a contract: first, random or least-loaded
+    #[structopt(long, default_value = "first")]
+    claim_policy: ClaimPolicy,
+
+    /// A file to save keeps to, and restore them from at startup
+    #[structopt(long)]
+    state: Option<PathBuf>,
+
+    /// The number of seconds between saves of the state file
+    #[structopt(long, default_value = "10")]
+    state_interval: u64,
+
+    /// A Prometheus Pushgateway to push metrics to
+    #[structopt(long)]
+    push_gateway: Option<reqwest::Url>,
+
+    /// The number of seconds between pushes of metrics
+    #[structopt(long, default_value = "15")]
+    push_interval: u64,
+
+    /// How deeply JSON or CBOR request bodies may nest
+    #[structopt(long, default_value = "64")]
+    max_depth: usize,
+
+    /// The longest request path and query, in bytes, before 414 is answered
+    #[structopt(long, default_value = "2048")]
+    max_uri_length: usize,
+
+    /// The seconds clients are asked to wait when refused during startup or shutdown
+    #[structopt(long, default_value = "5")]
+    shutdown_retry_after: u64,
+
+    /// Log request and response bodies (at TRACE level)
+    #[structopt(long)]
+    log_bodies: bool,
+
+    /// How log lines are written: text or json
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Print the effective configuration and exit
+    #[structopt(long)]
+    print_config: bool,
+}
+
+/// The effective configuration, reported at startup.
+#[derive(Debug, Serialize)]
+struct Config {
+    listen: Option<String>,
+    max_keeps: Option<usize>,
+    max_keeps_per_owner: Option<usize>,
+    keep_ttl: Option<u64>,
+    soft_delete_retention: Option<u64>,
+    id_scheme: String,
+    json_pretty: bool,
+    contracts: Option<PathBuf>,
+    relaxed: bool,
+    contracts_from_backends: bool,
+    tokens: Option<PathBuf>,
+    peer_uids: Vec<u32>,
+    peer_gids: Vec<u32>,
+    server_header: bool,
+    hide_expired: bool,
+    require_supported: bool,
+    claim_policy: String,
+    state: Option<PathBuf>,
+    state_interval: u64,
+    push_gateway: Option<String>,
+    push_interval: u64,
+    max_depth: usize,
+    max_uri_length: usize,
+    shutdown_retry_after: u64,
+    log_bodies: bool,
+    log_format: String,
 }
 
-const CONTRACTS: &[Contract] = &[
-    Contract {
-        uuid: Uuid::from_u128(0xe6234733_513a_4883_981a_bfa972fa706b),
-        backend: Backend::Nil,
-    },
-    Contract {
-        uuid: Uuid::from_u128(0x0afa438e_acaa_4158_9518_ad59256def34),
-        backend: Backend::Kvm,
-    },
-    Contract {
-        uuid: Uuid::from_u128(0x31a41b53_cb9e_447b_bfa2_bfb8e6e42ff9),
-        backend: Backend::Sev,
-    },
-    Contract {
-        uuid: Uuid::from_u128(0xea392851_3435_42d3_a4ad_c4e5e5c6c4c6),
-        backend: Backend::Sgx,
-    },
-];
-
-static KEEPS: Lazy<RwLock<HashMap<Uuid, Keep>>> = Lazy::new(|| RwLock::new(HashMap::new()));
-
-fn cborize<T: Serialize>(item: &T) -> Vec<u8> {
-    let mut buffer = Vec::new();
-    ciborium::ser::into_writer(&item, &mut buffer).unwrap();
-    buffer
+impl From<&Options> for Config {
+    fn from(options: &Options) -> Self {
+        Self {
+            listen: options.listen.as_ref().map(|l| l.to_string()),
+            max_keeps: options.max_keeps,
+            max_keeps_per_owner: options.max_keeps_per_owner,
+            keep_ttl: options.keep_ttl,
+            soft_delete_retention: options.soft_delete_retention,
+            id_scheme: options.id_scheme.to_string(),
+            json_pretty: options.json_pretty,
+            contracts: options.contracts.clone(),
+            relaxed: options.relaxed,
+            contracts_from_backends: options.contracts_from_backends,
+            tokens: options.tokens.clone(),
+            peer_uids: options.peer_uids.clone(),
+            peer_gids: options.peer_gids.clone(),
+            server_header: !options.no_server_header,
+            hide_expired: options.hide_expired,
+            require_supported: options.require_supported,
+            claim_policy: options.claim_policy.to_string(),
+            state: options.state.clone(),
+            state_interval: options.state_interval,
+            push_gateway: options.push_gateway.as_ref().map(|u| u.to_string()),
+            push_interval: options.push_interval,
+            max_depth: options.max_depth,
+            max_uri_length: options.max_uri_length,
+            shutdown_retry_after: options.shutdown_retry_after,
+            log_bodies: options.log_bodies,
+            log_format: options.log_format.to_string(),
+        }
+    }
 }
 
-fn error(code: StatusCode) -> Response<Vec<u8>> {
-    Response::builder().status(code).body(Vec::new()).unwrap()
+impl Config {
+    fn log(&self) {
+        tracing::info!(
+            listen = ?self.listen,
+            max_keeps = ?self.max_keeps,
+            max_keeps_per_owner = ?self.max_keeps_per_owner,
+            keep_ttl = ?self.keep_ttl,
+            soft_delete_retention = ?self.soft_delete_retention,
+            id_scheme = %self.id_scheme,
+            json_pretty = self.json_pretty,
+            contracts = ?self.contracts,
+            relaxed = self.relaxed,
+            contracts_from_backends = self.contracts_from_backends,
+            tokens = ?self.tokens,
+            peer_uids = ?self.peer_uids,
+            peer_gids = ?self.peer_gids,
+            server_header = self.server_header,
+            hide_expired = self.hide_expired,
+            require_supported = self.require_supported,
+            claim_policy = %self.claim_policy,
+            state = ?self.state,
+            state_interval = self.state_interval,
+            push_gateway = ?self.push_gateway,
+            push_interval = self.push_interval,
+            max_depth = self.max_depth,
+            max_uri_length = self.max_uri_length,
+            shutdown_retry_after = self.shutdown_retry_after,
+            log_bodies = self.log_bodies,
+            log_format = %self.log_format,
+            "starting contractmgr"
+        );
+    }
 }
 
-async fn serve<I>(incoming: I) -> tokio::io::Result<()>
-where
-    I: futures_core::stream::TryStream + Send,
-    I::Ok: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static + Unpin,
-    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
-{
-    // Client is requesting details of all contracts.
-    let get_contracts = warp::path!("contracts")
-        .and(warp::filters::method::get())
-        .map(|| {
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(CONTENT_TYPE, "application/cbor")
-                .body(cborize(&CONTRACTS))
-                .unwrap()
+/// Serves requests on the listener until the server has drained.
+///
+/// The listeners are made non-blocking for tokio, or a blocking accept would
+/// keep a draining server from ever returning.
+async fn listen(listener: Listener, state: AppState, peers: Peers) -> tokio::io::Result<()> {
+    match listener {
+        // Peers on a Unix socket can be known by their credentials.
+        Listener::Unix(socket) if !peers.uids.is_empty() || !peers.gids.is_empty() => {
+            socket.set_nonblocking(true)?;
+            serve_peers(UnixListener::from_std(socket)?, state, peers).await
+        }
+
+        Listener::Unix(socket) => {
+            socket.set_nonblocking(true)?;
+            let listen = UnixListener::from_std(socket)?;
+            let stream = UnixListenerStream::new(listen);
+            serve(stream, state).await
+        }
+
+        Listener::Tcp(socket) => {
+            socket.set_nonblocking(true)?;
+            let listen = TcpListener::from_std(socket)?;
+            let stream = TcpListenerStream::new(listen);
+            serve(stream, state).await
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> tokio::io::Result<()> {
+    let options = Options::from_args();
+
+    let config = Config::from(&options);
+    if options.print_config {
+        println!("{}", serde_json::to_string_pretty(&config).unwrap());
+        return Ok(());
+    }
+
+    // Bodies are logged at TRACE, but nothing else should be that verbose.
+    let mut targets = Targets::new().with_default(LevelFilter::INFO);
+    if options.log_bodies {
+        targets = targets.with_target("contractmgr::bodies", LevelFilter::TRACE);
+    }
+
+    let logs = tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::TRACE)
+        .with_writer(std::io::stderr);
+    match options.log_format {
+        LogFormat::Text => logs.finish().with(targets).init(),
+        LogFormat::Json => logs
+            .json()
+            .flatten_event(true)
+            .finish()
+            .with(targets)
+            .init(),
+    }
+    config.log();
+
+    // Handlers answer a panic with a bare 500, so the details are only here.
+    std::panic::set_hook(Box::new(|info| {
+        let backtrace = backtrace::Backtrace::new();
+        tracing::error!("{}\n{:?}", info, backtrace);
+    }));
+
+    let tokens = match options.tokens {
+        Some(ref path) => Some(Arc::new(Tokens::load(path)?)),
+        None => None,
+    };
+
+    // A contracts file is read while the server starts, and requests are
+    // refused until it is in. The self-test needs the contracts up front.
+    let reloadable = options.contracts.is_some();
+    let contracts = match (options.contracts_from_backends, options.contracts) {
+        (true, _) => Arc::new(Contracts::from_backends(&Host)),
+        (false, Some(path)) if !options.selftest => {
+            Arc::new(Contracts::pending(path, options.relaxed))
+        }
+        (false, path) => Arc::new(Contracts::load_with(path, options.relaxed)?),
+    };
+
+    // Reload the contracts file on SIGHUP.
+    if reloadable {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let contracts = contracts.clone();
+        let mut hangups = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                if let Err(e) = contracts.reload() {
+                    tracing::error!("failed to reload contracts: {}", e);
+                }
+            }
         });
+    }
+
+    let mut keeps = KeepStore::new().ids(options.id_scheme.build());
+    if let Some(max) = options.max_keeps {
+        keeps = keeps.capacity(max);
+    }
+    if let Some(max) = options.max_keeps_per_owner {
+        keeps = keeps.per_owner(max);
+    }
+    if let Some(secs) = options.keep_ttl {
+        keeps = keeps.ttl(Duration::from_secs(secs));
+    }
+    if let Some(secs) = options.soft_delete_retention {
+        keeps = keeps.retention(Duration::from_secs(secs));
+    }
+    let keeps = Arc::new(keeps);
 
-    // Client is requesting details of a single contract.
-    let get_contracts_uuid = warp::path!("contracts" / Uuid)
-        .and(warp::filters::method::get())
-        .map(|cuuid| match CONTRACTS.iter().find(|c| c.uuid == cuuid) {
-            None => error(StatusCode::NOT_FOUND),
-            Some(contract) => Response::builder()
-                .status(StatusCode::OK)
-                .header(CONTENT_TYPE, "application/cbor")
-                .body(cborize(&contract))
-                .unwrap(),
+    // Restore the keeps from the last run and save them as they change.
+    let state_file = options.state.map(StateFile::new);
+    if let Some(file) = state_file.clone() {
+        let restored = file.restore(&keeps);
+        tracing::info!(restored, "restored keeps");
+
+        let keeps = keeps.clone();
+        let period = Duration::from_secs(options.state_interval.max(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = file.save(&keeps) {
+                    tracing::error!("failed to save keeps: {}", e);
+                }
+            }
         });
+    }
 
-    // Client is attempting to claim a contract.
-    let post_contracts_uuid = warp::path!("contracts" / Uuid)
-        .and(warp::filters::method::post())
-        .map(|cuuid| match CONTRACTS.iter().find(|c| c.uuid == cuuid) {
-            None => error(StatusCode::NOT_FOUND),
-            Some(contract) => {
-                let kuuid = Uuid::new_v4();
-                let keep = Keep {
-                    uuid: kuuid,
-                    contract: contract.clone(),
-                };
-
-                KEEPS.write().unwrap().insert(kuuid, keep.clone());
-
-                Response::builder()
-                    .status(StatusCode::CREATED)
-                    .header(LOCATION, format!("/keeps/{}", kuuid))
-                    .header(CONTENT_TYPE, "application/cbor")
-                    .body(cborize(&keep))
-                    .unwrap()
+    // Periodically release the memory held by expired and deleted keeps.
+    let period = match (options.keep_ttl, options.soft_delete_retention) {
+        (Some(ttl), Some(retention)) => Some(ttl.min(retention)),
+        (ttl, retention) => ttl.or(retention),
+    };
+    if let Some(secs) = period {
+        let keeps = keeps.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(secs.max(1));
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                keeps.purge();
             }
         });
+    }
+
+    let state = AppState {
+        contracts,
+        keeps,
+        pretty: options.json_pretty,
+        tokens,
+        peer: None,
+        server_header: !options.no_server_header,
+        hide_expired: options.hide_expired,
+        probe: Arc::new(Host),
+        require_supported: options.require_supported,
+        claim_policy: options.claim_policy,
+        log_bodies: options.log_bodies,
+        state_file,
+        max_depth: options.max_depth,
+        max_uri_length: options.max_uri_length,
+        claims: Arc::default(),
+        listings: Arc::default(),
+        connections: Arc::default(),
+        retry_after: options.shutdown_retry_after,
+        requests: Arc::default(),
+    };
+
+    // Stop accepting connections on SIGTERM, but finish the requests in flight.
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    // Client is requesting details for all keeps.
-    let get_keeps = warp::path!("keeps")
-        .and(warp::filters::method::get())
-        .map(|| {
-            let keeps: Vec<Keep> = KEEPS.read().unwrap().values().cloned().collect();
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(CONTENT_TYPE, "application/cbor")
-                .body(cborize(&keeps))
-                .unwrap()
+        let connections = state.connections.clone();
+        let mut terminations = signal(SignalKind::terminate())?;
+        tokio::spawn(async move {
+            if terminations.recv().await.is_some() {
+                tracing::info!(active = connections.active(), "draining connections");
+                connections.drain();
+            }
         });
+    }
 
-    // Client is requesting details of a single keep.
-    let get_keeps_uuid = warp::path!("keeps" / Uuid)
-        .and(warp::filters::method::get())
-        .map(|kuuid| match KEEPS.write().unwrap().get(&kuuid) {
-            None => error(StatusCode::NOT_FOUND),
-            Some(keep) => Response::builder()
-                .status(StatusCode::OK)
-                .header(CONTENT_TYPE, "application/cbor")
-                .body(cborize(&keep))
-                .unwrap(),
+    // If the contracts file can't be read, the server drains as it would on
+    // SIGTERM, and the error is returned from here once it has.
+    let (fail, mut failed) = tokio::sync::oneshot::channel();
+    if !state.contracts.is_loaded() {
+        let contracts = state.contracts.clone();
+        let connections = state.connections.clone();
+        tokio::task::spawn_blocking(move || match contracts.reload() {
+            Ok(()) => tracing::info!(count = contracts.get().len(), "loaded contracts"),
+            Err(e) => {
+                tracing::error!("failed to load contracts: {}", e);
+                let _ = fail.send(e);
+                connections.drain();
+            }
         });
+    }
 
-    // Client is requesting destruction of a single keep.
-    let delete_keeps_uuid = warp::path!("keeps" / Uuid)
-        .and(warp::filters::method::delete())
-        .map(|kuuid| match KEEPS.write().unwrap().remove(&kuuid) {
-            Some(..) => StatusCode::OK,
-            None => StatusCode::NOT_FOUND,
+    // Push metrics in the background so that a slow gateway never holds up
+    // requests.
+    if let Some(gateway) = options.push_gateway {
+        let state = state.clone();
+        let client = reqwest::Client::new();
+        let period = Duration::from_secs(options.push_interval.max(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = push(&client, &gateway, &state).await {
+                    tracing::warn!("failed to push metrics: {}", e);
+                }
+            }
         });
+    }
 
-    let routes = get_contracts
-        .or(get_contracts_uuid)
-        .or(post_contracts_uuid)
-        .or(get_keeps)
-        .or(get_keeps_uuid)
-        .or(delete_keeps_uuid);
+    if options.selftest {
+        let socket = std::net::TcpListener::bind("127.0.0.1:0")?;
+        socket.set_nonblocking(true)?;
+        let addr = socket.local_addr()?;
 
-    warp::serve(routes).serve_incoming(incoming).await;
-    Ok(())
-}
+        let listen = TcpListener::from_std(socket)?;
+        let stream = TcpListenerStream::new(listen);
+        tokio::spawn(serve(
+            stream,
+            AppState {
+                tokens: None,
+                ..state
+            },
+        ));
 
-#[tokio::main]
-async fn main() -> tokio::io::Result<()> {
-    match Options::from_args().listen {
-        Listener::Unix(socket) => {
-            let listen = UnixListener::from_std(socket)?;
-            let stream = UnixListenerStream::new(listen);
-            serve(stream).await
-        }
+        let passed = selftest::run(addr).await;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
 
-        Listener::Tcp(socket) => {
-            let listen = TcpListener::from_std(socket)?;
-            let stream = TcpListenerStream::new(listen);
-            serve(stream).await
+    // Once serving returns, every request has finished and no more will
+    // arrive, so the keeps can be saved one final time.
+    let keeps = state.keeps.clone();
+    let state_file = state.state_file.clone();
+
+    let peers = Peers {
+        uids: options.peer_uids,
+        gids: options.peer_gids,
+    };
+    let served = listen(options.listen.unwrap(), state, peers).await;
+
+    if let Some(file) = state_file {
+        match file.save(&keeps) {
+            Ok(()) => tracing::info!("saved keeps"),
+            Err(e) => tracing::error!("failed to save keeps: {}", e),
         }
     }
+
+    match failed.try_recv() {
+        Ok(e) => Err(e),
+        Err(..) => served,
+    }
 }
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::rates::WINDOW;
+use super::AppState;
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use reqwest::Url;
+use warp::http::StatusCode;
+use warp::reply::{Reply, Response};
+
+/// The Prometheus job name under which metrics are pushed.
+const JOB: &str = "contractmgr";
+
+/// The classes of response status, by their first digit.
+const CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Marks a response whose body couldn't be encoded, so that it is counted.
+#[derive(Copy, Clone, Debug)]
+pub struct Unencodable;
+
+/// Counts the requests answered, by class of response status, along with
+/// the bodies which couldn't be decoded or encoded.
+///
+/// The counters are atomics, so recording a request never waits on the keep
+/// store or on other requests.
+#[derive(Debug, Default)]
+pub struct Requests {
+    classes: [AtomicU64; 5],
+    undecodable: AtomicU64,
+    unencodable: AtomicU64,
+}
+
+impl Requests {
+    /// Counts the reply as it goes out.
+    pub fn record(&self, reply: impl Reply) -> Response {
+        let response = reply.into_response();
+        if let Some(counter) = self.classes.get(Self::class(response.status())) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if response.extensions().get::<Unencodable>().is_some() {
+            self.failed_encode();
+        }
+
+        response
+    }
+
+    /// Counts a request body which couldn't be decoded.
+    pub fn failed_decode(&self) {
+        self.undecodable.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a response body which couldn't be encoded.
+    pub fn failed_encode(&self) {
+        self.unencodable.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn class(status: StatusCode) -> usize {
+        (status.as_u16() / 100) as usize - 1
+    }
+
+    /// The number of requests answered with a status in the class of `status`.
+    pub fn answered(&self, status: StatusCode) -> u64 {
+        self.classes
+            .get(Self::class(status))
+            .map(|c| c.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// The number of requests answered in total.
+    pub fn total(&self) -> u64 {
+        self.classes.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+
+    /// The number of request bodies which couldn't be decoded.
+    pub fn decode_failures(&self) -> u64 {
+        self.undecodable.load(Ordering::Relaxed)
+    }
+
+    /// The number of response bodies which couldn't be encoded.
+    pub fn encode_failures(&self) -> u64 {
+        self.unencodable.load(Ordering::Relaxed)
+    }
+}
+
+/// Renders the server's metrics in the Prometheus text format.
+pub fn render(state: &AppState) -> String {
+    let mut text = String::new();
+
+    let mut gauge = |name: &str, help: &str, value: usize| {
+        writeln!(text, "# HELP contractmgr_{} {}", name, help).unwrap();
+        writeln!(text, "# TYPE contractmgr_{} gauge", name).unwrap();
+        writeln!(text, "contractmgr_{} {}", name, value).unwrap();
+    };
+
+    gauge(
+        "contracts",
+        "Contracts offered.",
+        state.contracts.get().len(),
+    );
+    gauge("keeps", "Live keeps.", state.keeps.list().len());
+    if let Some(max) = state.keeps.max_keeps() {
+        gauge("keeps_max", "The most keeps allowed.", max);
+    }
+
+    writeln!(text, "# HELP contractmgr_requests_total Requests answered.").unwrap();
+    writeln!(text, "# TYPE contractmgr_requests_total counter").unwrap();
+    for (class, counter) in CLASSES.iter().zip(state.requests.classes.iter()) {
+        let count = counter.load(Ordering::Relaxed);
+        writeln!(
+            text,
+            "contractmgr_requests_total{{class=\"{}\"}} {}",
+            class, count
+        )
+        .unwrap();
+    }
+
+    let mut counter = |name: &str, help: &str, value: u64| {
+        writeln!(text, "# HELP contractmgr_{} {}", name, help).unwrap();
+        writeln!(text, "# TYPE contractmgr_{} counter", name).unwrap();
+        writeln!(text, "contractmgr_{} {}", name, value).unwrap();
+    };
+
+    counter(
+        "decode_failures_total",
+        "Request bodies which couldn't be decoded.",
+        state.requests.decode_failures(),
+    );
+    counter(
+        "encode_failures_total",
+        "Response bodies which couldn't be encoded.",
+        state.requests.encode_failures(),
+    );
+
+    let claims = state.claims.counts();
+    if !claims.is_empty() {
+        writeln!(
+            text,
+            "# HELP contractmgr_claims Keeps claimed per contract in the last {}s.",
+            WINDOW.as_secs()
+        )
+        .unwrap();
+        writeln!(text, "# TYPE contractmgr_claims gauge").unwrap();
+        for (contract, count) in claims {
+            writeln!(
+                text,
+                "contractmgr_claims{{contract=\"{}\"}} {}",
+                contract, count
+            )
+            .unwrap();
+        }
+    }
+
+    text
+}
+
+/// Sends the metrics to a Prometheus Pushgateway.
+///
+/// Each push replaces everything previously pushed for the job.
+pub async fn push(
+    client: &reqwest::Client,
+    gateway: &Url,
+    state: &AppState,
+) -> reqwest::Result<()> {
+    let url = gateway
+        .join(&format!("metrics/job/{}", JOB))
+        .expect("job is a valid path");
+
+    client
+        .put(url)
+        .timeout(Duration::from_secs(10))
+        .body(render(state))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
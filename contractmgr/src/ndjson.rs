@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::depth;
+
+use franca::{Conflict, Exported, KeepStore};
+
+use serde::Serialize;
+use tokio_stream::{Stream, StreamExt};
+use warp::hyper::body::Buf;
+use warp::{Filter, Rejection};
+
+/// The media type of a stream of JSON documents, one per line.
+pub const KIND: &str = "application/x-ndjson";
+
+/// Why a line of an import wasn't imported.
+#[derive(Debug, Serialize)]
+pub struct LineError {
+    /// The line number, counting from one
+    line: usize,
+    error: String,
+
+    /// Whether the line wasn't a keep at all, rather than one which
+    /// couldn't be imported
+    #[serde(skip)]
+    pub undecodable: bool,
+}
+
+/// Passes only requests whose body is newline-delimited JSON.
+///
+/// Other requests are left for the routes taking whole documents.
+pub fn body() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::header::optional("content-type")
+        .and_then(|kind: Option<String>| async move {
+            match kind {
+                Some(kind) if kind.split(';').next().unwrap().trim() == KIND => Ok(()),
+                _ => Err(warp::reject()),
+            }
+        })
+        .untuple_one()
+}
+
+/// Imports keeps from a body holding one exported keep on each line.
+///
+/// Each line is imported as soon as it has arrived, so the body is never
+/// held in full; a line longer than `max_line` is refused unread. Lines
+/// which can't be imported are reported, and don't stop the rest.
+pub async fn import<S, B>(
+    body: S,
+    keeps: &KeepStore,
+    conflict: Conflict,
+    max_line: usize,
+    max_depth: usize,
+) -> (usize, Vec<LineError>)
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    tokio::pin!(body);
+
+    let mut imported = 0;
+    let mut errors = Vec::new();
+    let mut line = 0;
+    let mut buffer = Vec::new();
+    let mut overlong = false;
+
+    let mut one = |line: usize, bytes: &[u8], overlong: bool| {
+        let (error, undecodable) = if overlong {
+            ("line too long".to_string(), false)
+        } else if bytes.iter().all(u8::is_ascii_whitespace) {
+            return;
+        } else if !depth::json(bytes, max_depth) {
+            ("nested too deeply".to_string(), true)
+        } else {
+            match serde_json::from_slice::<Exported>(bytes) {
+                Err(e) => (e.to_string(), true),
+                Ok(exported) => match keeps.import(vec![exported], conflict) {
+                    Err(..) => ("conflicts with an existing keep".to_string(), false),
+                    Ok(n) => {
+                        imported += n;
+                        return;
+                    }
+                },
+            }
+        };
+
+        errors.push(LineError {
+            line,
+            error,
+            undecodable,
+        });
+    };
+
+    while let Some(chunk) = body.next().await {
+        let mut chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                tracing::warn!("import body failed: {}", e);
+                break;
+            }
+        };
+
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            let (end, newline) = match bytes.iter().position(|b| *b == b'\n') {
+                Some(at) => (at, true),
+                None => (bytes.len(), false),
+            };
+
+            if !overlong {
+                buffer.extend_from_slice(&bytes[..end]);
+                if buffer.len() > max_line {
+                    overlong = true;
+                    buffer = Vec::new();
+                }
+            }
+
+            chunk.advance(end + newline as usize);
+            if newline {
+                line += 1;
+                one(line, &buffer, overlong);
+                buffer.clear();
+                overlong = false;
+            }
+        }
+    }
+
+    // The last line needn't end with a newline
+    if overlong || !buffer.is_empty() {
+        one(line + 1, &buffer, overlong);
+    }
+
+    (imported, errors)
+}
@@ -0,0 +1,34 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use warp::http::StatusCode;
+use warp::reply::{Reply, Response};
+
+/// Describes a panic by its message, if it has one.
+fn message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => message,
+            None => "(no message)",
+        },
+    }
+}
+
+/// Runs a handler, answering a bare 500 if it panics.
+///
+/// The panic is logged, but nothing about it reaches the client. This only
+/// keeps the worker alive: a panic while the keep store is locked poisons it,
+/// after which every request that touches the store panics in turn and is
+/// answered with a 500. Only `/healthz?deep=1` reports such a store.
+pub fn guard<R: Reply>(handler: impl FnOnce() -> R) -> Response {
+    match catch_unwind(AssertUnwindSafe(|| handler().into_response())) {
+        Ok(response) => response,
+        Err(panic) => {
+            tracing::error!("request handler panicked: {}", message(&*panic));
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{serve, AppState, Role};
+
+use tokio::net::UnixListener;
+
+/// The Unix users and groups allowed to change anything.
+///
+/// Any other peer may only read.
+#[derive(Clone, Debug, Default)]
+pub struct Peers {
+    pub uids: Vec<u32>,
+    pub gids: Vec<u32>,
+}
+
+impl Peers {
+    /// Decides the role of a peer from its credentials.
+    pub fn role(&self, uid: u32, gid: u32) -> Role {
+        if self.uids.contains(&uid) || self.gids.contains(&gid) {
+            Role::Admin
+        } else {
+            Role::Reader
+        }
+    }
+}
+
+/// Serves the API on a Unix socket, authorizing peers by their credentials.
+///
+/// Each connection is served with the role of the process at the other end,
+/// which stands in for any token it sends.
+pub async fn serve_peers(
+    listener: UnixListener,
+    state: AppState,
+    peers: Peers,
+) -> tokio::io::Result<()> {
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = state.connections.draining() => break,
+        };
+
+        let role = match stream.peer_cred() {
+            Ok(cred) => peers.role(cred.uid(), cred.gid()),
+            Err(e) => {
+                tracing::warn!("failed to read peer credentials: {}", e);
+                continue;
+            }
+        };
+
+        let state = AppState {
+            peer: Some(role),
+            ..state.clone()
+        };
+
+        let once = tokio_stream::once(Ok::<_, std::io::Error>(stream));
+        tokio::spawn(serve(once, state));
+    }
+
+    // Each connection has its own server, so wait for them all to finish.
+    while state.connections.active() > 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use franca::{Conflict, Export, KeepStore};
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// A file which keeps are saved to and restored from across restarts.
+///
+/// Saves are written to a temporary file beside it which is then renamed
+/// into place, so an interrupted save never damages the previous one.
+#[derive(Clone, Debug)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The file a save is written to before it replaces the state file.
+    pub fn tmp(&self) -> PathBuf {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tmp.into()
+    }
+
+    /// Checks that saves can still be written beside the state file.
+    pub fn check(&self) -> Result<()> {
+        let mut probe = self.path.clone().into_os_string();
+        probe.push(".probe");
+
+        std::fs::write(&probe, b"")?;
+        std::fs::remove_file(&probe)
+    }
+
+    fn read(path: &Path) -> Result<Export> {
+        let file = File::open(path)?;
+        let export: Export = ciborium::de::from_reader(file)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+        if export.version != Export::VERSION {
+            let msg = format!("unsupported version: {}", export.version);
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+
+        Ok(export)
+    }
+
+    /// Saves all live keeps.
+    pub fn save(&self, keeps: &KeepStore) -> Result<()> {
+        let tmp = self.tmp();
+
+        let mut file = File::create(&tmp)?;
+        ciborium::ser::into_writer(&keeps.export(), &mut file).map_err(|e| match e {
+            ciborium::ser::Error::Io(e) => e,
+            ciborium::ser::Error::Value(msg) => Error::new(ErrorKind::InvalidData, msg),
+        })?;
+        file.sync_all()?;
+
+        std::fs::rename(&tmp, &self.path)
+    }
+
+    /// Restores the saved keeps into the store, returning how many there were.
+    ///
+    /// A damaged state file is not fatal: the temporary file of an
+    /// interrupted save is tried instead, and failing that nothing is
+    /// restored.
+    pub fn restore(&self, keeps: &KeepStore) -> usize {
+        for path in &[self.path.clone(), self.tmp()] {
+            match Self::read(path) {
+                Ok(export) => return keeps.import(export.keeps, Conflict::Replace).unwrap_or(0),
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => tracing::warn!("cannot restore keeps from {}: {}", path.display(), e),
+            }
+        }
+
+        0
+    }
+}
@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use franca::{Contract, KeepStore};
+
+use uuid::Uuid;
+
+/// How a claim by backend chooses between the contracts offering it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClaimPolicy {
+    /// The first contract, in the order they were given.
+    First,
+
+    /// Any of the contracts, chosen at random.
+    Random,
+
+    /// The contract with the fewest live keeps, the first of them on a tie.
+    LeastLoaded,
+}
+
+impl ClaimPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClaimPolicy::First => "first",
+            ClaimPolicy::Random => "random",
+            ClaimPolicy::LeastLoaded => "least-loaded",
+        }
+    }
+
+    /// Chooses one of the `contracts`, counting the keeps in `keeps` if
+    /// the policy needs to.
+    pub fn choose<'a, I>(self, mut contracts: I, keeps: &KeepStore) -> Option<&'a Contract>
+    where
+        I: Iterator<Item = &'a Contract>,
+    {
+        match self {
+            ClaimPolicy::First => contracts.next(),
+
+            ClaimPolicy::Random => {
+                let contracts: Vec<_> = contracts.collect();
+                match contracts.len() {
+                    0 => None,
+                    n => {
+                        let index = Uuid::new_v4().as_u128() % n as u128;
+                        Some(contracts[index as usize])
+                    }
+                }
+            }
+
+            ClaimPolicy::LeastLoaded => {
+                let counts = keeps.counts();
+                contracts.min_by_key(|c| counts.get(&c.uuid).copied().unwrap_or_default())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ClaimPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for ClaimPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(ClaimPolicy::First),
+            "random" => Ok(ClaimPolicy::Random),
+            "least-loaded" => Ok(ClaimPolicy::LeastLoaded),
+            _ => Err(format!("unknown claim policy: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+/// How far back keep claims are counted.
+pub const WINDOW: Duration = Duration::from_secs(60);
+
+/// Counts the keeps claimed under each contract over a sliding window, and
+/// holds back claims of contracts which are cooling down.
+#[derive(Debug, Default)]
+pub struct Claims {
+    window: Mutex<HashMap<Uuid, VecDeque<Instant>>>,
+    last: Mutex<HashMap<Uuid, Instant>>,
+}
+
+impl Claims {
+    /// Drops the claims which have slid out of the window.
+    fn expire(claims: &mut HashMap<Uuid, VecDeque<Instant>>, now: Instant) {
+        claims.retain(|_, times| {
+            while let Some(time) = times.front() {
+                if now.duration_since(*time) < WINDOW {
+                    break;
+                }
+
+                times.pop_front();
+            }
+
+            !times.is_empty()
+        });
+    }
+
+    /// Counts a keep claimed under the contract.
+    pub fn record(&self, contract: &Uuid) {
+        let now = Instant::now();
+        let mut claims = self.window.lock().unwrap();
+        Self::expire(&mut claims, now);
+        claims.entry(*contract).or_default().push_back(now);
+    }
+
+    /// Gets the number of keeps claimed under each contract in the window.
+    ///
+    /// Contracts without recent claims are left out.
+    pub fn counts(&self) -> BTreeMap<Uuid, usize> {
+        let mut claims = self.window.lock().unwrap();
+        Self::expire(&mut claims, Instant::now());
+        claims
+            .iter()
+            .map(|(uuid, times)| (*uuid, times.len()))
+            .collect()
+    }
+
+    /// Begins a claim under the contract, unless another began less than
+    /// `cooldown` ago, in which case returns how long is left to wait.
+    ///
+    /// A claim which then fails should be [`Claims::abandon`]ed, so that it
+    /// holds back no others.
+    pub fn begin(&self, contract: &Uuid, cooldown: Duration) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut last = self.last.lock().unwrap();
+
+        if let Some(time) = last.get(contract) {
+            let since = now.duration_since(*time);
+            if since < cooldown {
+                return Err(cooldown - since);
+            }
+        }
+
+        last.insert(*contract, now);
+        Ok(())
+    }
+
+    /// Forgets a claim which was begun but failed.
+    pub fn abandon(&self, contract: &Uuid) {
+        self.last.lock().unwrap().remove(contract);
+    }
+}
@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use franca::{Contract, Keep};
+
+use std::net::SocketAddr;
+
+use serde::de::DeserializeOwned;
+use warp::http::{Method, Request, StatusCode};
+use warp::hyper::{body::to_bytes, Body, Client};
+
+struct Server {
+    client: Client<warp::hyper::client::HttpConnector>,
+    addr: SocketAddr,
+}
+
+impl Server {
+    async fn request(&self, method: Method, path: &str) -> Result<(StatusCode, Vec<u8>), String> {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{}", self.addr, path))
+            .body(Body::empty())
+            .map_err(|e| e.to_string())?;
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| e.to_string())?;
+        let status = response.status();
+        let body = to_bytes(response.into_body())
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok((status, body.to_vec()))
+    }
+
+    async fn expect(
+        &self,
+        method: Method,
+        path: &str,
+        code: StatusCode,
+    ) -> Result<Vec<u8>, String> {
+        match self.request(method, path).await? {
+            (status, body) if status == code => Ok(body),
+            (status, ..) => Err(format!("expected {}, got {}", code, status)),
+        }
+    }
+}
+
+fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, String> {
+    ciborium::de::from_reader(body).map_err(|e| e.to_string())
+}
+
+async fn cycle(server: &Server) -> Result<(), String> {
+    // List the contracts.
+    let body = server
+        .expect(Method::GET, "/contracts", StatusCode::OK)
+        .await?;
+    let contracts: Vec<Contract> = decode(&body)?;
+    let contract = contracts.first().ok_or("no contracts")?;
+    println!("PASS list contracts");
+
+    // Create a keep.
+    let path = format!("/contracts/{}", contract.uuid);
+    let body = server
+        .expect(Method::POST, &path, StatusCode::CREATED)
+        .await?;
+    let keep: Keep = decode(&body)?;
+    if !keep.contract.core_eq(contract) {
+        return Err("created keep has the wrong contract".into());
+    }
+    println!("PASS create keep");
+
+    // Get the keep.
+    let path = format!("/keeps/{}", keep.uuid);
+    let body = server.expect(Method::GET, &path, StatusCode::OK).await?;
+    if decode::<Keep>(&body)? != keep {
+        return Err("fetched keep differs from created keep".into());
+    }
+    println!("PASS get keep");
+
+    // List the keeps.
+    let body = server.expect(Method::GET, "/keeps", StatusCode::OK).await?;
+    if !decode::<Vec<Keep>>(&body)?.contains(&keep) {
+        return Err("created keep is not listed".into());
+    }
+    println!("PASS list keeps");
+
+    // Delete the keep.
+    server.expect(Method::DELETE, &path, StatusCode::OK).await?;
+    server
+        .expect(Method::GET, &path, StatusCode::NOT_FOUND)
+        .await?;
+    println!("PASS delete keep");
+
+    Ok(())
+}
+
+/// Runs a full CRUD cycle against the server, reporting each step.
+pub async fn run(addr: SocketAddr) -> bool {
+    let server = Server {
+        client: Client::new(),
+        addr,
+    };
+
+    match cycle(&server).await {
+        Ok(()) => true,
+        Err(e) => {
+            println!("FAIL {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::io::Result;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection};
+
+/// The level of access granted to a token.
+///
+/// Each role is granted everything the roles before it are.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Reader,
+    Writer,
+    Admin,
+}
+
+/// A mapping of bearer tokens to their roles.
+#[derive(Debug)]
+pub struct Tokens(HashMap<String, Role>);
+
+impl Tokens {
+    /// Reads a JSON file mapping each token to its role.
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(Self(serde_json::from_reader(file)?))
+    }
+
+    fn role(&self, authorization: Option<&str>) -> Option<Role> {
+        let token = authorization?.strip_prefix("Bearer ")?;
+        self.0.get(token).copied()
+    }
+}
+
+/// Rejects a request which lacks the required role.
+#[derive(Debug)]
+struct Denied(StatusCode);
+
+impl warp::reject::Reject for Denied {}
+
+/// Requires a token with at least the `needed` role.
+///
+/// A peer known by its credentials needs no token, but its own role must
+/// suffice. Otherwise, when no tokens are configured, every request is
+/// allowed.
+pub fn require(
+    tokens: Option<Arc<Tokens>>,
+    peer: Option<Role>,
+    needed: Role,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let tokens = tokens.clone();
+            async move {
+                if let Some(role) = peer {
+                    if role < needed {
+                        return Err(warp::reject::custom(Denied(StatusCode::FORBIDDEN)));
+                    }
+
+                    return Ok(());
+                }
+
+                let tokens = match tokens {
+                    None => return Ok(()),
+                    Some(tokens) => tokens,
+                };
+
+                match tokens.role(authorization.as_deref()) {
+                    None => Err(warp::reject::custom(Denied(StatusCode::UNAUTHORIZED))),
+                    Some(role) if role < needed => {
+                        Err(warp::reject::custom(Denied(StatusCode::FORBIDDEN)))
+                    }
+                    Some(..) => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Finds the status code of a denied request.
+pub fn denied(rejection: &Rejection) -> Option<StatusCode> {
+    rejection.find::<Denied>().map(|denied| denied.0)
+}
@@ -4,13 +4,20 @@
 
 use std::collections::BTreeMap;
 
-use franca::{Backend, Contract, Keep};
+use franca::{Backend, Contract, Export, Keep};
 
 use uuid::Uuid;
-use warp::http::header::{HeaderValue, CONTENT_TYPE, LOCATION};
+use warp::http::header::{HeaderValue, ACCEPT, CONTENT_LOCATION, CONTENT_TYPE, LOCATION};
 use warp::http::StatusCode;
 
 async fn spawn_server(timeout: &str) -> tokio::io::Result<(String, tokio::process::Child)> {
+    spawn_server_with(timeout, &[]).await
+}
+
+async fn spawn_server_with(
+    timeout: &str,
+    args: &[&str],
+) -> tokio::io::Result<(String, tokio::process::Child)> {
     const BIN: &str = env!("CARGO_BIN_EXE_contractmgr");
 
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
@@ -36,6 +43,7 @@ async fn spawn_server(timeout: &str) -> tokio::io::Result<(String, tokio::proces
                 .arg(timeout)
                 .arg(BIN)
                 .arg(&host)
+                .args(args)
                 .spawn()?;
 
             // Wait for the server to start.
@@ -43,6 +51,15 @@ async fn spawn_server(timeout: &str) -> tokio::io::Result<(String, tokio::proces
                 tokio::time::sleep(Duration::from_millis(50)).await;
             }
 
+            // Wait for its contracts to load.
+            let readyz = format!("http://{}/readyz", host);
+            while child.try_wait()?.is_none() {
+                match reqwest::get(&readyz).await {
+                    Ok(response) if response.status() != StatusCode::SERVICE_UNAVAILABLE => break,
+                    _ => tokio::time::sleep(Duration::from_millis(50)).await,
+                }
+            }
+
             return Ok((host, child));
         }
     }
@@ -135,6 +152,57 @@ async fn post_contracts_uuid() {
     }
 }
 
+#[tokio::test]
+async fn post_contracts_uuid_old_client() {
+    // The shapes of a contract and a keep as the first clients knew them
+    #[derive(Debug, serde::Deserialize)]
+    struct OldContract {
+        uuid: Uuid,
+        backend: Backend,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct OldKeep {
+        uuid: Uuid,
+        contract: OldContract,
+    }
+
+    let (host, _) = spawn_server("5").await.unwrap();
+
+    // Get all the contracts
+    let url = format!("http://{}/contracts", host);
+    let response = reqwest::get(&url).await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let contracts: Vec<OldContract> = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Make a keep for each contract, with neither a body nor a content type
+    for contract in contracts {
+        let url = format!("http://{}/contracts/{}", host, contract.uuid);
+        let response = reqwest::Client::new().post(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let bytes = response.bytes().await.unwrap();
+        let keep: OldKeep = ciborium::de::from_reader(&bytes[..]).unwrap();
+        assert_eq!(keep.contract.uuid, contract.uuid);
+        assert_eq!(keep.contract.backend, contract.backend);
+
+        // A keep in the old shape is still read, with every newer field unset
+        let mut old = Vec::new();
+        let shape = serde_json::json!({
+            "uuid": keep.uuid,
+            "contract": { "uuid": contract.uuid, "backend": contract.backend },
+        });
+        ciborium::ser::into_writer(&shape, &mut old).unwrap();
+        let keep: Keep = ciborium::de::from_reader(&old[..]).unwrap();
+        assert_eq!(keep.contract.backend, contract.backend);
+        assert!(keep.contract.enabled);
+        assert_eq!(keep.owner, None);
+        assert!(keep.labels.is_empty());
+        assert_eq!(keep.created, None);
+        assert_eq!(keep.links, None);
+    }
+}
+
 #[tokio::test]
 async fn get_keeps() {
     let (host, _) = spawn_server("5").await.unwrap();
@@ -236,3 +304,813 @@ async fn delete_keeps_uuid() {
     let unknown: Vec<Keep> = ciborium::de::from_reader(&bytes[..]).unwrap();
     assert_eq!(unknown.len(), 0);
 }
+
+#[tokio::test]
+async fn get_contracts_json_pretty() {
+    let (host, _) = spawn_server_with("5", &["--json-pretty"]).await.unwrap();
+
+    let url = format!("http://{}/contracts", host);
+    let response = reqwest::Client::new()
+        .get(&url)
+        .header(ACCEPT, "application/json")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE),
+        Some(&HeaderValue::from_static("application/json"))
+    );
+
+    let text = response.text().await.unwrap();
+    assert!(text.contains('\n'));
+    assert!(text.contains("\n  "));
+
+    let contracts: Vec<Contract> = serde_json::from_str(&text).unwrap();
+    assert_eq!(contracts.len(), 4);
+}
+
+#[tokio::test]
+async fn post_keeps_uuid_revoke() {
+    let (host, _) = spawn_server("5").await.unwrap();
+
+    // Get all the contracts
+    let url = format!("http://{}/contracts", host);
+    let response = reqwest::get(&url).await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let contracts: Vec<Contract> = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Create a keep
+    let url = format!("http://{}/contracts/{}", host, contracts[0].uuid);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let keep: Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Revoke the keep
+    let url = format!("http://{}/keeps/{}/revoke", host, keep.uuid);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The revoked keep is gone
+    let url = format!("http://{}/keeps/{}", host, keep.uuid);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+    let response = reqwest::Client::new().delete(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    // An unknown keep was never found
+    let url = format!("http://{}/keeps/{}", host, Uuid::new_v4());
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = reqwest::Client::new().delete(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn get_keeps_large() {
+    const KEEPS: usize = 1000;
+
+    let (host, _) = spawn_server("10").await.unwrap();
+    let client = reqwest::Client::new();
+
+    // Get all the contracts
+    let url = format!("http://{}/contracts", host);
+    let response = reqwest::get(&url).await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let contracts: Vec<Contract> = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Make enough keeps for the response to span several chunks
+    let mut keeps: BTreeMap<Uuid, Keep> = BTreeMap::new();
+    for contract in contracts.iter().cycle().take(KEEPS) {
+        let url = format!("http://{}/contracts/{}", host, contract.uuid);
+        let response = client.post(&url).send().await.unwrap();
+        let bytes = response.bytes().await.unwrap();
+        let keep: Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+        keeps.insert(keep.uuid, keep);
+    }
+
+    // Fetch all the keeps from the server
+    let url = format!("http://{}/keeps", host);
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE),
+        Some(&HeaderValue::from_static("application/cbor"))
+    );
+
+    // Make sure the streamed body decodes to exactly the created keeps
+    let bytes = response.bytes().await.unwrap();
+    assert!(bytes.len() > 64 * 1024);
+    let unknown: Vec<Keep> = ciborium::de::from_reader(&bytes[..]).unwrap();
+    let unknown: BTreeMap<Uuid, Keep> = unknown.into_iter().map(|k| (k.uuid, k)).collect();
+    assert_eq!(keeps, unknown);
+}
+
+#[tokio::test]
+async fn selftest() {
+    const BIN: &str = env!("CARGO_BIN_EXE_contractmgr");
+
+    let output = tokio::process::Command::new("timeout")
+        .arg("5")
+        .arg(BIN)
+        .arg("--selftest")
+        .output()
+        .await
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("PASS delete keep"));
+    assert!(!stdout.contains("FAIL"));
+}
+
+#[tokio::test]
+async fn post_contracts_uuid_links() {
+    let (host, _) = spawn_server("5").await.unwrap();
+
+    // Get all the contracts
+    let url = format!("http://{}/contracts", host);
+    let response = reqwest::get(&url).await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let contracts: Vec<Contract> = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Create a keep
+    let url = format!("http://{}/contracts/{}", host, contracts[0].uuid);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let location = response.headers().get(LOCATION).cloned().unwrap();
+    let content_location = response.headers().get(CONTENT_LOCATION).cloned().unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let keep: Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // All of the links point to the canonical keep URL
+    let path = format!("/keeps/{}", keep.uuid);
+    assert_eq!(location, path);
+    assert_eq!(content_location, path);
+    assert_eq!(keep.links.unwrap().this, path);
+
+    // The canonical keep URL resolves to the keep
+    let url = format!("http://{}{}", host, path);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn contracts_reload_on_sighup() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+    use std::time::Duration;
+
+    async fn fetch(host: &str) -> Vec<Contract> {
+        let url = format!("http://{}/contracts", host);
+        let response = reqwest::get(&url).await.unwrap();
+        let bytes = response.bytes().await.unwrap();
+        ciborium::de::from_reader(&bytes[..]).unwrap()
+    }
+
+    let nil = Contract::new(
+        Uuid::from_u128(0x8d2c4e3a_64c7_4bb0_9a52_8d0e21f3c9d4),
+        Backend::Nil,
+    );
+    let kvm = Contract::new(
+        Uuid::from_u128(0x5b5c0b0e_6c1a_4f3e_b1a4_77a0a7e0d1f2),
+        Backend::Kvm,
+    );
+
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&[&nil]).unwrap()).unwrap();
+
+    let (host, child) = spawn_server_with("5", &["--contracts", path.to_str().unwrap()])
+        .await
+        .unwrap();
+
+    // Signal the server itself rather than the `timeout` wrapping it
+    let timeout = child.id().unwrap();
+    let children = format!("/proc/{}/task/{}/children", timeout, timeout);
+    let children = std::fs::read_to_string(children).unwrap();
+    let pid = Pid::from_raw(children.trim().parse().unwrap());
+    assert_eq!(fetch(&host).await, vec![nil.clone()]);
+
+    // An invalid file is rejected and the old contracts are kept
+    std::fs::write(&path, b"[{").unwrap();
+    kill(pid, Signal::SIGHUP).unwrap();
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(fetch(&host).await, vec![nil.clone()]);
+
+    // So is a file of contracts which could never be claimed
+    let now = chrono::Utc::now();
+    let closed = Contract {
+        not_before: Some(now + chrono::Duration::hours(1)),
+        not_after: Some(now),
+        ..kvm.clone()
+    };
+    std::fs::write(&path, serde_json::to_vec(&[&nil, &closed]).unwrap()).unwrap();
+    kill(pid, Signal::SIGHUP).unwrap();
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(fetch(&host).await, vec![nil.clone()]);
+
+    // A valid file replaces the contracts
+    std::fs::write(&path, serde_json::to_vec(&[&nil, &kvm]).unwrap()).unwrap();
+    kill(pid, Signal::SIGHUP).unwrap();
+
+    let mut contracts = fetch(&host).await;
+    for _ in 0..20 {
+        if contracts.len() > 1 {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        contracts = fetch(&host).await;
+    }
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(contracts, vec![nil, kvm]);
+}
+
+#[tokio::test]
+async fn keeps_export_import() {
+    let (host, _) = spawn_server("5").await.unwrap();
+    let client = reqwest::Client::new();
+
+    // Get all the contracts
+    let url = format!("http://{}/contracts", host);
+    let response = reqwest::get(&url).await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let contracts: Vec<Contract> = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Make a keep for each contract
+    for contract in &contracts {
+        let url = format!("http://{}/contracts/{}", host, contract.uuid);
+        let response = client.post(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // Export all the keeps
+    let url = format!("http://{}/keeps:export", host);
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let backup = response.bytes().await.unwrap();
+    let export: Export = ciborium::de::from_reader(&backup[..]).unwrap();
+    assert_eq!(export.version, Export::VERSION);
+    assert_eq!(export.keeps.len(), contracts.len());
+
+    // Purge all the keeps
+    for exported in &export.keeps {
+        let url = format!("http://{}/keeps/{}", host, exported.keep.uuid);
+        let response = client.delete(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Import the backup
+    let url = format!("http://{}/keeps:import", host);
+    let response = client
+        .post(&url)
+        .header(CONTENT_TYPE, "application/cbor")
+        .body(backup.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The keeps are restored exactly
+    let url = format!("http://{}/keeps:export", host);
+    let response = client.get(&url).send().await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let mut restored: Export = ciborium::de::from_reader(&bytes[..]).unwrap();
+    let mut original = export;
+    original.keeps.sort_by_key(|e| e.keep.uuid);
+    restored.keeps.sort_by_key(|e| e.keep.uuid);
+    assert_eq!(original, restored);
+
+    // Importing again conflicts by default...
+    let url = format!("http://{}/keeps:import", host);
+    let response = client
+        .post(&url)
+        .header(CONTENT_TYPE, "application/cbor")
+        .body(backup.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // ... unless conflicts are skipped
+    let url = format!("http://{}/keeps:import?conflict=skip", host);
+    let response = client
+        .post(&url)
+        .header(CONTENT_TYPE, "application/cbor")
+        .body(backup)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn print_config() {
+    const BIN: &str = env!("CARGO_BIN_EXE_contractmgr");
+
+    let output = tokio::process::Command::new(BIN)
+        .arg("127.0.0.1:0")
+        .arg("--max-keeps")
+        .arg("7")
+        .arg("--print-config")
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(config["listen"].as_str().unwrap().starts_with("127.0.0.1:"));
+    assert_eq!(config["max_keeps"], 7);
+    assert_eq!(config["id_scheme"], "uuidv4");
+}
+
+#[tokio::test]
+async fn json_access_log() {
+    const BIN: &str = env!("CARGO_BIN_EXE_contractmgr");
+
+    use std::process::Stdio;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpStream;
+
+    let host = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let mut child = tokio::process::Command::new("timeout")
+        .arg("5")
+        .arg(BIN)
+        .arg(&host)
+        .arg("--log-format")
+        .arg("json")
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    while TcpStream::connect(&host).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let url = format!("http://{}/contracts", host);
+    let response = reqwest::Client::new()
+        .get(&url)
+        .header("x-request-id", "json-access-log")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Every line is JSON; the request's is among them
+    let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+    let line = loop {
+        let line = lines.next_line().await.unwrap().unwrap();
+        let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+        if line["target"] == "contractmgr::access" {
+            break line;
+        }
+    };
+    child.kill().await.unwrap();
+
+    assert_eq!(line["method"], "GET");
+    assert_eq!(line["path"], "/contracts");
+    assert_eq!(line["status"], 200);
+    assert!(line["latency_us"].is_u64());
+    assert_eq!(line["request_id"], "json-access-log");
+}
+
+#[tokio::test]
+async fn post_backends_name() {
+    let (host, _) = spawn_server("5").await.unwrap();
+    let client = reqwest::Client::new();
+
+    // Claim a keep by backend
+    let url = format!("http://{}/backends/nil", host);
+    let response = client.post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers().get(LOCATION).cloned().unwrap();
+
+    let bytes = response.bytes().await.unwrap();
+    let keep: Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(keep.contract.backend, Backend::Nil);
+    assert_eq!(location, format!("/keeps/{}", keep.uuid));
+
+    // The keep exists
+    let url = format!("http://{}/keeps/{}", host, keep.uuid);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Unknown backends have no contracts
+    let url = format!("http://{}/backends/unknown", host);
+    let response = client.post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn tokens_roles() {
+    let path = std::env::temp_dir().join(format!("tokens-{}.json", Uuid::new_v4()));
+    let tokens = r#"{"r": "reader", "w": "writer", "a": "admin"}"#;
+    std::fs::write(&path, tokens).unwrap();
+
+    let (host, _) = spawn_server_with("5", &["--tokens", path.to_str().unwrap()])
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let client = reqwest::Client::new();
+    let contracts = format!("http://{}/contracts", host);
+    let backends = format!("http://{}/backends/nil", host);
+    let export = format!("http://{}/keeps:export", host);
+    let import = format!("http://{}/keeps:import", host);
+
+    // Missing and unknown tokens are unauthorized
+    let response = client.get(&contracts).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client
+        .get(&contracts)
+        .bearer_auth("x")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Readers can read, but not claim
+    let response = client
+        .get(&contracts)
+        .bearer_auth("r")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post(&backends)
+        .bearer_auth("r")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Writers can claim and destroy, but not export
+    let response = client
+        .post(&backends)
+        .bearer_auth("w")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+    let keep = format!("http://{}{}", host, location);
+    let response = client.get(&keep).bearer_auth("w").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get(&export).bearer_auth("w").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client.delete(&keep).bearer_auth("w").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Admins can do everything
+    let response = client.get(&export).bearer_auth("a").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.bytes().await.unwrap();
+    let response = client
+        .post(&import)
+        .header(CONTENT_TYPE, "application/cbor")
+        .body(body.clone())
+        .bearer_auth("w");
+    assert_eq!(
+        response.send().await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+    let response = client
+        .post(&import)
+        .header(CONTENT_TYPE, "application/cbor")
+        .body(body)
+        .bearer_auth("a");
+    assert_eq!(response.send().await.unwrap().status(), StatusCode::OK);
+
+    // Unknown routes are still not found
+    let url = format!("http://{}/nope", host);
+    let response = client.get(&url).bearer_auth("a").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn get_contracts_fields_sort() {
+    let contracts: Vec<Contract> = [Backend::Kvm, Backend::Nil, Backend::Kvm, Backend::Nil]
+        .iter()
+        .map(|backend| Contract::new(Uuid::new_v4(), backend.clone()))
+        .collect();
+
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&contracts).unwrap()).unwrap();
+
+    let (host, _) = spawn_server_with("5", &["--contracts", path.to_str().unwrap()])
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let client = reqwest::Client::new();
+    let get = |query: &str| {
+        let url = format!("http://{}/contracts?{}", host, query);
+        client.get(&url).header(ACCEPT, "application/json").send()
+    };
+
+    // Projection drops the unrequested fields
+    let response = get("fields=backend").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.bytes().await.unwrap();
+    let projected: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(
+        projected,
+        serde_json::json!([
+            { "backend": "kvm" },
+            { "backend": "nil" },
+            { "backend": "kvm" },
+            { "backend": "nil" },
+        ])
+    );
+
+    // Sorting keeps contracts with equal keys in their original order
+    let response = get("sort=backend").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.bytes().await.unwrap();
+    let sorted: Vec<Contract> = serde_json::from_slice(&bytes).unwrap();
+    let expected = vec![
+        contracts[0].clone(),
+        contracts[2].clone(),
+        contracts[1].clone(),
+        contracts[3].clone(),
+    ];
+    assert_eq!(sorted, expected);
+
+    // Both can be combined
+    let response = get("sort=backend&fields=uuid,backend").await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let sorted: Vec<Contract> = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(sorted, expected);
+
+    // Unknown names are rejected
+    let response = get("fields=uuid,color").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = get("sort=color").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn state_truncated() {
+    let path = std::env::temp_dir().join(format!("state-{}.cbor", Uuid::new_v4()));
+    let state = path.to_str().unwrap();
+
+    // Save a keep
+    let (host, mut child) = spawn_server_with("2", &["--state", state, "--state-interval", "1"])
+        .await
+        .unwrap();
+    let url = format!("http://{}/backends/nil", host);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    child.wait().await.unwrap();
+
+    // Damage the saved state as a crash mid-write might
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+
+    // The server still starts, just without the keep
+    let (host, _) = spawn_server_with("5", &["--state", state]).await.unwrap();
+    let url = format!("http://{}/keeps", host);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.bytes().await.unwrap();
+    let keeps: Vec<Keep> = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert!(keeps.is_empty());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn push_gateway() {
+    use tokio::sync::mpsc;
+    use warp::Filter;
+
+    // A stub gateway which passes on whatever it is sent
+    let (sender, mut pushes) = mpsc::unbounded_channel();
+    let gateway = warp::path!("metrics" / "job" / String)
+        .and(warp::filters::method::put())
+        .and(warp::body::bytes())
+        .map(move |job: String, body: warp::hyper::body::Bytes| {
+            sender.send((job, body)).unwrap();
+            StatusCode::OK
+        });
+
+    let (addr, server) = warp::serve(gateway).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let url = format!("http://{}/", addr);
+    let (_, _) = spawn_server_with("5", &["--push-gateway", &url, "--push-interval", "1"])
+        .await
+        .unwrap();
+
+    let pushed = tokio::time::timeout(std::time::Duration::from_secs(4), pushes.recv());
+    let (job, body) = pushed.await.unwrap().unwrap();
+    assert_eq!(job, "contractmgr");
+
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("contractmgr_contracts 4\n"));
+    assert!(body.contains("contractmgr_keeps 0\n"));
+    assert!(body.contains("contractmgr_decode_failures_total 0\n"));
+    assert!(body.contains("contractmgr_encode_failures_total 0\n"));
+}
+
+#[tokio::test]
+async fn contracts_from_backends() {
+    async fn offered() -> Vec<Contract> {
+        let (host, mut child) = spawn_server_with("5", &["--contracts-from-backends"])
+            .await
+            .unwrap();
+
+        let url = format!("http://{}/contracts", host);
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let bytes = response.bytes().await.unwrap();
+        child.kill().await.unwrap();
+        ciborium::de::from_reader(&bytes[..]).unwrap()
+    }
+
+    // The same contracts are offered after a restart
+    let first = offered().await;
+    let second = offered().await;
+    assert_eq!(first, second);
+
+    // The nil backend is always supported
+    let nil = first.iter().find(|c| c.backend == Backend::Nil).unwrap();
+    assert_eq!(nil.uuid.get_version_num(), 5);
+}
+
+#[tokio::test]
+async fn status_connections_draining() {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Status {
+        active: usize,
+        draining: bool,
+    }
+
+    let (host, mut child) = spawn_server("10").await.unwrap();
+    let url = format!("http://{}/status/connections", host);
+    let status = || async {
+        let response = reqwest::Client::new()
+            .get(&url)
+            .header(ACCEPT, "application/json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = response.bytes().await.unwrap();
+        serde_json::from_slice::<Status>(&bytes).unwrap()
+    };
+
+    // Only the status request itself is in flight
+    let idle = status().await;
+    assert_eq!(idle.active, 1);
+    assert!(!idle.draining);
+
+    // Hold a request open by sending only part of its body
+    let mut held = TcpStream::connect(&host).await.unwrap();
+    held.write_all(b"POST /keeps:import HTTP/1.1\r\nHost: localhost\r\n")
+        .await
+        .unwrap();
+    held.write_all(b"Content-Type: application/json\r\nContent-Length: 2\r\n\r\n{")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(status().await.active, 2);
+
+    // Once asked to stop, the server waits for the held request
+    let stopped = tokio::process::Command::new("kill")
+        .arg("-TERM")
+        .arg(child.id().unwrap().to_string())
+        .status()
+        .await
+        .unwrap();
+    assert!(stopped.success());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(child.try_wait().unwrap().is_none());
+
+    held.write_all(b"}").await.unwrap();
+    let mut response = [0u8; 12];
+    held.read_exact(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 "));
+    drop(held);
+
+    let exited = tokio::time::timeout(Duration::from_secs(5), child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(exited.success());
+}
+
+#[tokio::test]
+async fn contracts_load_failure() {
+    let dir = std::env::temp_dir().join(format!("contractmgr-{}", Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    let contracts = dir.join("contracts.json");
+    let state = dir.join("state.cbor");
+    std::fs::write(&contracts, b"[{").unwrap();
+
+    let args = [
+        "--contracts",
+        contracts.to_str().unwrap(),
+        "--state",
+        state.to_str().unwrap(),
+    ];
+    let (_, mut child) = spawn_server_with("10", &args).await.unwrap();
+
+    // The server gives up, but still saves the keeps on the way out
+    let exited = tokio::time::timeout(std::time::Duration::from_secs(5), child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!exited.success());
+    assert!(state.exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_saves_state() {
+    let path = std::env::temp_dir().join(format!("state-{}.cbor", Uuid::new_v4()));
+    let state = path.to_str().unwrap();
+
+    // Periodic saves are too rare to catch the keep
+    let (host, mut child) =
+        spawn_server_with("10", &["--state", state, "--state-interval", "3600"])
+            .await
+            .unwrap();
+    let url = format!("http://{}/backends/nil", host);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let bytes = response.bytes().await.unwrap();
+    let keep: Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    let stopped = tokio::process::Command::new("kill")
+        .arg("-TERM")
+        .arg(child.id().unwrap().to_string())
+        .status()
+        .await
+        .unwrap();
+    assert!(stopped.success());
+
+    let exited = tokio::time::timeout(std::time::Duration::from_secs(5), child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(exited.success());
+
+    // The keep was saved on the way out
+    let saved: Export = ciborium::de::from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+    assert!(saved.keeps.iter().any(|e| e.keep.uuid == keep.uuid));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn deadline_body() {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    let (host, _) = spawn_server("10").await.unwrap();
+
+    // Send only part of each body, with a deadline that passes meanwhile
+    let bodies: [&[u8]; 2] = [
+        b"Content-Type: application/json\r\nContent-Length: 2\r\n\r\n{",
+        b"Content-Type: application/x-ndjson\r\nTransfer-Encoding: chunked\r\n\r\n1\r\n\n\r\n",
+    ];
+
+    for body in bodies.iter() {
+        let deadline = (Utc::now() + chrono::Duration::milliseconds(500)).to_rfc3339();
+        let head = format!(
+            "POST /keeps:import HTTP/1.1\r\nHost: localhost\r\nX-Deadline: {}\r\n",
+            deadline
+        );
+
+        let mut held = TcpStream::connect(&host).await.unwrap();
+        held.write_all(head.as_bytes()).await.unwrap();
+        held.write_all(body).await.unwrap();
+
+        let mut response = [0u8; 12];
+        tokio::time::timeout(Duration::from_secs(5), held.read_exact(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&response, b"HTTP/1.1 408");
+    }
+}
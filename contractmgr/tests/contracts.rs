@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::Contracts;
+use franca::{Backend, Contract};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use uuid::Uuid;
+
+fn contracts(backends: &[Backend]) -> Vec<Contract> {
+    backends
+        .iter()
+        .map(|backend| Contract::new(Uuid::new_v4(), backend.clone()))
+        .collect()
+}
+
+#[test]
+fn reload_while_reading() {
+    let one = contracts(&[Backend::Nil, Backend::Kvm]);
+    let two = contracts(&[Backend::Sev, Backend::Sgx, Backend::Kvm]);
+    let one_json = serde_json::to_vec(&one).unwrap();
+    let two_json = serde_json::to_vec(&two).unwrap();
+
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, &one_json).unwrap();
+    let offered = Arc::new(Contracts::load(Some(path.clone())).unwrap());
+    let done = Arc::new(AtomicBool::new(false));
+
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let offered = offered.clone();
+            let done = done.clone();
+            let one = one.clone();
+            let two = two.clone();
+
+            thread::spawn(move || {
+                let mut reads = 0usize;
+                while !done.load(Ordering::Relaxed) {
+                    let snapshot = offered.get();
+                    assert!(**snapshot == one || **snapshot == two);
+                    reads += 1;
+                }
+                reads
+            })
+        })
+        .collect();
+
+    // Swap between the two lists as fast as possible
+    for i in 0..200 {
+        let json = if i % 2 == 0 { &two_json } else { &one_json };
+        std::fs::write(&path, json).unwrap();
+        offered.reload().unwrap();
+    }
+
+    done.store(true, Ordering::Relaxed);
+    for reader in readers {
+        assert!(reader.join().unwrap() > 0);
+    }
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(**offered.get(), one);
+}
+
+#[test]
+fn index() {
+    let backends: Vec<Backend> = (0..1000)
+        .map(|i| match i % 3 {
+            0 => Backend::Sev,
+            1 => Backend::Sgx,
+            _ => Backend::Kvm,
+        })
+        .collect();
+    let list = contracts(&backends);
+
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&list).unwrap()).unwrap();
+    let offered = Contracts::load(Some(path.clone())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let snapshot = offered.get();
+    for contract in &list {
+        assert_eq!(snapshot.find(&contract.uuid), Some(contract));
+    }
+    assert_eq!(snapshot.find(&Uuid::new_v4()), None);
+
+    // Each backend lists its own contracts, in file order
+    for backend in &[Backend::Sev, Backend::Sgx, Backend::Kvm] {
+        let indexed: Vec<&Contract> = snapshot.backend(backend).collect();
+        let scanned: Vec<&Contract> = list.iter().filter(|c| c.backend == *backend).collect();
+        assert_eq!(indexed, scanned);
+    }
+    assert_eq!(snapshot.backend(&Backend::Nil).count(), 0);
+}
+
+#[test]
+fn json5() {
+    const JSON5: &str = r#"
+        // Contracts offered by this host
+        [
+            {
+                uuid: "0afa438e-acaa-4158-9518-ad59256def34",
+                backend: "kvm",
+                cost: 3, /* cheap */
+            },
+        ]
+    "#;
+
+    let uuid = Uuid::from_u128(0x0afa438e_acaa_4158_9518_ad59256def34);
+    let path = std::env::temp_dir().join(format!("contracts-{}.json5", Uuid::new_v4()));
+    std::fs::write(&path, JSON5).unwrap();
+    let offered = Contracts::load(Some(path.clone())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let snapshot = offered.get();
+    assert_eq!(snapshot.len(), 1);
+    assert_eq!(snapshot[0].uuid, uuid);
+    assert_eq!(snapshot[0].backend, Backend::Kvm);
+    assert_eq!(snapshot[0].cost, Some(3));
+
+    // A .json file is strict unless relaxed parsing is asked for
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, JSON5).unwrap();
+    assert!(Contracts::load(Some(path.clone())).is_err());
+    let offered = Contracts::load_with(Some(path.clone()), true).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(offered.get()[0].uuid, uuid);
+}
+
+#[test]
+fn invalid() {
+    let mut list = contracts(&[Backend::Nil, Backend::Kvm]);
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&list).unwrap()).unwrap();
+    let offered = Contracts::load(Some(path.clone())).unwrap();
+
+    // A contract which could never be claimed fails the whole file
+    let now = chrono::Utc::now();
+    list[1].not_before = Some(now + chrono::Duration::hours(1));
+    list[1].not_after = Some(now);
+    std::fs::write(&path, serde_json::to_vec(&list).unwrap()).unwrap();
+
+    let err = Contracts::load(Some(path.clone())).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().starts_with("invalid contract 1:"));
+
+    // A reload keeps the contracts it had
+    assert!(offered.reload().is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(offered.get().len(), 2);
+    assert_eq!(offered.get()[1].not_after, None);
+}
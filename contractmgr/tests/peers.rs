@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{serve_peers, AppState, Contracts, Peers, Role};
+use franca::KeepStore;
+
+use std::path::{Path, PathBuf};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+fn state() -> AppState {
+    AppState::new(Contracts::load(None).unwrap(), KeepStore::new())
+}
+
+fn spawn(peers: Peers) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("peers-{}.sock", uuid::Uuid::new_v4()));
+    let listener = UnixListener::bind(&path).unwrap();
+    tokio::spawn(serve_peers(listener, state(), peers));
+    path
+}
+
+/// Sends a bodiless request over the socket, returning the status code.
+async fn status(path: &Path, method: &str, uri: &str) -> u16 {
+    let mut stream = UnixStream::connect(path).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, uri
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    // Only the status line is of interest; the body may not even be text
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let end = response
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .expect("no status line");
+    let line = String::from_utf8_lossy(&response[..end]).into_owned();
+    line.split(' ').nth(1).unwrap().parse().unwrap()
+}
+
+#[test]
+fn role() {
+    let peers = Peers {
+        uids: vec![1000],
+        gids: vec![10],
+    };
+
+    assert_eq!(peers.role(1000, 1000), Role::Admin);
+    assert_eq!(peers.role(1001, 10), Role::Admin);
+    assert_eq!(peers.role(1001, 1001), Role::Reader);
+}
+
+#[tokio::test]
+async fn allowed() {
+    let uid = nix::unistd::getuid().as_raw();
+    let path = spawn(Peers {
+        uids: vec![uid],
+        gids: vec![],
+    });
+
+    assert_eq!(status(&path, "GET", "/contracts").await, 200);
+    assert_eq!(status(&path, "POST", "/backends/nil").await, 201);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn disallowed() {
+    let uid = nix::unistd::getuid().as_raw();
+    let gid = nix::unistd::getgid().as_raw();
+    let path = spawn(Peers {
+        uids: vec![uid.wrapping_add(1)],
+        gids: vec![gid.wrapping_add(1)],
+    });
+
+    // Anyone may read, but only the allowed peers may change anything
+    assert_eq!(status(&path, "GET", "/contracts").await, 200);
+    assert_eq!(status(&path, "POST", "/backends/nil").await, 403);
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{Contracts, StateFile};
+use franca::KeepStore;
+
+use uuid::Uuid;
+
+#[test]
+fn save_restore() {
+    let path = std::env::temp_dir().join(format!("state-{}.cbor", Uuid::new_v4()));
+    let file = StateFile::new(path.clone());
+    let contracts = Contracts::load(None).unwrap();
+
+    let keeps = KeepStore::new();
+    for contract in contracts.get().iter() {
+        keeps.create(contract).unwrap();
+    }
+    file.save(&keeps).unwrap();
+    assert!(!file.tmp().exists());
+
+    // The keeps come back as they were
+    let restored = KeepStore::new();
+    assert_eq!(file.restore(&restored), 4);
+    let mut expected = keeps.list();
+    let mut actual = restored.list();
+    expected.sort_by_key(|k| k.uuid);
+    actual.sort_by_key(|k| k.uuid);
+    assert_eq!(actual, expected);
+
+    // A save interrupted before it replaced the file is not needed...
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(file.tmp(), &bytes[..bytes.len() / 2]).unwrap();
+    assert_eq!(file.restore(&KeepStore::new()), 4);
+
+    // ... unless the file itself is damaged
+    std::fs::write(file.tmp(), &bytes).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+    assert_eq!(file.restore(&KeepStore::new()), 4);
+
+    // With nothing usable, nothing is restored
+    std::fs::write(file.tmp(), b"").unwrap();
+    assert_eq!(file.restore(&KeepStore::new()), 0);
+
+    std::fs::remove_file(file.tmp()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(file.restore(&KeepStore::new()), 0);
+}
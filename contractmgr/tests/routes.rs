@@ -0,0 +1,1849 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{log_bodies, routes, AppState, ClaimPolicy, Contracts, Role, StateFile, Tokens};
+use franca::{Backend, Conflict, Contract, Export, Keep, KeepStore, Probe};
+
+use std::sync::{Arc, Mutex};
+
+use chrono::{Duration, Utc};
+use serde::de::DeserializeOwned;
+use warp::http::header::{
+    HeaderValue, ACCEPT, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LOCATION, RETRY_AFTER, SERVER,
+};
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::test::request;
+
+fn state() -> AppState {
+    AppState::new(Contracts::load(None).unwrap(), KeepStore::new())
+}
+
+fn decode<T: DeserializeOwned>(body: &Bytes) -> T {
+    ciborium::de::from_reader(&body[..]).unwrap()
+}
+
+#[tokio::test]
+async fn get_contracts() {
+    let api = routes(state());
+
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/cbor");
+
+    let contracts: Vec<Contract> = decode(response.body());
+    assert_eq!(contracts.len(), 4);
+
+    let path = format!("/contracts/{}", contracts[0].uuid);
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(decode::<Contract>(response.body()), contracts[0]);
+
+    let response = request()
+        .path("/contracts?fields=backend")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+}
+
+#[tokio::test]
+async fn crud() {
+    let api = routes(state());
+
+    // Create
+    let response = request()
+        .method("POST")
+        .path("/backends/kvm")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let keep: Keep = decode(response.body());
+    assert_eq!(keep.contract.backend, Backend::Kvm);
+    let path = Keep::path(&keep.uuid);
+    assert_eq!(response.headers()[LOCATION], path.as_str());
+
+    // Read
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(decode::<Keep>(response.body()), keep);
+
+    let response = request().path("/keeps").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(decode::<Vec<Keep>>(response.body()), vec![keep.clone()]);
+
+    // Delete
+    let response = request().method("DELETE").path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = request().method("DELETE").path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn revoke() {
+    let api = routes(state());
+
+    let response = request()
+        .method("POST")
+        .path("/backends/nil")
+        .reply(&api)
+        .await;
+    let keep: Keep = decode(response.body());
+    let path = Keep::path(&keep.uuid);
+
+    let revoke = format!("{}/revoke", path);
+    let response = request().method("POST").path(&revoke).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn export_import() {
+    let one = state();
+    let api = routes(one.clone());
+
+    for _ in 0..3 {
+        let response = request()
+            .method("POST")
+            .path("/backends/sgx")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = request().path("/keeps:export").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let backup = response.body().clone();
+    let export: Export = decode(&backup);
+    assert_eq!(export.keeps.len(), 3);
+
+    // Restore the backup into a fresh instance
+    let two = state();
+    let response = request()
+        .method("POST")
+        .path("/keeps:import")
+        .header(CONTENT_TYPE, "application/cbor")
+        .body(backup.clone())
+        .reply(&routes(two.clone()))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut expected = one.keeps.list();
+    let mut restored = two.keeps.list();
+    expected.sort_by_key(|k| k.uuid);
+    restored.sort_by_key(|k| k.uuid);
+    assert_eq!(restored, expected);
+
+    // Importing them again conflicts
+    let response = request()
+        .method("POST")
+        .path("/keeps:import")
+        .header(CONTENT_TYPE, "application/cbor")
+        .body(backup)
+        .reply(&routes(two))
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn import_ndjson() {
+    #[derive(Debug, serde::Deserialize)]
+    struct LineError {
+        line: usize,
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Imported {
+        imported: usize,
+        #[serde(default)]
+        errors: Vec<LineError>,
+    }
+
+    let one = state();
+    for _ in 0..3 {
+        let contract = one.contracts.get().iter().next().unwrap().clone();
+        one.keeps.create(&contract).unwrap();
+    }
+
+    // One keep to a line, with a blank line, a broken one and no final newline
+    let export = one.keeps.export();
+    let mut lines: Vec<String> = export
+        .keeps
+        .iter()
+        .map(|e| serde_json::to_string(e).unwrap())
+        .collect();
+    lines.insert(1, String::new());
+    lines.insert(3, "{\"keep\": 7}".into());
+    let body = lines.join("\n");
+
+    let two = state();
+    let response = request()
+        .method("POST")
+        .path("/keeps:import")
+        .header(CONTENT_TYPE, "application/x-ndjson")
+        .body(body.clone())
+        .reply(&routes(two.clone()))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let imported: Imported = decode(response.body());
+    assert_eq!(imported.imported, 3);
+    assert_eq!(imported.errors.len(), 1);
+    assert_eq!(imported.errors[0].line, 4);
+
+    let mut expected = one.keeps.list();
+    let mut restored = two.keeps.list();
+    expected.sort_by_key(|k| k.uuid);
+    restored.sort_by_key(|k| k.uuid);
+    assert_eq!(restored, expected);
+
+    // Importing them again fails line by line
+    let response = request()
+        .method("POST")
+        .path("/keeps:import")
+        .header(CONTENT_TYPE, "application/x-ndjson")
+        .body(body)
+        .reply(&routes(two))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let imported: Imported = decode(response.body());
+    assert_eq!(imported.imported, 0);
+    let lines: Vec<usize> = imported.errors.iter().map(|e| e.line).collect();
+    assert_eq!(lines, vec![1, 3, 4, 5]);
+}
+
+#[tokio::test]
+async fn tokens() {
+    let path = std::env::temp_dir().join(format!("tokens-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, r#"{"r": "reader"}"#).unwrap();
+    let tokens = Tokens::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let api = routes(AppState {
+        tokens: Some(Arc::new(tokens)),
+        ..state()
+    });
+
+    let response = request().path("/keeps").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = request()
+        .path("/keeps")
+        .header("authorization", "Bearer r")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request()
+        .method("POST")
+        .path("/backends/nil")
+        .header("authorization", "Bearer r")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn server_header() {
+    let server = concat!("contractmgr/", env!("CARGO_PKG_VERSION"));
+    let api = routes(state());
+
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[SERVER], server);
+
+    let response = request().path("/nowhere").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[SERVER], server);
+
+    let path = Keep::path(&uuid::Uuid::new_v4());
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[SERVER], server);
+
+    // The header can be suppressed
+    let api = routes(AppState {
+        server_header: false,
+        ..state()
+    });
+
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(SERVER).is_none());
+}
+
+#[tokio::test]
+async fn not_acceptable() {
+    let api = routes(state());
+
+    let response = request()
+        .path("/contracts")
+        .header(ACCEPT, "application/xml")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(response.headers()[CONTENT_TYPE], "text/plain");
+    assert_eq!(
+        response.body(),
+        "application/cbor\napplication/json\n".as_bytes()
+    );
+
+    // Requests without a preference get CBOR
+    for accept in &["*/*", "application/xml, */*;q=0.1", ""] {
+        let response = request()
+            .path("/contracts")
+            .header(ACCEPT, *accept)
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/cbor");
+    }
+}
+
+fn offering(contracts: &[Contract]) -> AppState {
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(contracts).unwrap()).unwrap();
+    let contracts = Contracts::load(Some(path.clone())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    AppState {
+        contracts: Arc::new(contracts),
+        ..state()
+    }
+}
+
+#[tokio::test]
+async fn no_contracts() {
+    let api = routes(offering(&[]));
+
+    // Offering nothing is a valid state, not an error
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/cbor");
+    assert!(decode::<Vec<Contract>>(response.body()).is_empty());
+
+    let response = request().path("/contracts/claimable").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(decode::<Vec<Contract>>(response.body()).is_empty());
+
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // There is nothing to claim
+    for backend in &["nil", "kvm"] {
+        let path = format!("/backends/{}", backend);
+        let response = request().method("POST").path(&path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    let path = format!("/contracts/{}", uuid::Uuid::nil());
+    let response = request().method("POST").path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn validity_window() {
+    let now = Utc::now();
+    let window = |nb: Option<i64>, na: Option<i64>| Contract {
+        not_before: nb.map(|h| now + Duration::hours(h)),
+        not_after: na.map(|h| now + Duration::hours(h)),
+        ..Contract::new(uuid::Uuid::new_v4(), Backend::Nil)
+    };
+
+    let before = window(Some(1), Some(2));
+    let during = window(Some(-1), Some(1));
+    let after = window(Some(-2), Some(-1));
+    let contracts = [before.clone(), during.clone(), after.clone()];
+    let api = routes(offering(&contracts));
+
+    for (contract, status) in &[
+        (&before, StatusCode::FORBIDDEN),
+        (&during, StatusCode::CREATED),
+        (&after, StatusCode::FORBIDDEN),
+    ] {
+        let path = format!("/contracts/{}", contract.uuid);
+        let response = request().method("POST").path(&path).reply(&api).await;
+        assert_eq!(response.status(), *status);
+    }
+
+    // Claiming by backend picks the contract which is currently valid
+    let response = request()
+        .method("POST")
+        .path("/backends/nil")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(decode::<Keep>(response.body()).contract, during);
+
+    // Expired contracts are listed unless hidden
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(decode::<Vec<Contract>>(response.body()), contracts);
+
+    let api = routes(AppState {
+        hide_expired: true,
+        ..offering(&contracts)
+    });
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(
+        decode::<Vec<Contract>>(response.body()),
+        vec![before, during]
+    );
+}
+
+fn same_backend() -> Vec<Contract> {
+    let contract = || Contract::new(uuid::Uuid::new_v4(), Backend::Nil);
+
+    vec![contract(), contract(), contract()]
+}
+
+async fn claim_nil(api: &AppState) -> Contract {
+    let response = request()
+        .method("POST")
+        .path("/backends/nil")
+        .reply(&routes(api.clone()))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    decode::<Keep>(response.body()).contract
+}
+
+#[tokio::test]
+async fn claim_policy_first() {
+    let contracts = same_backend();
+    let app = offering(&contracts);
+
+    for _ in 0..5 {
+        assert_eq!(claim_nil(&app).await, contracts[0]);
+    }
+}
+
+#[tokio::test]
+async fn claim_policy_random() {
+    let contracts = same_backend();
+    let app = AppState {
+        claim_policy: ClaimPolicy::Random,
+        ..offering(&contracts)
+    };
+
+    // The odds of 64 claims all landing on one contract are negligible
+    let mut chosen = std::collections::HashSet::new();
+    for _ in 0..64 {
+        let contract = claim_nil(&app).await;
+        assert!(contracts.contains(&contract));
+        chosen.insert(contract.uuid);
+    }
+    assert!(chosen.len() > 1);
+}
+
+#[tokio::test]
+async fn claim_policy_least_loaded() {
+    let contracts = same_backend();
+    let app = AppState {
+        claim_policy: ClaimPolicy::LeastLoaded,
+        ..offering(&contracts)
+    };
+
+    for contract in &[&contracts[0], &contracts[0], &contracts[1]] {
+        app.keeps.create(contract).unwrap();
+    }
+
+    // The emptiest contract is chosen, and ties go to the earliest
+    assert_eq!(claim_nil(&app).await, contracts[2]);
+    assert_eq!(claim_nil(&app).await, contracts[1]);
+    assert_eq!(claim_nil(&app).await, contracts[2]);
+
+    // Deleted keeps no longer count
+    for keep in app.keeps.list() {
+        if keep.contract == contracts[0] {
+            app.keeps.delete(&keep.uuid);
+        }
+    }
+    assert_eq!(claim_nil(&app).await, contracts[0]);
+}
+
+#[tokio::test]
+async fn delete_if_match() {
+    let app = state();
+    let api = routes(app.clone());
+
+    let response = request()
+        .method("POST")
+        .path("/backends/nil")
+        .reply(&api)
+        .await;
+    let keep: Keep = decode(response.body());
+    let path = Keep::path(&keep.uuid);
+
+    let response = request().path(&path).reply(&api).await;
+    let stale = response.headers()[ETAG].clone();
+
+    // Replace the keep behind the client's back
+    let export = app.keeps.export();
+    app.keeps.import(export.keeps, Conflict::Replace).unwrap();
+
+    let response = request()
+        .method("DELETE")
+        .path(&path)
+        .header(IF_MATCH, stale)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let fresh = response.headers()[ETAG].clone();
+
+    let response = request()
+        .method("DELETE")
+        .path(&path)
+        .header(IF_MATCH, fresh)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn capabilities() {
+    let get = |app: AppState| async move {
+        let response = request()
+            .path("/capabilities")
+            .header(ACCEPT, "application/json")
+            .reply(&routes(app))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        serde_json::from_slice::<serde_json::Value>(response.body()).unwrap()
+    };
+
+    let document = get(state()).await;
+    assert_eq!(
+        document,
+        serde_json::json!({
+            "encodings": ["application/cbor", "application/json"],
+            "auth": false,
+            "max_body": 16 * 1024 * 1024,
+            "max_keeps": null,
+            "max_keeps_per_owner": null,
+            "keep_ttl": null,
+            "soft_delete_retention": null,
+            "hide_expired": false,
+            "require_supported": false,
+            "claim_policy": "first",
+            "claim_cooldowns": {},
+        })
+    );
+
+    let path = std::env::temp_dir().join(format!("tokens-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, "{}").unwrap();
+    let tokens = Tokens::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut cooling = Contracts::load(None).unwrap().get().to_vec();
+    cooling[0].claim_cooldown = Some(30);
+    let uuid = cooling[0].uuid.to_string();
+
+    let document = get(AppState {
+        keeps: Arc::new(
+            KeepStore::new()
+                .capacity(5)
+                .per_owner(2)
+                .ttl(std::time::Duration::from_secs(60)),
+        ),
+        tokens: Some(Arc::new(tokens)),
+        hide_expired: true,
+        require_supported: true,
+        claim_policy: ClaimPolicy::LeastLoaded,
+        ..offering(&cooling)
+    })
+    .await;
+    assert_eq!(document["auth"], true);
+    assert_eq!(document["max_keeps"], 5);
+    assert_eq!(document["max_keeps_per_owner"], 2);
+    assert_eq!(document["keep_ttl"], 60);
+    assert_eq!(document["hide_expired"], true);
+    assert_eq!(document["require_supported"], true);
+    assert_eq!(document["claim_policy"], "least-loaded");
+    assert_eq!(document["claim_cooldowns"], serde_json::json!({ uuid: 30 }));
+
+    // A peer authorized by its Unix credentials needs no token
+    let document = get(AppState {
+        peer: Some(Role::Reader),
+        ..state()
+    })
+    .await;
+    assert_eq!(document["auth"], true);
+}
+
+#[tokio::test]
+async fn get_backends() {
+    let app = AppState {
+        probe: Arc::new(Plain),
+        ..state()
+    };
+    let response = request()
+        .path("/backends")
+        .header(ACCEPT, "application/json")
+        .reply(&routes(app))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let document: Vec<serde_json::Value> = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(document.len(), Backend::all().len());
+    for (entry, backend) in document.iter().zip(Backend::all()) {
+        assert_eq!(entry["name"], backend.as_str());
+        assert_eq!(entry["hint"], backend.display_hint());
+        assert!(entry["aliases"].is_array());
+        assert!(entry["tier"].is_string());
+        assert_eq!(entry["supported"], Plain.supports(backend));
+        assert!(entry.get("capacity").is_some());
+        match backend {
+            Backend::Nil => assert!(entry["device"].is_null()),
+            _ => assert!(entry["device"].as_str().unwrap().starts_with("/dev/")),
+        }
+    }
+
+    let sev = &document[1];
+    assert_eq!(
+        sev["aliases"],
+        serde_json::json!(["sev-es", "sev-snp", "snp"])
+    );
+    assert_eq!(sev["tier"], "confidential");
+    assert_eq!(sev["device"], "/dev/sev");
+}
+
+#[tokio::test]
+async fn soft_delete() {
+    let app = AppState {
+        keeps: Arc::new(KeepStore::new().retention(std::time::Duration::from_millis(200))),
+        ..state()
+    };
+    let api = routes(app.clone());
+
+    let create = || request().method("POST").path("/backends/nil").reply(&api);
+    let keep: Keep = decode(create().await.body());
+    let path = Keep::path(&keep.uuid);
+    let restore = format!("{}/restore", path);
+
+    // Deleted keeps are hidden until restored
+    let response = request().method("DELETE").path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = request().method("POST").path(&restore).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(decode::<Keep>(response.body()), keep);
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // After the retention period they are gone for good
+    let response = request().method("DELETE").path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+    let response = request().method("POST").path(&restore).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(app.keeps.purge(), 1);
+}
+
+/// Pretends the host can only run unencrypted keeps.
+#[derive(Debug)]
+struct Plain;
+
+impl Probe for Plain {
+    fn supports(&self, backend: &Backend) -> bool {
+        matches!(*backend, Backend::Nil | Backend::Kvm)
+    }
+}
+
+#[tokio::test]
+async fn get_contracts_supported() {
+    let api = routes(AppState {
+        probe: Arc::new(Plain),
+        ..state()
+    });
+
+    let response = request()
+        .path("/contracts?supported=true")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let contracts: Vec<Contract> = decode(response.body());
+    let mut backends: Vec<_> = contracts.iter().map(|c| c.backend.as_str()).collect();
+    backends.sort_unstable();
+    assert_eq!(backends, vec!["kvm", "nil"]);
+
+    for query in &["", "?supported=false"] {
+        let path = format!("/contracts{}", query);
+        let response = request().path(&path).reply(&api).await;
+        assert_eq!(decode::<Vec<Contract>>(response.body()).len(), 4);
+    }
+}
+
+#[tokio::test]
+async fn import_content_type() {
+    let app = state();
+    let api = routes(app.clone());
+    let export = Export {
+        version: Export::VERSION,
+        keeps: Vec::new(),
+    };
+
+    // Bodies must say what they are
+    let response = request()
+        .method("POST")
+        .path("/keeps:import")
+        .body(serde_json::to_vec(&export).unwrap())
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let response = request()
+        .method("POST")
+        .path("/keeps:import")
+        .header(CONTENT_TYPE, "text/plain")
+        .body(serde_json::to_vec(&export).unwrap())
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // JSON is accepted as well as CBOR
+    let response = request()
+        .method("POST")
+        .path("/keeps:import")
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(serde_json::to_vec(&export).unwrap())
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn import_too_deep() {
+    const LEVELS: usize = 100_000;
+
+    let api = routes(state());
+    let import = |kind: &'static str, body: Vec<u8>| {
+        request()
+            .method("POST")
+            .path("/keeps:import")
+            .header(CONTENT_TYPE, kind)
+            .body(body)
+    };
+
+    let json = format!("{}{}", "[".repeat(LEVELS), "]".repeat(LEVELS));
+    let response = import("application/json", json.into_bytes())
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Arrays of one item, nested, around a zero
+    let mut cbor = vec![0x81; LEVELS];
+    cbor.push(0x00);
+    let response = import("application/cbor", cbor).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // The limit is configurable
+    let export = Export {
+        version: Export::VERSION,
+        keeps: Vec::new(),
+    };
+    let body = serde_json::to_vec(&export).unwrap();
+
+    let response = import("application/json", body.clone()).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let api = routes(AppState {
+        max_depth: 1,
+        ..state()
+    });
+    let response = import("application/json", body).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Collects everything written to the log.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn logged_bodies() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let export = serde_json::json!({ "version": 1, "keeps": [], "token": "hunter2" });
+    let body = serde_json::to_vec(&export).unwrap();
+
+    // Bodies are not logged by default
+    let response = request()
+        .method("POST")
+        .path("/keeps:import")
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.clone())
+        .reply(&routes(state()))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!captured.text().contains("\"version\":1"));
+
+    // But both directions are when asked, without secrets
+    let response = request()
+        .method("POST")
+        .path("/keeps:import?conflict=skip")
+        .header(CONTENT_TYPE, "application/json")
+        .body(body)
+        .reply(&log_bodies(routes(state())))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(decode::<serde_json::Value>(response.body())["imported"], 0);
+
+    let logged = captured.text();
+    assert!(logged.contains("\"version\":1"));
+    assert!(logged.contains("\"imported\":0"));
+    assert!(logged.contains("[redacted]"));
+    assert!(!logged.contains("hunter2"));
+}
+
+#[tokio::test]
+async fn get_contracts_sort_cost() {
+    let priced = |cost| Contract {
+        cost,
+        ..Contract::new(uuid::Uuid::new_v4(), Backend::Nil)
+    };
+
+    let contracts = [priced(None), priced(Some(7)), priced(None), priced(Some(2))];
+    let api = routes(offering(&contracts));
+
+    // Cheapest first, with unknown costs last in their configured order
+    let response = request().path("/contracts?sort=cost").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let sorted: Vec<Contract> = decode(response.body());
+    let expected = [&contracts[3], &contracts[1], &contracts[0], &contracts[2]];
+    assert_eq!(sorted.iter().collect::<Vec<_>>(), expected);
+
+    let response = request()
+        .path("/contracts?sort=cost&fields=cost")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!([{ "cost": 2 }, { "cost": 7 }, {}, {}])
+    );
+}
+
+#[tokio::test]
+async fn evict_backend() {
+    let app = state();
+    let api = routes(app.clone());
+
+    for backend in &["nil", "sev", "sev", "kvm"] {
+        let path = format!("/backends/{}", backend);
+        let response = request().method("POST").path(&path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = request()
+        .method("DELETE")
+        .path("/keeps?backend=sev")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body, serde_json::json!({ "evicted": 2 }));
+
+    let mut left: Vec<_> = app
+        .keeps
+        .list()
+        .into_iter()
+        .map(|k| k.contract.backend)
+        .collect();
+    left.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    assert_eq!(left, vec![Backend::Kvm, Backend::Nil]);
+
+    // A backend must be named, and known
+    for path in &["/keeps", "/keeps?backend=sevv"] {
+        let response = request().method("DELETE").path(path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    assert_eq!(app.keeps.list().len(), 2);
+
+    // Only admins may evict
+    let path = std::env::temp_dir().join(format!("tokens-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, r#"{"w": "writer", "a": "admin"}"#).unwrap();
+    let tokens = Tokens::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let api = routes(AppState {
+        tokens: Some(Arc::new(tokens)),
+        ..app.clone()
+    });
+
+    for (token, status) in &[("w", StatusCode::FORBIDDEN), ("a", StatusCode::OK)] {
+        let response = request()
+            .method("DELETE")
+            .path("/keeps?backend=kvm")
+            .header("authorization", format!("Bearer {}", token))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), *status);
+    }
+
+    assert_eq!(app.keeps.list().len(), 1);
+}
+
+#[tokio::test]
+async fn evict_backend_alias() {
+    let app = state();
+    let api = routes(app.clone());
+
+    for backend in &["nil", "sev"] {
+        let path = format!("/backends/{}", backend);
+        let response = request().method("POST").path(&path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = request()
+        .method("DELETE")
+        .path("/keeps?backend=snp")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body, serde_json::json!({ "evicted": 1 }));
+
+    let left: Vec<_> = app.keeps.list().into_iter().map(|k| k.contract).collect();
+    assert_eq!(left.len(), 1);
+    assert_eq!(left[0].backend, Backend::Nil);
+}
+
+#[tokio::test]
+async fn healthz() {
+    let dir = std::env::temp_dir().join(format!("healthz-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+
+    let api = routes(AppState {
+        state_file: Some(StateFile::new(dir.join("state.cbor"))),
+        ..state()
+    });
+
+    for path in &["/healthz", "/healthz?deep=1"] {
+        let response = request().path(path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Make the state directory unwritable, even for root, by replacing it
+    std::fs::remove_dir(&dir).unwrap();
+    std::fs::write(&dir, b"").unwrap();
+
+    let response = request().path("/healthz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request().path("/healthz?deep=1").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    std::fs::remove_file(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn deadline() {
+    let api = routes(state());
+
+    let past = (Utc::now() - Duration::seconds(5)).to_rfc3339();
+    let response = request()
+        .path("/contracts")
+        .header("x-deadline", past)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+
+    let future = (Utc::now() + Duration::seconds(60)).to_rfc3339();
+    let response = request()
+        .path("/contracts")
+        .header("x-deadline", future)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request()
+        .path("/contracts")
+        .header("x-deadline", "tomorrow")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn stats() {
+    let app = state();
+    let api = routes(app.clone());
+    let contracts = app.contracts.get();
+
+    let path = format!("/contracts/{}", contracts[0].uuid);
+    for _ in 0..3 {
+        let response = request().method("POST").path(&path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let path = format!("/contracts/{}", contracts[1].uuid);
+    let response = request().method("POST").path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = request()
+        .path("/stats")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let stats: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(stats["window"], 60);
+    assert_eq!(stats["claims"][contracts[0].uuid.to_string()], 3);
+    assert_eq!(stats["claims"][contracts[1].uuid.to_string()], 1);
+    assert!(stats["claims"][contracts[2].uuid.to_string()].is_null());
+}
+
+#[tokio::test]
+async fn require_supported() {
+    let app = AppState {
+        probe: Arc::new(Plain),
+        require_supported: true,
+        ..state()
+    };
+    let api = routes(app.clone());
+
+    for contract in app.contracts.get().iter() {
+        let path = format!("/contracts/{}", contract.uuid);
+        let response = request().method("POST").path(&path).reply(&api).await;
+
+        match contract.backend {
+            Backend::Nil | Backend::Kvm => assert_eq!(response.status(), StatusCode::CREATED),
+            _ => assert_eq!(response.status(), StatusCode::CONFLICT),
+        }
+    }
+
+    assert_eq!(app.keeps.list().len(), 2);
+
+    // Without the flag, any contract can be claimed
+    let api = routes(AppState {
+        probe: Arc::new(Plain),
+        ..state()
+    });
+
+    let response = request()
+        .method("POST")
+        .path("/backends/sgx")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn get_contracts_claimable() {
+    let now = Utc::now();
+    let contract = |backend, nb: Option<i64>, na: Option<i64>| Contract {
+        not_before: nb.map(|h| now + Duration::hours(h)),
+        not_after: na.map(|h| now + Duration::hours(h)),
+        ..Contract::new(uuid::Uuid::new_v4(), backend)
+    };
+
+    let claimable = contract(Backend::Kvm, Some(-1), Some(1));
+    let contracts = [
+        claimable.clone(),
+        contract(Backend::Sgx, None, None),
+        contract(Backend::Nil, Some(1), None),
+        contract(Backend::Nil, None, Some(-1)),
+    ];
+
+    let app = AppState {
+        probe: Arc::new(Plain),
+        keeps: Arc::new(KeepStore::new().capacity(1)),
+        ..offering(&contracts)
+    };
+    let api = routes(app.clone());
+
+    let response = request().path("/contracts/claimable").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        decode::<Vec<Contract>>(response.body()),
+        vec![claimable.clone()]
+    );
+
+    // Nothing can be claimed once the server is full
+    app.keeps.create(&claimable).unwrap();
+    let response = request().path("/contracts/claimable").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(decode::<Vec<Contract>>(response.body()).is_empty());
+}
+
+#[tokio::test]
+async fn get_keeps_uuid_drift() {
+    let mut contracts = vec![
+        Contract {
+            cost: Some(1),
+            ..Contract::new(uuid::Uuid::new_v4(), Backend::Nil)
+        },
+        Contract::new(uuid::Uuid::new_v4(), Backend::Kvm),
+    ];
+
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&contracts).unwrap()).unwrap();
+    let app = AppState {
+        contracts: Arc::new(Contracts::load(Some(path.clone())).unwrap()),
+        ..state()
+    };
+    let api = routes(app.clone());
+
+    let changed = app.keeps.create(&contracts[0]).unwrap();
+    let removed = app.keeps.create(&contracts[1]).unwrap();
+
+    let drift = |uuid: uuid::Uuid| {
+        let api = api.clone();
+        async move {
+            let response = request()
+                .path(&format!("/keeps/{}/drift", uuid))
+                .header(ACCEPT, "application/json")
+                .reply(&api)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_slice::<serde_json::Value>(response.body()).unwrap()
+        }
+    };
+
+    // Nothing has changed yet
+    let report = drift(changed.uuid).await;
+    assert_eq!(report["gone"], false);
+    assert_eq!(report["fields"], serde_json::json!([]));
+
+    // Reprice and throttle one contract and withdraw the other
+    contracts[0].cost = Some(2);
+    contracts[0].claim_cooldown = Some(30);
+    contracts.truncate(1);
+    std::fs::write(&path, serde_json::to_vec(&contracts).unwrap()).unwrap();
+    app.contracts.reload().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let report = drift(changed.uuid).await;
+    assert_eq!(report["gone"], false);
+    assert_eq!(
+        report["fields"],
+        serde_json::json!(["cost", "claim_cooldown"])
+    );
+
+    let report = drift(removed.uuid).await;
+    assert_eq!(report["gone"], true);
+
+    // A UUID offered again for another backend is a different contract
+    contracts[0].backend = Backend::Kvm;
+    std::fs::write(&path, serde_json::to_vec(&contracts).unwrap()).unwrap();
+    app.contracts.reload().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let report = drift(changed.uuid).await;
+    assert_eq!(report["gone"], true);
+
+    let response = request()
+        .path(&format!("/keeps/{}/drift", uuid::Uuid::new_v4()))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn attestation_policy() {
+    let contract = Contract {
+        attestation_policy: Some((0..=255).collect()),
+        ..Contract::new(uuid::Uuid::new_v4(), Backend::Sev)
+    };
+    let api = routes(offering(std::slice::from_ref(&contract)));
+
+    let path = format!("/contracts/{}", contract.uuid);
+    for accept in &["application/cbor", "application/json"] {
+        let response = request()
+            .path(&path)
+            .header(ACCEPT, *accept)
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let returned: Contract = match *accept {
+            "application/json" => serde_json::from_slice(response.body()).unwrap(),
+            _ => decode(response.body()),
+        };
+        assert_eq!(returned, contract);
+    }
+
+    // The policy can be asked for on its own
+    let response = request()
+        .path("/contracts?fields=uuid,attestation_policy")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    let whole = serde_json::to_value(&contract).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!([{
+            "uuid": whole["uuid"],
+            "attestation_policy": whole["attestation_policy"],
+        }])
+    );
+
+    // Contracts without a policy don't mention it at all
+    let response = request()
+        .path("/contracts")
+        .header(ACCEPT, "application/json")
+        .reply(&routes(state()))
+        .await;
+    let body = String::from_utf8(response.body().to_vec()).unwrap();
+    assert!(!body.contains("attestation_policy"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn request_metrics_concurrent() {
+    const TASKS: usize = 8;
+    const ROUNDS: usize = 50;
+
+    let app = state();
+    let api = routes(app.clone());
+    let contract = app.contracts.get()[0].clone();
+
+    // Each round claims, lists and deletes a keep
+    let tasks: Vec<_> = (0..TASKS)
+        .map(|_| {
+            let api = api.clone();
+            let path = format!("/contracts/{}", contract.uuid);
+            tokio::spawn(async move {
+                for _ in 0..ROUNDS {
+                    let response = request().method("POST").path(&path).reply(&api).await;
+                    assert_eq!(response.status(), StatusCode::CREATED);
+                    let keep: Keep = decode(response.body());
+
+                    let response = request().path("/keeps").reply(&api).await;
+                    assert_eq!(response.status(), StatusCode::OK);
+
+                    let response = request()
+                        .method("DELETE")
+                        .path(&Keep::path(&keep.uuid))
+                        .reply(&api)
+                        .await;
+                    assert!(response.status().is_success());
+                }
+            })
+        })
+        .collect();
+
+    // Meanwhile, the store is used directly
+    let keeps = app.keeps.clone();
+    let direct = tokio::task::spawn_blocking(move || {
+        for _ in 0..TASKS * ROUNDS {
+            let keep = keeps.create(&contract).unwrap();
+            keeps.list();
+            keeps.delete(&keep.uuid).unwrap();
+        }
+    });
+
+    let all = async {
+        for task in tasks {
+            task.await.unwrap();
+        }
+        direct.await.unwrap();
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(30), all)
+        .await
+        .expect("requests and store operations deadlocked");
+
+    let total = (TASKS * ROUNDS * 3) as u64;
+    assert_eq!(app.requests.total(), total);
+    assert_eq!(app.requests.answered(StatusCode::OK), total);
+
+    let path = Keep::path(&uuid::Uuid::new_v4());
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(app.requests.answered(StatusCode::NOT_FOUND), 1);
+    assert_eq!(app.requests.total(), total + 1);
+}
+
+#[tokio::test]
+async fn decode_failures() {
+    let app = state();
+    let api = routes(app.clone());
+    let path = format!("/contracts/{}", app.contracts.get()[0].uuid);
+    assert_eq!(app.requests.decode_failures(), 0);
+
+    let response = request()
+        .method("POST")
+        .path(&path)
+        .header(CONTENT_TYPE, "application/json")
+        .body("{\"owner\": ")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(app.requests.decode_failures(), 1);
+
+    let response = request()
+        .method("POST")
+        .path("/keeps:import")
+        .header(CONTENT_TYPE, "application/cbor")
+        .body(vec![0xff, 0x00])
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(app.requests.decode_failures(), 2);
+
+    // An unsupported body wasn't decoded at all
+    let response = request()
+        .method("POST")
+        .path(&path)
+        .header(CONTENT_TYPE, "text/plain")
+        .body("owner")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(app.requests.decode_failures(), 2);
+    assert_eq!(app.requests.encode_failures(), 0);
+}
+
+#[tokio::test]
+async fn post_contracts_uuid_labels() {
+    let app = state();
+    let api = routes(app.clone());
+    let path = format!("/contracts/{}", app.contracts.get()[0].uuid);
+
+    let body = serde_json::json!({
+        "owner": "alice",
+        "labels": { "team": "infra", "env": "test" },
+    });
+    let response = request()
+        .method("POST")
+        .path(&path)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&body).unwrap())
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let keep: Keep = decode(response.body());
+    assert_eq!(keep.owner.as_deref(), Some("alice"));
+    assert_eq!(keep.labels["team"], "infra");
+    assert_eq!(keep.labels["env"], "test");
+    assert_eq!(app.keeps.get(&keep.uuid), Some(keep.clone()));
+
+    // Labels can be given in CBOR, and owner left out
+    let mut labels = std::collections::BTreeMap::new();
+    labels.insert("team", "infra");
+    let mut cbor = Vec::new();
+    ciborium::ser::into_writer(&serde_json::json!({ "labels": labels }), &mut cbor).unwrap();
+    let response = request()
+        .method("POST")
+        .path(&path)
+        .header(CONTENT_TYPE, "application/cbor")
+        .body(cbor)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let keep: Keep = decode(response.body());
+    assert_eq!(keep.owner, None);
+    assert_eq!(keep.labels.len(), 1);
+
+    // Without a body, the keep has no details
+    let response = request().method("POST").path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let keep: Keep = decode(response.body());
+    assert_eq!(keep.owner, None);
+    assert!(keep.labels.is_empty());
+
+    // Bodies must still be well-formed
+    let response = request()
+        .method("POST")
+        .path(&path)
+        .header(CONTENT_TYPE, "application/json")
+        .body("{\"labels\": [")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = request()
+        .method("POST")
+        .path(&path)
+        .header(CONTENT_TYPE, "text/plain")
+        .body("owner=alice")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(app.keeps.list().len(), 3);
+}
+
+#[tokio::test]
+async fn get_contracts_region() {
+    let located = |region: Option<&str>| Contract {
+        region: region.map(Into::into),
+        ..Contract::new(uuid::Uuid::new_v4(), Backend::Nil)
+    };
+
+    let contracts = [located(Some("eu")), located(None), located(Some("us"))];
+    let api = routes(offering(&contracts));
+
+    let response = request().path("/contracts?region=eu").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        decode::<Vec<Contract>>(response.body()),
+        vec![contracts[0].clone()]
+    );
+
+    let response = request().path("/contracts?region=ap").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(decode::<Vec<Contract>>(response.body()).is_empty());
+
+    // Without a region, every contract is listed
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(decode::<Vec<Contract>>(response.body()).len(), 3);
+
+    let response = request()
+        .path("/contracts?fields=region")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!([{ "region": "eu" }, {}, { "region": "us" }])
+    );
+}
+
+#[tokio::test]
+async fn get_contracts_params() {
+    use ciborium::value::Value;
+
+    let params = Value::Map(vec![(
+        Value::Text("mode".into()),
+        Value::Text("fast".into()),
+    )]);
+    let contracts = [
+        Contract {
+            params: Some(params),
+            ..Contract::new(uuid::Uuid::new_v4(), Backend::Sev)
+        },
+        Contract::new(uuid::Uuid::new_v4(), Backend::Nil),
+    ];
+    let api = routes(offering(&contracts));
+
+    let response = request()
+        .path("/contracts?fields=params")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!([{ "params": { "mode": "fast" } }, {}])
+    );
+}
+
+#[tokio::test]
+async fn claim_cooldown() {
+    let cooling = |cooldown| Contract {
+        claim_cooldown: cooldown,
+        ..Contract::new(uuid::Uuid::new_v4(), Backend::Nil)
+    };
+
+    let contracts = [cooling(Some(60)), cooling(None)];
+    let api = routes(offering(&contracts));
+    let claim = |contract: &Contract| {
+        request()
+            .method("POST")
+            .path(&format!("/contracts/{}", contract.uuid))
+            .reply(&api)
+    };
+
+    let response = claim(&contracts[0]).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // A second claim within the cooldown is throttled
+    let response = claim(&contracts[0]).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()[RETRY_AFTER]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 60);
+
+    // Contracts without a cooldown are claimed as often as asked
+    for _ in 0..2 {
+        let response = claim(&contracts[1]).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = request()
+        .path("/contracts?fields=claim_cooldown")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body, serde_json::json!([{ "claim_cooldown": 60 }, {}]));
+}
+
+#[tokio::test]
+async fn disabled() {
+    let toggled = |enabled| Contract {
+        enabled,
+        ..Contract::new(uuid::Uuid::new_v4(), Backend::Nil)
+    };
+
+    let contracts = [toggled(true), toggled(false)];
+    let api = routes(offering(&contracts));
+
+    // Disabled contracts are hidden unless asked for
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        decode::<Vec<Contract>>(response.body()),
+        vec![contracts[0].clone()]
+    );
+
+    let response = request()
+        .path("/contracts?include_disabled=1")
+        .reply(&api)
+        .await;
+    assert_eq!(decode::<Vec<Contract>>(response.body()), contracts.to_vec());
+
+    let response = request()
+        .path("/contracts?include_disabled=1&fields=enabled")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!([{ "enabled": true }, { "enabled": false }])
+    );
+
+    let response = request().path("/contracts/claimable").reply(&api).await;
+    assert_eq!(
+        decode::<Vec<Contract>>(response.body()),
+        vec![contracts[0].clone()]
+    );
+
+    // They can still be looked up, but not claimed
+    let path = format!("/contracts/{}", contracts[1].uuid);
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request().method("POST").path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let path = format!("/contracts/{}", contracts[0].uuid);
+    let response = request().method("POST").path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Claims by backend pass over the disabled contract
+    let response = request()
+        .method("POST")
+        .path("/backends/nil")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let keep: Keep = decode(response.body());
+    assert_eq!(keep.contract.uuid, contracts[0].uuid);
+}
+
+#[tokio::test]
+async fn listing_cache() {
+    let builtin = Contracts::load(None).unwrap().get().to_vec();
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&builtin).unwrap()).unwrap();
+
+    let app = AppState {
+        contracts: Arc::new(Contracts::load(Some(path.clone())).unwrap()),
+        ..state()
+    };
+    let api = routes(app.clone());
+
+    let list = |accept: &'static str| {
+        let api = api.clone();
+        async move {
+            let response = request()
+                .path("/contracts")
+                .header(ACCEPT, accept)
+                .reply(&api)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), accept);
+            response.into_body()
+        }
+    };
+
+    // Each encoding is listed the same way every time
+    let cbor = list("application/cbor").await;
+    let json = list("application/json").await;
+    assert_ne!(cbor, json);
+    for _ in 0..3 {
+        assert_eq!(list("application/cbor").await, cbor);
+        assert_eq!(list("application/json").await, json);
+    }
+    assert_eq!(decode::<Vec<Contract>>(&cbor), builtin);
+
+    // Until a reload, changes to the file aren't seen
+    std::fs::write(&path, serde_json::to_vec(&builtin[..1]).unwrap()).unwrap();
+    assert_eq!(list("application/cbor").await, cbor);
+
+    app.contracts.reload().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let reloaded = list("application/cbor").await;
+    assert_ne!(reloaded, cbor);
+    assert_eq!(decode::<Vec<Contract>>(&reloaded), builtin[..1].to_vec());
+    let body: Vec<Contract> = serde_json::from_slice(&list("application/json").await).unwrap();
+    assert_eq!(body, builtin[..1].to_vec());
+}
+
+#[tokio::test]
+async fn get_contracts_etag() {
+    let api = routes(state());
+
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers().get(ETAG).unwrap().clone();
+
+    // An unchanged listing isn't sent again
+    let response = request()
+        .path("/contracts")
+        .header(IF_NONE_MATCH, etag.clone())
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get(ETAG), Some(&etag));
+    assert!(response.body().is_empty());
+
+    // Each encoding is tagged apart
+    let response = request()
+        .path("/contracts")
+        .header(ACCEPT, "application/json")
+        .header(IF_NONE_MATCH, etag)
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn uri_too_long() {
+    let api = routes(state());
+
+    let path = format!("/contracts/{}", "a".repeat(4096));
+    let response = request().path(&path).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::URI_TOO_LONG);
+
+    // The query counts towards the length too
+    let api = routes(AppState {
+        max_uri_length: 24,
+        ..state()
+    });
+
+    let response = request().path("/contracts?region=eu").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request()
+        .path("/contracts?region=antarctica")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::URI_TOO_LONG);
+}
+
+/// Fails loudly whenever it is asked anything.
+#[derive(Debug)]
+struct Broken;
+
+impl Probe for Broken {
+    fn supports(&self, _: &Backend) -> bool {
+        panic!("probe failed")
+    }
+}
+
+#[tokio::test]
+async fn handler_panics() {
+    let api = routes(AppState {
+        probe: Arc::new(Broken),
+        ..state()
+    });
+
+    // The panic becomes a bare 500
+    let response = request()
+        .path("/contracts?supported=true")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(response.body().is_empty());
+
+    // Other requests are still answered
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn shutting_down() {
+    let app = AppState {
+        retry_after: 30,
+        ..state()
+    };
+    let api = routes(app.clone());
+
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Requests arriving once draining has begun are turned away
+    app.connections.drain();
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        response.headers().get(RETRY_AFTER),
+        Some(&HeaderValue::from_static("30"))
+    );
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({ "detail": "server shutting down" })
+    );
+
+    // The drain can still be watched
+    let response = request()
+        .path("/status/connections")
+        .header(ACCEPT, "application/json")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["draining"], true);
+}
+
+#[tokio::test]
+async fn loading() {
+    let builtin = Contracts::load(None).unwrap().get().to_vec();
+    let path = std::env::temp_dir().join(format!("contracts-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_vec(&builtin).unwrap()).unwrap();
+
+    let app = AppState {
+        contracts: Arc::new(Contracts::pending(path.clone(), false)),
+        retry_after: 2,
+        ..state()
+    };
+    let api = routes(app.clone());
+
+    // Until the contracts are loaded, requests are turned away
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        response.headers().get(RETRY_AFTER),
+        Some(&HeaderValue::from_static("2"))
+    );
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body, serde_json::json!({ "detail": "contracts loading" }));
+
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // The server is alive all the same
+    let response = request().path("/healthz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    app.contracts.reload().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request().path("/contracts").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed: Vec<Contract> = decode(response.body());
+    assert_eq!(listed, builtin);
+
+    // Draining makes the server unready again
+    app.connections.drain();
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn max_keeps_per_owner() {
+    let app = AppState {
+        keeps: Arc::new(KeepStore::new().per_owner(2)),
+        ..state()
+    };
+    let api = routes(app.clone());
+    let path = format!("/contracts/{}", app.contracts.get()[0].uuid);
+
+    let claim = |owner: Option<&str>| {
+        let body = match owner {
+            Some(owner) => serde_json::json!({ "owner": owner }),
+            None => serde_json::json!({}),
+        };
+
+        request()
+            .method("POST")
+            .path(&path)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&body).unwrap())
+            .reply(&api)
+    };
+
+    assert_eq!(claim(Some("alice")).await.status(), StatusCode::CREATED);
+    assert_eq!(claim(Some("alice")).await.status(), StatusCode::CREATED);
+    assert_eq!(
+        claim(Some("alice")).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Other owners, and keeps without one, are unaffected
+    assert_eq!(claim(Some("bob")).await.status(), StatusCode::CREATED);
+    assert_eq!(claim(None).await.status(), StatusCode::CREATED);
+    assert_eq!(claim(None).await.status(), StatusCode::CREATED);
+    assert_eq!(claim(None).await.status(), StatusCode::CREATED);
+
+    // Deleting one of alice's keeps makes room for another
+    let owned = app.keeps.list();
+    let alice = owned.iter().find(|k| k.owner.as_deref() == Some("alice"));
+    app.keeps.delete(&alice.unwrap().uuid).unwrap();
+    assert_eq!(claim(Some("alice")).await.status(), StatusCode::CREATED);
+    assert_eq!(
+        claim(Some("alice")).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use contractmgr::{serve, AppState, Contracts};
+use franca::{Keep, KeepStore};
+
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use warp::http::StatusCode;
+
+async fn spawn(state: AppState) -> String {
+    let socket = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let listen = TcpListener::from_std(socket).unwrap();
+    tokio::spawn(serve(TcpListenerStream::new(listen), state));
+    addr.to_string()
+}
+
+fn state() -> AppState {
+    AppState::new(Contracts::load(None).unwrap(), KeepStore::new())
+}
+
+#[tokio::test]
+async fn isolated() {
+    let one = state();
+    let two = state();
+    let host = spawn(one.clone()).await;
+    let other = spawn(two.clone()).await;
+
+    // Claim a keep from the first instance
+    let url = format!("http://{}/backends/nil", host);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let bytes = response.bytes().await.unwrap();
+    let keep: Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Only the first instance has it
+    assert_eq!(one.keeps.list(), vec![keep.clone()]);
+    assert!(two.keeps.list().is_empty());
+
+    let url = format!("http://{}/keeps/{}", other, keep.uuid);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
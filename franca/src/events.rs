@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::Keep;
+
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+/// The number of events kept for a subscriber which has fallen behind.
+///
+/// A subscriber further behind than this misses the oldest events and is
+/// told how many it missed.
+pub const BACKLOG: usize = 256;
+
+/// A change made to the keeps of a store.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The keep was created.
+    Created(Keep),
+
+    /// The keep was deleted, or hidden until its retention has passed.
+    Deleted(Keep),
+
+    /// The deleted keep was restored.
+    Restored(Keep),
+
+    /// The keep was revoked.
+    Revoked(Keep),
+
+    /// The keep was imported, replacing any keep with the same UUID.
+    Imported(Keep),
+}
+
+/// Delivers the events of a store to each of its subscribers.
+#[derive(Debug)]
+pub(crate) struct Events(Sender<Event>);
+
+impl Default for Events {
+    fn default() -> Self {
+        Self(broadcast::channel(BACKLOG).0)
+    }
+}
+
+impl Events {
+    /// Sends an event to the current subscribers, if there are any.
+    pub fn publish(&self, event: Event) {
+        let _ = self.0.send(event);
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.0.subscribe()
+    }
+}
@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+/// Generates the IDs of new keeps.
+pub trait IdScheme: Debug + Send + Sync {
+    fn generate(&self) -> Uuid;
+}
+
+/// Random (version 4) UUIDs, the default.
+#[derive(Debug, Default)]
+pub struct UuidV4;
+
+impl IdScheme for UuidV4 {
+    fn generate(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// ULIDs, carried in the UUID space.
+///
+/// The top 48 bits are the creation time in milliseconds since the Unix
+/// epoch and the rest are random, so keep IDs sort by creation time. IDs
+/// created within the same millisecond are made to increase monotonically.
+///
+/// The tradeoff is that these IDs reveal when a keep was created and have
+/// fewer random bits (80, rather than 122). They also don't carry the UUID
+/// version and variant bits, so UUID libraries may misreport their version.
+#[derive(Debug, Default)]
+pub struct Ulid {
+    last: Mutex<u128>,
+}
+
+impl IdScheme for Ulid {
+    fn generate(&self) -> Uuid {
+        const RANDOM: u128 = (1 << 80) - 1;
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let next = (millis << 80) | (Uuid::new_v4().as_u128() & RANDOM);
+
+        let mut last = self.last.lock().unwrap();
+        *last = if next > *last { next } else { *last + 1 };
+        Uuid::from_u128(*last)
+    }
+}
+
+/// The ID schemes which can be selected by name.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    UuidV4,
+    Ulid,
+}
+
+impl Scheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::UuidV4 => "uuidv4",
+            Scheme::Ulid => "ulid",
+        }
+    }
+
+    /// Creates a generator for the scheme.
+    pub fn build(self) -> Box<dyn IdScheme> {
+        match self {
+            Scheme::UuidV4 => Box::new(UuidV4),
+            Scheme::Ulid => Box::new(Ulid::default()),
+        }
+    }
+}
+
+impl std::str::FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uuidv4" => Ok(Scheme::UuidV4),
+            "ulid" => Ok(Scheme::Ulid),
+            _ => Err(format!("unknown ID scheme: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
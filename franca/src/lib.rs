@@ -2,13 +2,59 @@
 
 #![deny(clippy::all)]
 
+mod events;
+mod ids;
+mod store;
+
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-pub use koine::{Backend, Contract};
+pub use events::{Event, BACKLOG};
+pub use ids::{IdScheme, Scheme, Ulid, UuidV4};
+pub use koine::{Backend, Contract, Host, Probe, Tier, Timestamp};
+pub use store::{
+    Conflict, Conflicting, Export, Exported, Full, KeepStore, Snapshot, Stale, REVOCATIONS,
+};
+
+/// Hypermedia links to related resources.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Links {
+    /// The canonical path of the resource itself.
+    #[serde(rename = "self")]
+    pub this: String,
+}
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Keep {
     pub uuid: Uuid,
     pub contract: Contract,
+
+    /// Who the keep was created for, if anyone said.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    /// Free-form labels given when the keep was created.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+
+    /// When the store created the keep.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<Timestamp>,
+
+    /// How to reach the running keep, as its launcher reported it (e.g.
+    /// `vsock:3:1024` or `unix:/run/keeps/<uuid>.sock`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<String>,
+
+    #[serde(rename = "_links", default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<Links>,
+}
+
+impl Keep {
+    /// The canonical path of a keep.
+    pub fn path(uuid: &Uuid) -> String {
+        format!("/keeps/{}", uuid)
+    }
 }
@@ -0,0 +1,559 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::events::Events;
+use super::{Backend, Contract, Event, IdScheme, Keep, Links, Timestamp};
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::Receiver;
+use uuid::Uuid;
+
+/// The keeps of a store, which can be cloned cheaply as clones share
+/// structure until changed.
+type Keeps = im::HashMap<Uuid, Entry>;
+
+/// The maximum number of revoked keeps remembered by a store.
+pub const REVOCATIONS: usize = 1024;
+
+/// The store, or the owner of a keep, has reached its configured limit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Full {
+    /// The store holds as many live keeps as it may.
+    Store,
+
+    /// The owner holds as many live keeps as each owner may.
+    Owner,
+}
+
+/// An import contained keeps which already exist in the store.
+#[derive(Copy, Clone, Debug)]
+pub struct Conflicting;
+
+/// The keep has changed since the caller last saw it.
+#[derive(Copy, Clone, Debug)]
+pub struct Stale;
+
+/// How an import treats keeps which already exist in the store.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Conflict {
+    /// Keep the existing keep.
+    Skip,
+
+    /// Overwrite the existing keep.
+    Replace,
+
+    /// Abort the whole import.
+    Fail,
+}
+
+/// A keep along with its store metadata.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Exported {
+    pub keep: Keep,
+
+    /// The creation time of the keep, in seconds since the Unix epoch.
+    pub created: u64,
+}
+
+/// A versioned snapshot of all live keeps, for backup and migration.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Export {
+    pub version: u32,
+    pub keeps: Vec<Exported>,
+}
+
+impl Export {
+    /// The current version of the export format.
+    pub const VERSION: u32 = 1;
+}
+
+/// A time in whole seconds since the Unix epoch.
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    keep: Keep,
+
+    /// The wall-clock creation time, as exported and told to clients.
+    created: SystemTime,
+
+    /// When the store took the keep in, and how old it was then. Ages are
+    /// measured from these, so that moving the wall clock can't change them.
+    stored: Instant,
+    aged: Duration,
+
+    revision: u64,
+    deleted: Option<Instant>,
+}
+
+impl Entry {
+    fn new(keep: Keep, created: SystemTime, revision: u64) -> Self {
+        Self {
+            keep,
+            created,
+            stored: Instant::now(),
+            aged: created.elapsed().unwrap_or_default(),
+            revision,
+            deleted: None,
+        }
+    }
+
+    /// How long ago the keep was created.
+    fn age(&self) -> Duration {
+        self.aged + self.stored.elapsed()
+    }
+
+    /// An opaque tag which changes whenever the entry is replaced.
+    fn etag(&self) -> String {
+        format!("\"{:x}.{:x}\"", seconds(self.created), self.revision)
+    }
+}
+
+/// The most recently revoked keep UUIDs, oldest first.
+#[derive(Debug, Default)]
+struct Revoked {
+    order: VecDeque<Uuid>,
+    uuids: HashSet<Uuid>,
+}
+
+/// A thread-safe collection of keeps.
+///
+/// A store may optionally be limited in the number of keeps it holds and in
+/// how long each keep lives. Expired keeps are never returned and do not
+/// count against the capacity, but they occupy memory until purged.
+///
+/// Revoked keeps are removed from the store, but the store remembers the
+/// last `REVOCATIONS` of their UUIDs so that they can be reported as gone.
+///
+/// When a retention period is set, deleted keeps are hidden rather than
+/// removed, and can be restored until the period has passed.
+///
+/// Every change to the keeps is published as an [`Event`] to the store's
+/// subscribers, in the order the changes were made.
+#[derive(Debug, Default)]
+pub struct KeepStore {
+    keeps: RwLock<Keeps>,
+    revoked: RwLock<Revoked>,
+    capacity: Option<usize>,
+    per_owner: Option<usize>,
+    ttl: Option<Duration>,
+    retention: Option<Duration>,
+    revision: AtomicU64,
+    ids: Option<Box<dyn IdScheme>>,
+    events: Events,
+}
+
+impl KeepStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the number of live keeps in the store.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Limits the number of live keeps each owner may hold.
+    ///
+    /// Keeps without an owner are only limited by the store's capacity.
+    pub fn per_owner(mut self, limit: usize) -> Self {
+        self.per_owner = Some(limit);
+        self
+    }
+
+    /// Expires keeps once they are older than `ttl`.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Retains deleted keeps for `retention` so that they can be restored.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Generates the IDs of new keeps with `ids` rather than as random UUIDs.
+    pub fn ids(mut self, ids: Box<dyn IdScheme>) -> Self {
+        self.ids = Some(ids);
+        self
+    }
+
+    /// The maximum number of live keeps, if limited.
+    pub fn max_keeps(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// The maximum number of live keeps of each owner, if limited.
+    pub fn max_keeps_per_owner(&self) -> Option<usize> {
+        self.per_owner
+    }
+
+    /// The age at which keeps expire, if they do.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// How long deleted keeps can be restored for, if at all.
+    pub fn max_retention(&self) -> Option<Duration> {
+        self.retention
+    }
+
+    /// Subscribes to the events of every later change to the keeps.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    fn fresh(&self, entry: &Entry) -> bool {
+        match self.ttl {
+            Some(ttl) => entry.age() < ttl,
+            None => true,
+        }
+    }
+
+    fn live(&self, entry: &Entry) -> bool {
+        entry.deleted.is_none() && self.fresh(entry)
+    }
+
+    fn restorable(&self, entry: &Entry) -> bool {
+        match (entry.deleted, self.retention) {
+            (Some(deleted), Some(retention)) => deleted.elapsed() < retention && self.fresh(entry),
+            _ => false,
+        }
+    }
+
+    /// Deletes a live keep, or only hides it when deleted keeps are retained.
+    fn remove(&self, keeps: &mut Keeps, uuid: &Uuid) -> Option<Keep> {
+        if self.retention.is_none() {
+            let keep = keeps.remove(uuid).filter(|e| self.live(e))?.keep;
+            self.events.publish(Event::Deleted(keep.clone()));
+            return Some(keep);
+        }
+
+        let entry = keeps.get_mut(uuid).filter(|e| self.live(e))?;
+        entry.deleted = Some(Instant::now());
+        self.events.publish(Event::Deleted(entry.keep.clone()));
+        Some(entry.keep.clone())
+    }
+
+    /// Checks that the owner of a keep may hold another live keep.
+    fn allowed(&self, keeps: &Keeps, keep: &Keep) -> Result<(), Full> {
+        let (limit, owner) = match (self.per_owner, keep.owner.as_ref()) {
+            (Some(limit), Some(owner)) => (limit, owner),
+            _ => return Ok(()),
+        };
+
+        let owned = keeps
+            .values()
+            .filter(|e| self.live(e) && e.keep.owner.as_ref() == Some(owner))
+            .count();
+
+        match owned >= limit {
+            true => Err(Full::Owner),
+            false => Ok(()),
+        }
+    }
+
+    fn revise(&self) -> u64 {
+        self.revision.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Creates a new keep from the contract.
+    pub fn create(&self, contract: &Contract) -> Result<Keep, Full> {
+        self.create_with(contract, |_| ())
+    }
+
+    /// Creates a new keep from the contract, letting `init` fill in its
+    /// details before it is stored.
+    pub fn create_with<F>(&self, contract: &Contract, init: F) -> Result<Keep, Full>
+    where
+        F: FnOnce(&mut Keep),
+    {
+        let mut keeps = self.keeps.write().unwrap();
+
+        if let Some(capacity) = self.capacity {
+            keeps.retain(|_, e| self.live(e) || self.restorable(e));
+            if keeps.values().filter(|e| self.live(e)).count() >= capacity {
+                return Err(Full::Store);
+            }
+        }
+
+        let uuid = match self.ids {
+            Some(ref ids) => ids.generate(),
+            None => Uuid::new_v4(),
+        };
+        let created = SystemTime::now();
+        let mut keep = Keep {
+            uuid,
+            contract: contract.clone(),
+            owner: None,
+            labels: BTreeMap::new(),
+            created: Some(created.into()),
+            handle: None,
+            links: Some(Links {
+                this: Keep::path(&uuid),
+            }),
+        };
+        init(&mut keep);
+        self.allowed(&keeps, &keep)?;
+
+        let entry = Entry::new(keep.clone(), created, self.revise());
+
+        keeps.insert(keep.uuid, entry);
+        self.events.publish(Event::Created(keep.clone()));
+        Ok(keep)
+    }
+
+    /// Gets a single live keep.
+    pub fn get(&self, uuid: &Uuid) -> Option<Keep> {
+        let keeps = self.keeps.read().unwrap();
+        keeps
+            .get(uuid)
+            .filter(|e| self.live(e))
+            .map(|e| e.keep.clone())
+    }
+
+    /// Gets the entity tag of a single live keep.
+    pub fn etag(&self, uuid: &Uuid) -> Option<String> {
+        let keeps = self.keeps.read().unwrap();
+        keeps.get(uuid).filter(|e| self.live(e)).map(|e| e.etag())
+    }
+
+    /// Takes a consistent view of the store.
+    ///
+    /// The lock is only held while the map is cloned, which is cheap, so
+    /// changes can carry on while the snapshot is read.
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot {
+            store: self,
+            keeps: self.keeps.read().unwrap().clone(),
+        }
+    }
+
+    /// Lists all live keeps.
+    pub fn list(&self) -> Vec<Keep> {
+        self.snapshot().list()
+    }
+
+    /// Counts the live keeps of each contract, by contract UUID.
+    pub fn counts(&self) -> HashMap<Uuid, usize> {
+        let keeps = self.keeps.read().unwrap();
+        let mut counts = HashMap::new();
+        for entry in keeps.values().filter(|e| self.live(e)) {
+            *counts.entry(entry.keep.contract.uuid).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Deletes a single live keep.
+    pub fn delete(&self, uuid: &Uuid) -> Option<Keep> {
+        let mut keeps = self.keeps.write().unwrap();
+        self.remove(&mut keeps, uuid)
+    }
+
+    /// Deletes a single live keep if its entity tag is accepted.
+    pub fn delete_if<F>(&self, uuid: &Uuid, accept: F) -> Result<Option<Keep>, Stale>
+    where
+        F: FnOnce(&str) -> bool,
+    {
+        let mut keeps = self.keeps.write().unwrap();
+        match keeps.get(uuid).filter(|e| self.live(e)) {
+            None => Ok(None),
+            Some(entry) if !accept(&entry.etag()) => Err(Stale),
+            Some(..) => Ok(self.remove(&mut keeps, uuid)),
+        }
+    }
+
+    /// Deletes every live keep accepted by the filter, returning how many.
+    ///
+    /// No keeps can be created while this runs.
+    pub fn delete_all<F>(&self, filter: F) -> usize
+    where
+        F: Fn(&Keep) -> bool,
+    {
+        let mut keeps = self.keeps.write().unwrap();
+
+        let doomed: Vec<Uuid> = keeps
+            .values()
+            .filter(|e| self.live(e) && filter(&e.keep))
+            .map(|e| e.keep.uuid)
+            .collect();
+
+        for uuid in &doomed {
+            self.remove(&mut keeps, uuid);
+        }
+
+        doomed.len()
+    }
+
+    /// Restores a deleted keep which is still retained.
+    pub fn restore(&self, uuid: &Uuid) -> Result<Option<Keep>, Full> {
+        let mut keeps = self.keeps.write().unwrap();
+
+        match keeps.get(uuid) {
+            Some(entry) if self.restorable(entry) => self.allowed(&keeps, &entry.keep)?,
+            _ => return Ok(None),
+        }
+
+        if let Some(capacity) = self.capacity {
+            if keeps.values().filter(|e| self.live(e)).count() >= capacity {
+                return Err(Full::Store);
+            }
+        }
+
+        let entry = keeps.get_mut(uuid).unwrap();
+        entry.deleted = None;
+        self.events.publish(Event::Restored(entry.keep.clone()));
+        Ok(Some(entry.keep.clone()))
+    }
+
+    /// Revokes a single live keep.
+    ///
+    /// A deleted keep is left alone, so that it can still be restored.
+    pub fn revoke(&self, uuid: &Uuid) -> Option<Keep> {
+        let mut keeps = self.keeps.write().unwrap();
+        match keeps.get(uuid) {
+            Some(entry) if self.live(entry) => (),
+            _ => return None,
+        }
+        let keep = keeps.remove(uuid).unwrap().keep;
+
+        let mut revoked = self.revoked.write().unwrap();
+        if revoked.order.len() >= REVOCATIONS {
+            let oldest = revoked.order.pop_front().unwrap();
+            revoked.uuids.remove(&oldest);
+        }
+        revoked.order.push_back(keep.uuid);
+        revoked.uuids.insert(keep.uuid);
+
+        self.events.publish(Event::Revoked(keep.clone()));
+        Some(keep)
+    }
+
+    /// Indicates whether the keep was recently revoked.
+    pub fn is_revoked(&self, uuid: &Uuid) -> bool {
+        self.revoked.read().unwrap().uuids.contains(uuid)
+    }
+
+    /// Exports all live keeps.
+    pub fn export(&self) -> Export {
+        let snapshot = self.snapshot();
+        let keeps = snapshot
+            .keeps
+            .values()
+            .filter(|e| self.live(e))
+            .map(|e| Exported {
+                keep: e.keep.clone(),
+                created: seconds(e.created),
+            })
+            .collect();
+
+        Export {
+            version: Export::VERSION,
+            keeps,
+        }
+    }
+
+    /// Imports keeps, preserving their UUIDs and creation times.
+    ///
+    /// Imports are atomic and are not subject to the store's capacity.
+    /// Returns the number of keeps which were inserted.
+    pub fn import(
+        &self,
+        exported: Vec<Exported>,
+        conflict: Conflict,
+    ) -> Result<usize, Conflicting> {
+        let mut keeps = self.keeps.write().unwrap();
+
+        let exists = |keeps: &Keeps, uuid: &Uuid| match keeps.get(uuid) {
+            Some(entry) => self.live(entry),
+            None => false,
+        };
+
+        if conflict == Conflict::Fail && exported.iter().any(|e| exists(&keeps, &e.keep.uuid)) {
+            return Err(Conflicting);
+        }
+
+        let mut count = 0;
+        for mut e in exported {
+            if conflict == Conflict::Skip && exists(&keeps, &e.keep.uuid) {
+                continue;
+            }
+
+            e.keep.created = Some(Timestamp::from_secs(e.created));
+            let created = UNIX_EPOCH + Duration::from_secs(e.created);
+            let entry = Entry::new(e.keep, created, self.revise());
+
+            self.events.publish(Event::Imported(entry.keep.clone()));
+            keeps.insert(entry.keep.uuid, entry);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Checks that the store can still be changed, by adding a sentinel keep
+    /// and removing it again.
+    ///
+    /// The lock is held throughout, so the sentinel is never seen. This fails
+    /// once a thread has panicked while changing the store.
+    pub fn is_writable(&self) -> bool {
+        let mut keeps = match self.keeps.write() {
+            Ok(keeps) => keeps,
+            Err(..) => return false,
+        };
+
+        let uuid = Uuid::new_v4();
+        let keep = Keep {
+            uuid,
+            contract: Contract::new(Uuid::nil(), Backend::Nil),
+            owner: None,
+            labels: BTreeMap::new(),
+            created: None,
+            handle: None,
+            links: None,
+        };
+        let sentinel = Entry::new(keep, SystemTime::now(), 0);
+
+        let written = keeps.insert(uuid, sentinel).is_none() && keeps.remove(&uuid).is_some();
+        drop(keeps);
+
+        written && self.revoked.write().is_ok()
+    }
+
+    /// Removes all expired keeps and deleted keeps which are no longer
+    /// retained, returning how many were removed.
+    pub fn purge(&self) -> usize {
+        let mut keeps = self.keeps.write().unwrap();
+        let before = keeps.len();
+        keeps.retain(|_, e| self.live(e) || self.restorable(e));
+        before - keeps.len()
+    }
+}
+
+/// The keeps of a store at one moment, unaffected by later changes.
+pub struct Snapshot<'a> {
+    store: &'a KeepStore,
+    keeps: Keeps,
+}
+
+impl Snapshot<'_> {
+    /// Lists the keeps which were live when the snapshot was taken.
+    pub fn list(&self) -> Vec<Keep> {
+        self.keeps
+            .values()
+            .filter(|e| self.store.live(e))
+            .map(|e| e.keep.clone())
+            .collect()
+    }
+}
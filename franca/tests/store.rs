@@ -0,0 +1,430 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use franca::{
+    Backend, Conflict, Contract, Event, Export, Exported, Full, Keep, KeepStore, Scheme,
+    REVOCATIONS,
+};
+
+use tokio::sync::broadcast::error::TryRecvError;
+use uuid::Uuid;
+
+const CONTRACT: Contract = Contract::new(
+    Uuid::from_u128(0xe6234733_513a_4883_981a_bfa972fa706b),
+    Backend::Nil,
+);
+
+#[test]
+fn crud() {
+    let store = KeepStore::new();
+
+    let keep = store.create(&CONTRACT).unwrap();
+    assert_eq!(keep.contract, CONTRACT);
+    assert_eq!(store.get(&keep.uuid), Some(keep.clone()));
+    assert_eq!(store.list(), vec![keep.clone()]);
+
+    assert_eq!(store.delete(&keep.uuid), Some(keep.clone()));
+    assert_eq!(store.get(&keep.uuid), None);
+    assert_eq!(store.delete(&keep.uuid), None);
+    assert!(store.list().is_empty());
+}
+
+#[test]
+fn capacity() {
+    let store = KeepStore::new().capacity(2);
+
+    let first = store.create(&CONTRACT).unwrap();
+    store.create(&CONTRACT).unwrap();
+    assert!(store.create(&CONTRACT).is_err());
+
+    // Deleting a keep frees a slot.
+    store.delete(&first.uuid).unwrap();
+    store.create(&CONTRACT).unwrap();
+    assert_eq!(store.list().len(), 2);
+}
+
+#[test]
+fn ttl() {
+    let store = KeepStore::new().capacity(1).ttl(Duration::from_millis(100));
+
+    let keep = store.create(&CONTRACT).unwrap();
+    assert!(store.get(&keep.uuid).is_some());
+    assert!(store.create(&CONTRACT).is_err());
+    assert_eq!(store.purge(), 0);
+
+    thread::sleep(Duration::from_millis(150));
+
+    // Expired keeps are hidden...
+    assert_eq!(store.get(&keep.uuid), None);
+    assert!(store.list().is_empty());
+
+    // ... and don't count against the capacity.
+    let next = store.create(&CONTRACT).unwrap();
+    assert_eq!(store.list(), vec![next]);
+
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(store.purge(), 1);
+    assert_eq!(store.purge(), 0);
+}
+
+#[test]
+fn concurrency() {
+    const THREADS: usize = 8;
+    const KEEPS: usize = 100;
+
+    let store = Arc::new(KeepStore::new().capacity(THREADS * KEEPS));
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                (0..KEEPS)
+                    .map(|_| store.create(&CONTRACT).unwrap().uuid)
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let mut created = BTreeSet::new();
+    for thread in threads {
+        created.extend(thread.join().unwrap());
+    }
+
+    // Every keep is unique, listed and the store is now full.
+    assert_eq!(created.len(), THREADS * KEEPS);
+    let listed: BTreeSet<_> = store.list().into_iter().map(|k| k.uuid).collect();
+    assert_eq!(created, listed);
+    assert!(store.create(&CONTRACT).is_err());
+
+    let threads: Vec<_> = created
+        .into_iter()
+        .collect::<Vec<_>>()
+        .chunks(KEEPS)
+        .map(|chunk| {
+            let store = store.clone();
+            let chunk = chunk.to_vec();
+            thread::spawn(move || {
+                for uuid in chunk {
+                    assert!(store.delete(&uuid).is_some());
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert!(store.list().is_empty());
+}
+
+#[test]
+fn revoke() {
+    let store = KeepStore::new();
+
+    let keep = store.create(&CONTRACT).unwrap();
+    assert!(!store.is_revoked(&keep.uuid));
+    assert_eq!(store.revoke(&keep.uuid), Some(keep.clone()));
+    assert!(store.is_revoked(&keep.uuid));
+    assert_eq!(store.get(&keep.uuid), None);
+    assert_eq!(store.revoke(&keep.uuid), None);
+
+    // Only the most recent revocations are remembered.
+    for _ in 0..REVOCATIONS {
+        let other = store.create(&CONTRACT).unwrap();
+        store.revoke(&other.uuid).unwrap();
+        assert!(store.is_revoked(&other.uuid));
+    }
+    assert!(!store.is_revoked(&keep.uuid));
+}
+
+#[test]
+fn ttl_imported() {
+    let store = KeepStore::new().ttl(Duration::from_secs(60));
+    let keep = KeepStore::new().create(&CONTRACT).unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // An imported keep is as old as its creation time says
+    let stale = Exported {
+        keep: keep.clone(),
+        created: now - 120,
+    };
+    assert_eq!(store.import(vec![stale], Conflict::Fail).unwrap(), 1);
+    assert_eq!(store.get(&keep.uuid), None);
+
+    let fresh = Exported { keep, created: now };
+    assert_eq!(store.import(vec![fresh], Conflict::Fail).unwrap(), 1);
+    assert_eq!(store.list().len(), 1);
+}
+
+#[test]
+fn export_import() {
+    let store = KeepStore::new();
+    let keep = store.create(&CONTRACT).unwrap();
+
+    let export = store.export();
+    assert_eq!(export.version, Export::VERSION);
+    assert_eq!(export.keeps.len(), 1);
+    assert_eq!(export.keeps[0].keep, keep);
+
+    // Conflicts fail the import unless they are skipped or replaced.
+    assert!(store.import(export.keeps.clone(), Conflict::Fail).is_err());
+    assert_eq!(
+        store.import(export.keeps.clone(), Conflict::Skip).unwrap(),
+        0
+    );
+    assert_eq!(
+        store
+            .import(export.keeps.clone(), Conflict::Replace)
+            .unwrap(),
+        1
+    );
+
+    // An import into an empty store restores the keeps exactly.
+    let other = KeepStore::new();
+    assert_eq!(
+        other.import(export.keeps.clone(), Conflict::Fail).unwrap(),
+        1
+    );
+    assert_eq!(other.export(), export);
+}
+
+#[test]
+fn etag() {
+    let store = KeepStore::new();
+    let keep = store.create(&CONTRACT).unwrap();
+    let etag = store.etag(&keep.uuid).unwrap();
+    assert_eq!(store.etag(&keep.uuid).unwrap(), etag);
+
+    // Replacing the keep changes its tag.
+    let export = store.export();
+    store.import(export.keeps, Conflict::Replace).unwrap();
+    let fresh = store.etag(&keep.uuid).unwrap();
+    assert_ne!(fresh, etag);
+
+    // Only the current tag allows deletion.
+    assert!(store.delete_if(&keep.uuid, |t| t == etag).is_err());
+    assert_eq!(
+        store.delete_if(&keep.uuid, |t| t == fresh).unwrap(),
+        Some(keep.clone())
+    );
+    assert_eq!(store.delete_if(&keep.uuid, |_| true).unwrap(), None);
+    assert_eq!(store.etag(&keep.uuid), None);
+}
+
+#[test]
+fn soft_delete() {
+    let store = KeepStore::new()
+        .capacity(1)
+        .retention(Duration::from_millis(100));
+
+    // Deleted keeps are hidden but can be restored.
+    let keep = store.create(&CONTRACT).unwrap();
+    assert_eq!(store.delete(&keep.uuid), Some(keep.clone()));
+    assert_eq!(store.get(&keep.uuid), None);
+    assert!(store.list().is_empty());
+    assert_eq!(store.delete(&keep.uuid), None);
+    assert_eq!(store.restore(&keep.uuid).unwrap(), Some(keep.clone()));
+    assert_eq!(store.get(&keep.uuid), Some(keep.clone()));
+    assert_eq!(store.restore(&keep.uuid).unwrap(), None);
+
+    // Restoring cannot exceed the capacity.
+    store.delete(&keep.uuid).unwrap();
+    let other = store.create(&CONTRACT).unwrap();
+    assert!(store.restore(&keep.uuid).is_err());
+    store.delete(&other.uuid).unwrap();
+
+    // Once the retention period passes, they are gone for good.
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(store.restore(&keep.uuid).unwrap(), None);
+    assert_eq!(store.purge(), 2);
+}
+
+#[test]
+fn revoke_deleted() {
+    let store = KeepStore::new().retention(Duration::from_secs(60));
+    let mut events = store.subscribe();
+
+    // A deleted keep is not live, so there is nothing to revoke
+    let keep = store.create(&CONTRACT).unwrap();
+    store.delete(&keep.uuid).unwrap();
+    assert_eq!(store.revoke(&keep.uuid), None);
+    assert!(!store.is_revoked(&keep.uuid));
+
+    // It is still retained, and can be restored and then revoked
+    assert_eq!(store.restore(&keep.uuid).unwrap(), Some(keep.clone()));
+    assert_eq!(store.revoke(&keep.uuid), Some(keep.clone()));
+    assert!(store.is_revoked(&keep.uuid));
+    assert_eq!(store.restore(&keep.uuid).unwrap(), None);
+
+    let published: Vec<Event> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+    assert_eq!(
+        published,
+        vec![
+            Event::Created(keep.clone()),
+            Event::Deleted(keep.clone()),
+            Event::Restored(keep.clone()),
+            Event::Revoked(keep),
+        ]
+    );
+}
+
+#[test]
+fn writable() {
+    let store = KeepStore::new();
+    let keep = store.create(&CONTRACT).unwrap();
+    let mut events = store.subscribe();
+
+    // The check leaves no trace
+    assert!(store.is_writable());
+    assert_eq!(store.list(), vec![keep]);
+    assert_eq!(events.try_recv().unwrap_err(), TryRecvError::Empty);
+}
+
+#[test]
+fn delete_all() {
+    let store = KeepStore::new();
+    let sev = Contract {
+        backend: Backend::Sev,
+        ..CONTRACT
+    };
+
+    let nil = store.create(&CONTRACT).unwrap();
+    for _ in 0..3 {
+        store.create(&sev).unwrap();
+    }
+
+    assert_eq!(store.delete_all(|k| k.contract.backend == Backend::Sev), 3);
+    assert_eq!(store.list(), vec![nil]);
+    assert_eq!(store.delete_all(|k| k.contract.backend == Backend::Sev), 0);
+}
+
+#[test]
+fn ulid_ids() {
+    let store = KeepStore::new().ids(Scheme::Ulid.build());
+
+    // Some in the same millisecond, some across milliseconds
+    let mut created = Vec::new();
+    for i in 0..20 {
+        created.push(store.create(&CONTRACT).unwrap().uuid);
+        if i % 5 == 0 {
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    let mut sorted = created.clone();
+    sorted.sort();
+    assert_eq!(sorted, created);
+
+    // The creation time is in the top 48 bits
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let first = created[0].as_u128() >> 80;
+    assert!(first <= millis && millis - first < 60_000);
+}
+
+#[test]
+fn snapshot() {
+    const KEEPS: usize = 1000;
+
+    let store = Arc::new(KeepStore::new());
+    for _ in 0..KEEPS {
+        store.create(&CONTRACT).unwrap();
+    }
+
+    // A snapshot holds no lock, so creates carry on while it is read.
+    let snapshot = store.snapshot();
+    let before: BTreeSet<_> = snapshot.list().into_iter().map(|k| k.uuid).collect();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let lister = {
+        let store = store.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut last = 0;
+            while !done.load(Ordering::SeqCst) {
+                let listed = store.list().len();
+                assert!(listed >= last);
+                last = listed;
+            }
+        })
+    };
+
+    for _ in 0..KEEPS {
+        store.create(&CONTRACT).unwrap();
+    }
+
+    done.store(true, Ordering::SeqCst);
+    lister.join().unwrap();
+
+    // The snapshot is unchanged by the creates.
+    let after: BTreeSet<_> = snapshot.list().into_iter().map(|k| k.uuid).collect();
+    assert_eq!(before, after);
+    assert_eq!(after.len(), KEEPS);
+    assert_eq!(store.list().len(), KEEPS * 2);
+}
+
+#[test]
+fn per_owner() {
+    const THREADS: usize = 8;
+    const LIMIT: usize = 5;
+
+    let store = Arc::new(KeepStore::new().per_owner(LIMIT));
+    let owned = |owner: &str| {
+        let owner = owner.to_string();
+        move |keep: &mut Keep| keep.owner = Some(owner)
+    };
+
+    // However the creates race, the owner never exceeds the limit
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                (0..LIMIT)
+                    .filter(|_| store.create_with(&CONTRACT, owned("alice")).is_ok())
+                    .count()
+            })
+        })
+        .collect();
+
+    let created: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+    assert_eq!(created, LIMIT);
+    assert_eq!(
+        store.create_with(&CONTRACT, owned("alice")).unwrap_err(),
+        Full::Owner
+    );
+
+    // Other owners have their own allowance
+    assert!(store.create_with(&CONTRACT, owned("bob")).is_ok());
+    assert!(store.create(&CONTRACT).is_ok());
+}
+
+#[test]
+fn events() {
+    let store = KeepStore::new();
+    let mut events = store.subscribe();
+
+    let keep = store.create(&CONTRACT).unwrap();
+    assert_eq!(events.try_recv().unwrap(), Event::Created(keep.clone()));
+    assert_eq!(events.try_recv().unwrap_err(), TryRecvError::Empty);
+
+    // Failed changes publish nothing
+    assert_eq!(store.delete(&Uuid::nil()), None);
+    assert_eq!(events.try_recv().unwrap_err(), TryRecvError::Empty);
+
+    store.delete(&keep.uuid).unwrap();
+    assert_eq!(events.try_recv().unwrap(), Event::Deleted(keep));
+    assert_eq!(events.try_recv().unwrap_err(), TryRecvError::Empty);
+}
@@ -0,0 +1,19 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Parses arbitrary strings as backend names.
+//!
+//! Run with `cargo +nightly fuzz run backend`.
+
+#![no_main]
+
+use koine::Backend;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|name: &str| {
+    // A known name, or alias, always parses back to the same backend.
+    if let Ok(backend) = name.parse::<Backend>() {
+        assert_eq!(backend.as_str().parse::<Backend>().ok(), Some(backend));
+    }
+
+    let _ = Backend::parse_list(name);
+});
@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decodes arbitrary bytes as a contract, as the servers and client do.
+//!
+//! Run with `cargo +nightly fuzz run contract`.
+
+#![no_main]
+
+use koine::Contract;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Bad input must only ever be a clean error.
+    if let Ok(contract) = ciborium::de::from_reader::<Contract, _>(data) {
+        let mut buffer = Vec::new();
+        ciborium::ser::into_writer(&contract, &mut buffer).unwrap();
+    }
+});
@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decodes arbitrary bytes as a keep, as the client and imports do.
+//!
+//! Run with `cargo +nightly fuzz run keep`.
+
+#![no_main]
+
+use franca::Keep;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Bad input must only ever be a clean error.
+    if let Ok(keep) = ciborium::de::from_reader::<Keep, _>(data) {
+        let mut buffer = Vec::new();
+        ciborium::ser::into_writer(&keep, &mut buffer).unwrap();
+    }
+});
@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::launch::Keeps;
+use koine::{Backend, Probe};
+
+use serde::{Deserialize, Serialize};
+
+/// The most keeps of each backend this host is configured to run.
+///
+/// Written as a comma-separated list of `backend=count` pairs.
+#[derive(Clone, Debug, Default)]
+pub struct Limits(Vec<(Backend, usize)>);
+
+impl std::str::FromStr for Limits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = Self::default();
+
+        for pair in s.split(',') {
+            let mut parts = pair.splitn(2, '=');
+            let (name, count) = match (parts.next(), parts.next()) {
+                (Some(name), Some(count)) => (name, count),
+                _ => return Err(format!("expected backend=count: {}", pair)),
+            };
+
+            let backend: Backend = name
+                .trim()
+                .parse()
+                .map_err(|_| format!("unknown backend: {}", name))?;
+
+            let count = count
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid count: {}", count))?;
+
+            limits.set(backend, count);
+        }
+
+        Ok(limits)
+    }
+}
+
+impl std::fmt::Display for Limits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (backend, count)) in self.0.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(f, "{}{}={}", sep, backend, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How many more keeps of a backend the host can run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capacity {
+    pub backend: Backend,
+    pub configured: usize,
+    pub available: usize,
+}
+
+impl Limits {
+    fn set(&mut self, backend: Backend, count: usize) {
+        match self.0.iter_mut().find(|(b, _)| *b == backend) {
+            Some(limit) => limit.1 = count,
+            None => self.0.push((backend, count)),
+        }
+    }
+
+    /// Replaces the configured counts with those the hardware reports.
+    ///
+    /// Backends whose hardware says nothing keep their configured count.
+    pub fn detect(mut self, probe: &dyn Probe) -> Self {
+        for (backend, count) in probe.capacities() {
+            self.set(backend, count);
+        }
+
+        self
+    }
+
+    /// Reports the capacity of each configured backend the host supports.
+    ///
+    /// Keeps which are still running count against their backend's limit.
+    pub fn capacity(&self, probe: &dyn Probe, keeps: &Keeps) -> Vec<Capacity> {
+        self.0
+            .iter()
+            .filter(|(backend, _)| probe.supports(backend))
+            .map(|(backend, count)| Capacity {
+                backend: backend.clone(),
+                configured: *count,
+                available: count.saturating_sub(keeps.running(backend)),
+            })
+            .collect()
+    }
+}
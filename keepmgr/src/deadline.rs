@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::Infallible;
+use std::future::Future;
+
+use chrono::{DateTime, Utc};
+use warp::http::{HeaderMap, StatusCode};
+use warp::Filter;
+
+/// A request's `X-Deadline`, if it sent a readable one.
+pub type Deadline = Result<Option<DateTime<Utc>>, StatusCode>;
+
+/// Extracts the time after which the client no longer wants a response.
+pub fn header() -> impl Filter<Extract = (Deadline,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| {
+        let deadline = match headers.get("x-deadline") {
+            None => return Ok(None),
+            Some(deadline) => deadline,
+        };
+
+        deadline
+            .to_str()
+            .ok()
+            .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+            .map(|d| Some(d.with_timezone(&Utc)))
+            .ok_or(StatusCode::BAD_REQUEST)
+    })
+}
+
+/// Does the work, giving up with `408 Request Timeout` at the deadline.
+pub async fn within<T>(
+    deadline: Deadline,
+    work: impl Future<Output = Result<T, StatusCode>>,
+) -> Result<T, StatusCode> {
+    let deadline = match deadline? {
+        None => return work.await,
+        Some(deadline) => deadline,
+    };
+
+    let left = (deadline - Utc::now())
+        .to_std()
+        .map_err(|_| StatusCode::REQUEST_TIMEOUT)?;
+
+    match tokio::time::timeout(left, work).await {
+        Err(..) => Err(StatusCode::REQUEST_TIMEOUT),
+        Ok(result) => result,
+    }
+}
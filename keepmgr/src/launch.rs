@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use franca::Keep;
+use koine::{Backend, Contract};
+
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+use warp::http::StatusCode;
+
+/// The command which starts keeps of a backend.
+///
+/// Written as `backend=command`.
+#[derive(Clone, Debug)]
+pub struct Launcher {
+    backend: Backend,
+    command: String,
+}
+
+impl std::str::FromStr for Launcher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let (name, command) = match (parts.next(), parts.next()) {
+            (Some(name), Some(command)) if !command.is_empty() => (name, command),
+            _ => return Err(format!("expected backend=command: {}", s)),
+        };
+
+        let backend = name
+            .parse()
+            .map_err(|_| format!("unknown backend: {}", name))?;
+
+        Ok(Self {
+            backend,
+            command: command.into(),
+        })
+    }
+}
+
+impl std::fmt::Display for Launcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.backend, self.command)
+    }
+}
+
+/// How long a launcher has to report how to reach its keep.
+const HANDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A keep which has been asked for.
+#[derive(Debug)]
+pub enum Provisioned {
+    /// The keep was launched straight away.
+    Launched(Keep),
+
+    /// The keep will be launched once others have been.
+    Queued(Keep),
+}
+
+/// The keeps this host has launched, and the processes running them.
+///
+/// The number of keeps launching at once may be limited, in which case
+/// the rest wait their turn in the order they were asked for.
+#[derive(Debug, Default)]
+pub struct Keeps {
+    launchers: Vec<Launcher>,
+    children: Mutex<HashMap<Uuid, (Keep, Child)>>,
+    permits: Option<Arc<Semaphore>>,
+    queued: Mutex<HashMap<Uuid, Keep>>,
+}
+
+impl Keeps {
+    pub fn new(launchers: Vec<Launcher>) -> Self {
+        Self {
+            launchers,
+            ..Self::default()
+        }
+    }
+
+    /// Launches no more than `max` keeps at once.
+    pub fn max_provisioning(mut self, max: usize) -> Self {
+        self.permits = Some(Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// Starts a keep for the contract, or queues it to be started once a
+    /// launch finishes if too many keeps are already launching.
+    pub async fn provision(
+        self: &Arc<Self>,
+        contract: &Contract,
+    ) -> Result<Provisioned, StatusCode> {
+        let launcher = self.launcher(&contract.backend)?.clone();
+        let uuid = Uuid::new_v4();
+
+        let _permit = match self.permits {
+            None => None,
+            Some(ref permits) => match permits.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(..) => {
+                    let keep = self.enqueue(uuid, launcher, contract.clone(), permits.clone());
+                    return Ok(Provisioned::Queued(keep));
+                }
+            },
+        };
+
+        let keep = self.launch(uuid, &launcher, contract).await?;
+        Ok(Provisioned::Launched(keep))
+    }
+
+    /// Launches a keep once `permits` allow it.
+    ///
+    /// A queued keep which then fails to launch is logged and forgotten.
+    fn enqueue(
+        self: &Arc<Self>,
+        uuid: Uuid,
+        launcher: Launcher,
+        contract: Contract,
+        permits: Arc<Semaphore>,
+    ) -> Keep {
+        let keep = Keep {
+            uuid,
+            contract: contract.clone(),
+            owner: None,
+            labels: Default::default(),
+            created: None,
+            handle: None,
+            links: None,
+        };
+        self.queued.lock().unwrap().insert(uuid, keep.clone());
+
+        let keeps = self.clone();
+        tokio::spawn(async move {
+            let _permit = permits.acquire_owned().await.unwrap();
+            if let Err(code) = keeps.launch(uuid, &launcher, &contract).await {
+                tracing::warn!(keep = %uuid, "queued keep failed to launch: {}", code);
+            }
+            keeps.queued.lock().unwrap().remove(&uuid);
+        });
+
+        keep
+    }
+
+    /// Finds the launcher of a backend, the last given if there are several.
+    fn launcher(&self, backend: &Backend) -> Result<&Launcher, StatusCode> {
+        self.launchers
+            .iter()
+            .rev()
+            .find(|l| &l.backend == backend)
+            .ok_or(StatusCode::CONFLICT)
+    }
+
+    /// Starts a keep for the contract with the backend's launcher.
+    ///
+    /// The launcher is passed the keep and contract UUIDs as arguments, and
+    /// again, along with the backend, in `KEEP_UUID`, `KEEP_CONTRACT` and
+    /// `KEEP_BACKEND`. The first line it prints, if it prints one within
+    /// `HANDLE_TIMEOUT`, becomes the keep's handle; the rest is logged.
+    async fn launch(
+        &self,
+        uuid: Uuid,
+        launcher: &Launcher,
+        contract: &Contract,
+    ) -> Result<Keep, StatusCode> {
+        let mut child = Command::new(&launcher.command)
+            .arg(uuid.to_string())
+            .arg(contract.uuid.to_string())
+            .env("KEEP_UUID", uuid.to_string())
+            .env("KEEP_CONTRACT", contract.uuid.to_string())
+            .env("KEEP_BACKEND", contract.backend.as_str())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                tracing::error!("failed to launch {}: {}", launcher, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let handle = match tokio::time::timeout(HANDLE_TIMEOUT, lines.next_line()).await {
+            Ok(Ok(Some(line))) if !line.trim().is_empty() => Some(line.trim().to_string()),
+            _ => None,
+        };
+
+        // Keep draining the output so the launcher never blocks writing it
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::debug!(keep = %uuid, "{}", line);
+            }
+        });
+
+        let keep = Keep {
+            uuid,
+            contract: contract.clone(),
+            owner: None,
+            labels: Default::default(),
+            created: None,
+            handle,
+            links: None,
+        };
+
+        let mut children = self.children.lock().unwrap();
+        children.insert(uuid, (keep.clone(), child));
+        Ok(keep)
+    }
+
+    /// Finds a keep which is still running.
+    pub fn get(&self, uuid: &Uuid) -> Option<Keep> {
+        let mut children = self.children.lock().unwrap();
+        children.retain(|_, (_, child)| matches!(child.try_wait(), Ok(None)));
+        children.get(uuid).map(|(keep, _)| keep.clone())
+    }
+
+    /// Finds a keep which is waiting to be launched.
+    pub fn queued(&self, uuid: &Uuid) -> Option<Keep> {
+        self.queued.lock().unwrap().get(uuid).cloned()
+    }
+
+    /// Counts the keeps of a backend which are still running.
+    pub fn running(&self, backend: &Backend) -> usize {
+        let mut children = self.children.lock().unwrap();
+        children.retain(|_, (_, child)| matches!(child.try_wait(), Ok(None)));
+        children
+            .values()
+            .filter(|(k, _)| &k.contract.backend == backend)
+            .count()
+    }
+}
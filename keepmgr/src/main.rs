@@ -2,59 +2,69 @@
 
 #![deny(clippy::all)]
 
-use koine::{Backend, Contract};
+mod capacity;
+mod deadline;
+mod launch;
+mod upstream;
+
+use capacity::Limits;
+use franca::Keep;
+use koine::{Backend, Contract, Host, Probe};
+use launch::{Keeps, Launcher, Provisioned};
+use upstream::Upstream;
+
+use std::convert::Infallible;
+use std::sync::Arc;
 
 use serde::Serialize;
 use structopt::StructOpt;
 use tokio::net::{TcpListener, UnixListener};
 use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
 use uuid::Uuid;
-use warp::http::header::CONTENT_TYPE;
+use warp::http::header::{CONTENT_TYPE, LOCATION};
 use warp::http::{Response, StatusCode};
 use warp::Filter;
 
 const CONTRACTS: &[Contract] = &[
-    Contract {
-        uuid: Uuid::from_u128(0xe6234733_513a_4883_981a_bfa972fa706b),
-        backend: Backend::Nil,
-    },
-    Contract {
-        uuid: Uuid::from_u128(0x0afa438e_acaa_4158_9518_ad59256def34),
-        backend: Backend::Kvm,
-    },
-    Contract {
-        uuid: Uuid::from_u128(0x31a41b53_cb9e_447b_bfa2_bfb8e6e42ff9),
-        backend: Backend::Sev,
-    },
-    Contract {
-        uuid: Uuid::from_u128(0xea392851_3435_42d3_a4ad_c4e5e5c6c4c6),
-        backend: Backend::Sgx,
-    },
+    Contract::new(
+        Uuid::from_u128(0xe6234733_513a_4883_981a_bfa972fa706b),
+        Backend::Nil,
+    ),
+    Contract::new(
+        Uuid::from_u128(0x0afa438e_acaa_4158_9518_ad59256def34),
+        Backend::Kvm,
+    ),
+    Contract::new(
+        Uuid::from_u128(0x31a41b53_cb9e_447b_bfa2_bfb8e6e42ff9),
+        Backend::Sev,
+    ),
+    Contract::new(
+        Uuid::from_u128(0xea392851_3435_42d3_a4ad_c4e5e5c6c4c6),
+        Backend::Sgx,
+    ),
 ];
 
-trait ContractExt {
-    fn is_supported(&self) -> bool;
+#[derive(Debug)]
+enum Listener {
+    Unix(std::os::unix::net::UnixListener),
+    Tcp(std::net::TcpListener),
 }
 
-impl ContractExt for Contract {
-    fn is_supported(&self) -> bool {
-        use std::path::Path;
+impl std::fmt::Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Error;
+
+        match self {
+            Listener::Unix(socket) => match socket.local_addr().map_err(|_| Error)?.as_pathname() {
+                Some(path) => write!(f, "{}", path.display()),
+                None => write!(f, "(unnamed)"),
+            },
 
-        match self.backend {
-            Backend::Nil => true,
-            Backend::Kvm => Path::new("/dev/kvm").exists(),
-            Backend::Sev => Path::new("/dev/sev").exists(),
-            Backend::Sgx => Path::new("/dev/sgx_enclave").exists(),
+            Listener::Tcp(socket) => write!(f, "{}", socket.local_addr().map_err(|_| Error)?),
         }
     }
 }
 
-#[derive(Debug)]
-enum Listener {
-    Unix(std::os::unix::net::UnixListener),
-    Tcp(std::net::TcpListener),
-}
-
 impl std::str::FromStr for Listener {
     type Err = std::io::Error;
 
@@ -86,6 +96,68 @@ struct Options {
     /// The listening socket address or fd
     #[structopt(default_value = "[::]:3030")]
     listen: Listener,
+
+    /// The contractmgr base URL (defaults to built-in contracts)
+    #[structopt(long)]
+    upstream: Option<String>,
+
+    /// The most keeps of each backend this host can run (e.g. sev=4,sgx=8)
+    #[structopt(long)]
+    capacity: Option<Limits>,
+
+    /// Use the capacity the hardware reports, falling back to --capacity
+    #[structopt(long)]
+    auto_capacity: bool,
+
+    /// The command which starts keeps of a backend (e.g. sev=/usr/bin/sev-keep)
+    #[structopt(long, number_of_values = 1)]
+    launcher: Vec<Launcher>,
+
+    /// The most keeps launching at once, queuing the rest (unlimited by default)
+    #[structopt(long)]
+    max_provisioning: Option<usize>,
+
+    /// Print the effective configuration and exit
+    #[structopt(long)]
+    print_config: bool,
+}
+
+/// The effective configuration, reported at startup.
+#[derive(Debug, Serialize)]
+struct Config {
+    listen: String,
+    upstream: Option<String>,
+    capacity: Option<String>,
+    auto_capacity: bool,
+    launcher: Vec<String>,
+    max_provisioning: Option<usize>,
+}
+
+impl From<&Options> for Config {
+    fn from(options: &Options) -> Self {
+        Self {
+            listen: options.listen.to_string(),
+            upstream: options.upstream.clone(),
+            capacity: options.capacity.as_ref().map(|c| c.to_string()),
+            auto_capacity: options.auto_capacity,
+            launcher: options.launcher.iter().map(|l| l.to_string()).collect(),
+            max_provisioning: options.max_provisioning,
+        }
+    }
+}
+
+impl Config {
+    fn log(&self) {
+        tracing::info!(
+            listen = %self.listen,
+            upstream = ?self.upstream,
+            capacity = ?self.capacity,
+            auto_capacity = self.auto_capacity,
+            launcher = ?self.launcher,
+            max_provisioning = ?self.max_provisioning,
+            "starting keepmgr"
+        );
+    }
 }
 
 fn cborize<T: Serialize>(item: &T) -> Vec<u8> {
@@ -98,69 +170,208 @@ fn error(code: StatusCode) -> Response<Vec<u8>> {
     Response::builder().status(code).body(Vec::new()).unwrap()
 }
 
-async fn serve<I>(incoming: I) -> tokio::io::Result<()>
+/// A contract, annotated with whether this host can run its keeps.
+#[derive(Debug, Serialize)]
+struct Annotated<'a> {
+    #[serde(flatten)]
+    contract: &'a Contract,
+    supported: bool,
+}
+
+impl<'a> From<&'a Contract> for Annotated<'a> {
+    fn from(contract: &'a Contract) -> Self {
+        Self {
+            contract,
+            supported: Host.supports(&contract.backend),
+        }
+    }
+}
+
+/// Fetches all the contracts, whether or not this host supports them.
+async fn contracts(upstream: Option<Arc<Upstream>>) -> Result<Arc<Vec<Contract>>, StatusCode> {
+    Ok(match upstream {
+        Some(upstream) => upstream.contracts().await?,
+        None => Arc::new(CONTRACTS.to_vec()),
+    })
+}
+
+/// Fetches the contracts supported on this host.
+async fn supported(upstream: Option<Arc<Upstream>>) -> Result<Vec<Contract>, StatusCode> {
+    Ok(contracts(upstream)
+        .await?
+        .iter()
+        .filter(|c| Host.supports(&c.backend))
+        .cloned()
+        .collect())
+}
+
+async fn serve<I>(
+    incoming: I,
+    upstream: Option<Arc<Upstream>>,
+    limits: Limits,
+    keeps: Arc<Keeps>,
+) -> tokio::io::Result<()>
 where
     I: futures_core::stream::TryStream + Send,
     I::Ok: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static + Unpin,
     I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
 {
-    // Client is requesting details of all contracts.
+    let upstream = warp::any().map(move || upstream.clone());
+    let keeps = warp::any().map(move || keeps.clone());
+
+    // Client is requesting details of all contracts, and whether this host
+    // supports each of them.
     let get_contracts = warp::path!("contracts")
         .and(warp::filters::method::get())
-        .map(|| {
-            // TODO: fetch contracts from the contractmgr
-            let contracts: Vec<Contract> = CONTRACTS
-                .iter()
-                .cloned()
-                .filter(Contract::is_supported)
-                .collect();
-
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(CONTENT_TYPE, "application/cbor")
-                .body(cborize(&contracts))
-                .unwrap()
+        .and(upstream.clone())
+        .and(deadline::header())
+        .and_then(|upstream, deadline| async move {
+            Ok::<_, Infallible>(
+                match deadline::within(deadline, contracts(upstream)).await {
+                    Err(code) => error(code),
+                    Ok(contracts) => {
+                        let annotated: Vec<Annotated> = contracts.iter().map(Into::into).collect();
+                        Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, "application/cbor")
+                            .body(cborize(&annotated))
+                            .unwrap()
+                    }
+                },
+            )
         });
 
-    // Client is requesting details of a single contract.
+    // Client is requesting details of a single contract, and whether this
+    // host supports it.
     let get_contracts_uuid = warp::path!("contracts" / Uuid)
         .and(warp::filters::method::get())
-        .map(|cuuid| {
-            // TODO: fetch contracts from the contractmgr
-            let contracts: Vec<Contract> = CONTRACTS
-                .iter()
-                .cloned()
-                .filter(Contract::is_supported)
-                .collect();
-
-            match contracts.iter().find(|c| c.uuid == cuuid) {
+        .and(upstream.clone())
+        .and(deadline::header())
+        .and_then(|cuuid, upstream, deadline| async move {
+            let contracts = match deadline::within(deadline, contracts(upstream)).await {
+                Err(code) => return Ok::<_, Infallible>(error(code)),
+                Ok(contracts) => contracts,
+            };
+
+            Ok(match contracts.iter().find(|c| c.uuid == cuuid) {
                 None => error(StatusCode::NOT_FOUND),
                 Some(contract) => Response::builder()
                     .status(StatusCode::OK)
                     .header(CONTENT_TYPE, "application/cbor")
-                    .body(cborize(&contract))
+                    .body(cborize(&Annotated::from(contract)))
                     .unwrap(),
-            }
+            })
+        });
+
+    // Client is starting a keep under a contract.
+    let post_contracts_uuid = warp::path!("contracts" / Uuid)
+        .and(warp::filters::method::post())
+        .and(upstream)
+        .and(keeps.clone())
+        .and_then(|cuuid, upstream, keeps: Arc<Keeps>| async move {
+            let contracts = match supported(upstream).await {
+                Err(code) => return Ok::<_, Infallible>(error(code)),
+                Ok(contracts) => contracts,
+            };
+
+            let contract = match contracts.iter().find(|c| c.uuid == cuuid) {
+                None => return Ok(error(StatusCode::NOT_FOUND)),
+                Some(contract) => contract,
+            };
+
+            let (status, keep) = match keeps.provision(contract).await {
+                Err(code) => return Ok(error(code)),
+                Ok(Provisioned::Launched(keep)) => (StatusCode::CREATED, keep),
+                Ok(Provisioned::Queued(keep)) => (StatusCode::ACCEPTED, keep),
+            };
+
+            // A queued keep can be followed at its URL until it is launched.
+            Ok(Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "application/cbor")
+                .header(LOCATION, Keep::path(&keep.uuid))
+                .body(cborize(&keep))
+                .unwrap())
+        });
+
+    // Client is requesting details of a keep this host is running, or is
+    // waiting to launch.
+    let get_keeps_uuid = warp::path!("keeps" / Uuid)
+        .and(warp::filters::method::get())
+        .and(keeps.clone())
+        .map(|kuuid, keeps: Arc<Keeps>| {
+            let (status, keep) = match (keeps.get(&kuuid), keeps.queued(&kuuid)) {
+                (Some(keep), _) => (StatusCode::OK, keep),
+                (None, Some(keep)) => (StatusCode::ACCEPTED, keep),
+                (None, None) => return error(StatusCode::NOT_FOUND),
+            };
+
+            Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "application/cbor")
+                .body(cborize(&keep))
+                .unwrap()
         });
 
-    let routes = get_contracts.or(get_contracts_uuid);
+    // Client is asking how many more keeps this host can run.
+    let get_capacity = warp::path!("capacity")
+        .and(warp::filters::method::get())
+        .and(keeps)
+        .map(move |keeps: Arc<Keeps>| {
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, "application/cbor")
+                .body(cborize(&limits.capacity(&Host, &keeps)))
+                .unwrap()
+        });
+
+    let routes = get_contracts
+        .or(get_contracts_uuid)
+        .or(post_contracts_uuid)
+        .or(get_keeps_uuid)
+        .or(get_capacity);
     warp::serve(routes).run_incoming(incoming).await;
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
-    match Options::from_args().listen {
+    let options = Options::from_args();
+
+    let config = Config::from(&options);
+    if options.print_config {
+        println!("{}", serde_json::to_string_pretty(&config).unwrap());
+        return Ok(());
+    }
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+    config.log();
+
+    let upstream = options.upstream.map(|url| Arc::new(Upstream::new(url)));
+    let mut limits = options.capacity.unwrap_or_default();
+    if options.auto_capacity {
+        limits = limits.detect(&Host);
+        tracing::info!(capacity = %limits, "detected capacity");
+    }
+    let mut keeps = Keeps::new(options.launcher);
+    if let Some(max) = options.max_provisioning {
+        keeps = keeps.max_provisioning(max);
+    }
+    let keeps = Arc::new(keeps);
+
+    match options.listen {
         Listener::Unix(socket) => {
             let listen = UnixListener::from_std(socket)?;
             let stream = UnixListenerStream::new(listen);
-            serve(stream).await
+            serve(stream, upstream, limits, keeps).await
         }
 
         Listener::Tcp(socket) => {
             let listen = TcpListener::from_std(socket)?;
             let stream = TcpListenerStream::new(listen);
-            serve(stream).await
+            serve(stream, upstream, limits, keeps).await
         }
     }
 }
@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use koine::Contract;
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use warp::http::StatusCode;
+use warp::hyper::client::HttpConnector;
+use warp::hyper::{body::to_bytes, Client};
+
+/// How long fetched contracts are served before they are fetched again.
+const FRESHNESS: Duration = Duration::from_secs(30);
+
+type Fetched = Result<Arc<Vec<Contract>>, StatusCode>;
+
+/// A cached view of the contracts offered by a contractmgr.
+///
+/// Requests which find the cache fresh never wait on the contractmgr.
+/// Concurrent requests that miss the cache share a single upstream fetch,
+/// including its failure, so at most one request to the contractmgr is ever
+/// in flight.
+pub struct Upstream {
+    url: String,
+    client: Client<HttpConnector>,
+    cache: RwLock<Option<(Instant, Arc<Vec<Contract>>)>>,
+    inflight: Mutex<Option<Shared<BoxFuture<'static, Fetched>>>>,
+}
+
+async fn fetch(client: Client<HttpConnector>, url: String) -> Fetched {
+    let uri = format!("{}/contracts", url)
+        .parse()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let response = client.get(uri).await.map_err(|_| StatusCode::BAD_GATEWAY)?;
+    if response.status() != StatusCode::OK {
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+
+    let body = to_bytes(response.into_body())
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    ciborium::de::from_reader(&body[..])
+        .map(Arc::new)
+        .map_err(|_| StatusCode::BAD_GATEWAY)
+}
+
+impl Upstream {
+    pub fn new(url: String) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            client: Client::new(),
+            cache: RwLock::new(None),
+            inflight: Mutex::new(None),
+        }
+    }
+
+    fn cached(&self) -> Option<Arc<Vec<Contract>>> {
+        match &*self.cache.read().unwrap() {
+            Some((fetched, contracts)) if fetched.elapsed() < FRESHNESS => Some(contracts.clone()),
+            _ => None,
+        }
+    }
+
+    /// Gets all contracts, fetching them from the contractmgr if stale.
+    pub async fn contracts(&self) -> Fetched {
+        if let Some(contracts) = self.cached() {
+            return Ok(contracts);
+        }
+
+        // Join the fetch in flight, or start one. The cache is checked again
+        // since a fetch may have finished since it was last looked at.
+        let shared = {
+            let mut inflight = self.inflight.lock().unwrap();
+            if let Some(contracts) = self.cached() {
+                return Ok(contracts);
+            }
+
+            match &*inflight {
+                Some(shared) => shared.clone(),
+                None => {
+                    let shared = fetch(self.client.clone(), self.url.clone())
+                        .boxed()
+                        .shared();
+                    *inflight = Some(shared.clone());
+                    shared
+                }
+            }
+        };
+
+        let fetched = shared.clone().await;
+
+        // The first waiter to finish caches the result and clears the fetch,
+        // so that a failure is retried by the next request rather than by
+        // every waiter in turn.
+        let mut inflight = self.inflight.lock().unwrap();
+        if let Some(current) = &*inflight {
+            if current.ptr_eq(&shared) {
+                if let Ok(contracts) = &fetched {
+                    *self.cache.write().unwrap() = Some((Instant::now(), contracts.clone()));
+                }
+
+                *inflight = None;
+            }
+        }
+
+        fetched
+    }
+}
@@ -2,12 +2,68 @@
 
 #![deny(clippy::all)]
 
-use koine::{Backend, Contract};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-use warp::http::header::{HeaderValue, CONTENT_TYPE};
-use warp::http::StatusCode;
+use koine::{Backend, Contract, Host, Probe};
+
+use uuid::Uuid;
+use warp::http::header::{HeaderValue, CONTENT_TYPE, LOCATION};
+use warp::http::{Response, StatusCode};
+use warp::Filter;
+
+/// Serves `contracts` like a slow contractmgr, counting the fetches.
+async fn spawn_upstream(contracts: Vec<Contract>) -> (String, Arc<AtomicUsize>) {
+    spawn_upstream_with(StatusCode::OK, contracts).await
+}
+
+/// Like [`spawn_upstream`], but answering with `status`.
+async fn spawn_upstream_with(
+    status: StatusCode,
+    contracts: Vec<Contract>,
+) -> (String, Arc<AtomicUsize>) {
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let counter = fetches.clone();
+
+    let route = warp::path!("contracts").and_then(move || {
+        let counter = counter.clone();
+        let contracts = contracts.clone();
+        async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            let mut body = Vec::new();
+            ciborium::ser::into_writer(&contracts, &mut body).unwrap();
+            Ok::<_, std::convert::Infallible>(
+                Response::builder()
+                    .status(status)
+                    .header(CONTENT_TYPE, "application/cbor")
+                    .body(body)
+                    .unwrap(),
+            )
+        }
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let incoming = TcpListenerStream::new(listener);
+    tokio::spawn(warp::serve(route).run_incoming(incoming));
+
+    (url, fetches)
+}
 
 async fn spawn_server(timeout: &str) -> tokio::io::Result<(String, tokio::process::Child)> {
+    spawn_server_with(timeout, &[]).await
+}
+
+async fn spawn_server_with(
+    timeout: &str,
+    args: &[&str],
+) -> tokio::io::Result<(String, tokio::process::Child)> {
     const BIN: &str = env!("CARGO_BIN_EXE_keepmgr");
 
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
@@ -33,6 +89,7 @@ async fn spawn_server(timeout: &str) -> tokio::io::Result<(String, tokio::proces
                 .arg(timeout)
                 .arg(BIN)
                 .arg(&host)
+                .args(args)
                 .spawn()?;
 
             // Wait for the server to start.
@@ -89,3 +146,450 @@ async fn get_contracts_uuid() {
         assert_eq!(contract, ciborium::de::from_reader(&bytes[..]).unwrap());
     }
 }
+
+#[tokio::test]
+async fn get_contracts_upstream_coalesced() {
+    const REQUESTS: usize = 20;
+
+    let contract = Contract::new(
+        Uuid::from_u128(0x2e0d2f8f_4a4d_4c5e_8a4c_6fa1f0a5a3c1),
+        Backend::Nil,
+    );
+
+    let (upstream, fetches) = spawn_upstream(vec![contract.clone()]).await;
+    let (host, _) = spawn_server_with("5", &["--upstream", &upstream])
+        .await
+        .unwrap();
+
+    // Hit the cold cache with many concurrent requests
+    let url = format!("http://{}/contracts", host);
+    let requests: Vec<_> = (0..REQUESTS)
+        .map(|_| {
+            let url = url.clone();
+            tokio::spawn(async move { reqwest::get(&url).await.unwrap() })
+        })
+        .collect();
+
+    for request in requests {
+        let response = request.await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let bytes = response.bytes().await.unwrap();
+        let contracts: Vec<Contract> = ciborium::de::from_reader(&bytes[..]).unwrap();
+        assert_eq!(contracts, vec![contract.clone()]);
+    }
+
+    // The upstream only saw a single fetch
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn get_contracts_upstream_failed() {
+    const REQUESTS: usize = 20;
+
+    let (upstream, fetches) = spawn_upstream_with(StatusCode::SERVICE_UNAVAILABLE, vec![]).await;
+    let (host, _) = spawn_server_with("5", &["--upstream", &upstream])
+        .await
+        .unwrap();
+
+    let url = format!("http://{}/contracts", host);
+    let requests: Vec<_> = (0..REQUESTS)
+        .map(|_| {
+            let url = url.clone();
+            tokio::spawn(async move { reqwest::get(&url).await.unwrap() })
+        })
+        .collect();
+
+    for request in requests {
+        let response = request.await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    // The waiters shared the failure rather than each retrying
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    // But the next request does retry
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn get_contracts_upstream_empty() {
+    let (upstream, _) = spawn_upstream(Vec::new()).await;
+    let (host, _) = spawn_server_with("5", &["--upstream", &upstream])
+        .await
+        .unwrap();
+
+    // An upstream offering nothing is listed as such
+    let url = format!("http://{}/contracts", host);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.bytes().await.unwrap();
+    let contracts: Vec<Contract> = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert!(contracts.is_empty());
+
+    // And nothing can be launched
+    let url = format!("http://{}/contracts/{}", host, Uuid::nil());
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn print_config() {
+    const BIN: &str = env!("CARGO_BIN_EXE_keepmgr");
+
+    let output = tokio::process::Command::new(BIN)
+        .arg("127.0.0.1:0")
+        .arg("--upstream")
+        .arg("http://127.0.0.1:1")
+        .arg("--print-config")
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(config["listen"].as_str().unwrap().starts_with("127.0.0.1:"));
+    assert_eq!(config["upstream"], "http://127.0.0.1:1");
+}
+
+#[tokio::test]
+async fn deadline() {
+    let contract = Contract::new(
+        Uuid::from_u128(0x5b7c1d2e_9f3a_4e61_b0c8_2d4f6a8e1c37),
+        Backend::Nil,
+    );
+
+    let (upstream, fetches) = spawn_upstream(vec![contract]).await;
+    let (host, _) = spawn_server_with("5", &["--upstream", &upstream])
+        .await
+        .unwrap();
+
+    let url = format!("http://{}/contracts", host);
+    let client = reqwest::Client::new();
+
+    // A deadline which has already passed is refused without any work
+    let response = client
+        .get(&url)
+        .header("x-deadline", "2000-01-01T00:00:00Z")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(fetches.load(Ordering::SeqCst), 0);
+
+    // The slow upstream can't answer before a close deadline
+    let soon = chrono::Utc::now() + chrono::Duration::milliseconds(50);
+    let response = client
+        .get(&url)
+        .header("x-deadline", soon.to_rfc3339())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+
+    let response = client
+        .get(&url)
+        .header("x-deadline", "tomorrow")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn get_capacity() {
+    #[derive(Debug, serde::Deserialize)]
+    struct Capacity {
+        backend: Backend,
+        configured: usize,
+        available: usize,
+    }
+
+    let (host, _) = spawn_server_with("5", &["--capacity", "nil=3,sgx=8"])
+        .await
+        .unwrap();
+
+    let url = format!("http://{}/capacity", host);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE),
+        Some(&HeaderValue::from_static("application/cbor"))
+    );
+
+    let bytes = response.bytes().await.unwrap();
+    let capacity: Vec<Capacity> = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Every host can run nil keeps, but only some can run sgx ones
+    let nil = capacity.iter().find(|c| c.backend == Backend::Nil).unwrap();
+    assert_eq!((nil.configured, nil.available), (3, 3));
+    for other in capacity.iter().filter(|c| c.backend != Backend::Nil) {
+        assert_eq!(other.backend, Backend::Sgx);
+        assert_eq!((other.configured, other.available), (8, 8));
+    }
+}
+
+#[tokio::test]
+async fn get_capacity_auto() {
+    #[derive(Debug, serde::Deserialize)]
+    struct Capacity {
+        backend: Backend,
+        configured: usize,
+        available: usize,
+    }
+
+    let args = ["--auto-capacity", "--capacity", "nil=3"];
+    let (host, _) = spawn_server_with("5", &args).await.unwrap();
+
+    let url = format!("http://{}/capacity", host);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = response.bytes().await.unwrap();
+    let capacity: Vec<Capacity> = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Nil hardware reports nothing, so the configured count is used
+    let nil = capacity.iter().find(|c| c.backend == Backend::Nil).unwrap();
+    assert_eq!((nil.configured, nil.available), (3, 3));
+
+    // Any other backend is one this host's hardware reported on
+    for other in capacity.iter().filter(|c| c.backend != Backend::Nil) {
+        assert!(other.configured > 0);
+        assert_eq!(other.configured, other.available);
+    }
+}
+
+#[tokio::test]
+async fn post_contracts_uuid_launch() {
+    use std::os::unix::fs::PermissionsExt;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Capacity {
+        available: usize,
+    }
+
+    const NIL: &str = "e6234733-513a-4883-981a-bfa972fa706b";
+
+    // A launcher which records how it was run and then stays up
+    let dir = std::env::temp_dir().join(format!("keepmgr-{}", rand::random::<u64>()));
+    std::fs::create_dir(&dir).unwrap();
+    let output = dir.join("launched");
+    let script = dir.join("launch.sh");
+    std::fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$KEEP_BACKEND $KEEP_UUID $@\" > {}\nexec sleep 5\n",
+            output.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let launcher = format!("nil={}", script.display());
+    let args = ["--capacity", "nil=3", "--launcher", &launcher];
+    let (host, _) = spawn_server_with("5", &args).await.unwrap();
+
+    let url = format!("http://{}/contracts/{}", host, NIL);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let bytes = response.bytes().await.unwrap();
+    let keep: franca::Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(keep.contract.uuid.to_string(), NIL);
+
+    // Wait for the launcher to report in
+    let mut launched = String::new();
+    for _ in 0..100 {
+        launched = std::fs::read_to_string(&output).unwrap_or_default();
+        if launched.ends_with('\n') {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let expected = format!("nil {} {} {}\n", keep.uuid, keep.uuid, NIL);
+    assert_eq!(launched, expected);
+
+    // The running keep counts against the capacity
+    let url = format!("http://{}/capacity", host);
+    let response = reqwest::get(&url).await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let capacity: Vec<Capacity> = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(capacity.len(), 1);
+    assert_eq!(capacity[0].available, 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // A launcher which can't be run is a server error
+    let launcher = format!("nil={}", dir.join("missing").display());
+    let (host, _) = spawn_server_with("5", &["--launcher", &launcher])
+        .await
+        .unwrap();
+
+    let url = format!("http://{}/contracts/{}", host, NIL);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    // A backend without any launcher can't start keeps at all
+    let (host, _) = spawn_server("5").await.unwrap();
+    let url = format!("http://{}/contracts/{}", host, NIL);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn post_contracts_uuid_handle() {
+    use std::os::unix::fs::PermissionsExt;
+
+    const NIL: &str = "e6234733-513a-4883-981a-bfa972fa706b";
+
+    // A launcher which reports a fixed handle and then stays up
+    let dir = std::env::temp_dir().join(format!("keepmgr-{}", rand::random::<u64>()));
+    std::fs::create_dir(&dir).unwrap();
+    let script = dir.join("launch.sh");
+    std::fs::write(&script, "#!/bin/sh\necho vsock:3:1024\nexec sleep 5\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let launcher = format!("nil={}", script.display());
+    let (host, _) = spawn_server_with("5", &["--launcher", &launcher])
+        .await
+        .unwrap();
+
+    let url = format!("http://{}/contracts/{}", host, NIL);
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let bytes = response.bytes().await.unwrap();
+    let keep: franca::Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(keep.handle.as_deref(), Some("vsock:3:1024"));
+
+    // The running keep can be looked up again, handle and all
+    let url = format!("http://{}/keeps/{}", host, keep.uuid);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = response.bytes().await.unwrap();
+    let found: franca::Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(found, keep);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Keeps this host isn't running aren't found
+    let url = format!("http://{}/keeps/{}", host, Uuid::new_v4());
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn post_contracts_uuid_queued() {
+    use std::os::unix::fs::PermissionsExt;
+
+    const NIL: &str = "e6234733-513a-4883-981a-bfa972fa706b";
+    const KEEPS: usize = 3;
+
+    // A launcher which is slow to report its handle and then stays up
+    let dir = std::env::temp_dir().join(format!("keepmgr-{}", rand::random::<u64>()));
+    std::fs::create_dir(&dir).unwrap();
+    let script = dir.join("launch.sh");
+    std::fs::write(
+        &script,
+        "#!/bin/sh\nsleep 0.5\necho \"vsock:$KEEP_UUID\"\nexec sleep 10\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let launcher = format!("nil={}", script.display());
+    let args = ["--launcher", &launcher, "--max-provisioning", "1"];
+    let (host, _) = spawn_server_with("10", &args).await.unwrap();
+
+    // Ask for more keeps at once than may launch at once
+    let url = format!("http://{}/contracts/{}", host, NIL);
+    let requests: Vec<_> = (0..KEEPS)
+        .map(|_| {
+            let url = url.clone();
+            tokio::spawn(async move { reqwest::Client::new().post(&url).send().await.unwrap() })
+        })
+        .collect();
+
+    let mut queued = Vec::new();
+    for request in requests {
+        let response = request.await.unwrap();
+        match response.status() {
+            StatusCode::CREATED => (),
+            StatusCode::ACCEPTED => {
+                let location = response.headers()[LOCATION].to_str().unwrap().to_owned();
+                let bytes = response.bytes().await.unwrap();
+                let keep: franca::Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+                assert_eq!(location, format!("/keeps/{}", keep.uuid));
+                assert_eq!(keep.handle, None);
+                queued.push(location);
+            }
+            status => panic!("unexpected status: {}", status),
+        }
+    }
+
+    // Only one launched straight away; the rest waited their turn
+    assert_eq!(queued.len(), KEEPS - 1);
+
+    // Each queued keep is launched in the end
+    for location in queued {
+        let url = format!("http://{}{}", host, location);
+        let mut response = reqwest::get(&url).await.unwrap();
+        for _ in 0..100 {
+            if response.status() != StatusCode::ACCEPTED {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            response = reqwest::get(&url).await.unwrap();
+        }
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let bytes = response.bytes().await.unwrap();
+        let keep: franca::Keep = ciborium::de::from_reader(&bytes[..]).unwrap();
+        assert_eq!(keep.handle, Some(format!("vsock:{}", keep.uuid)));
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn get_contracts_supported() {
+    #[derive(Debug, serde::Deserialize)]
+    struct Annotated {
+        #[serde(flatten)]
+        contract: Contract,
+        supported: bool,
+    }
+
+    let (host, _) = spawn_server("5").await.unwrap();
+
+    let url = format!("http://{}/contracts", host);
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = response.bytes().await.unwrap();
+    let contracts: Vec<Annotated> = ciborium::de::from_reader(&bytes[..]).unwrap();
+
+    // Every built-in contract is listed, flagged by what this host can run
+    assert_eq!(contracts.len(), 4);
+    for annotated in &contracts {
+        let backend = &annotated.contract.backend;
+        assert_eq!(annotated.supported, Host.supports(backend));
+    }
+
+    // Nil keeps can run anywhere
+    let nil = contracts
+        .iter()
+        .find(|a| a.contract.backend == Backend::Nil);
+    assert!(nil.unwrap().supported);
+
+    // A single contract is annotated too
+    let contract = &contracts[0].contract;
+    let url = format!("http://{}/contracts/{}", host, contract.uuid);
+    let response = reqwest::get(&url).await.unwrap();
+    let bytes = response.bytes().await.unwrap();
+    let annotated: Annotated = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(&annotated.contract, contract);
+    assert_eq!(annotated.supported, contracts[0].supported);
+}
@@ -1,19 +1,59 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
-#[serde(rename_all = "lowercase")]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Backend {
     Nil,
     Sev,
     Sgx,
     Kvm,
+
+    /// A backend added after this version, known only by its name.
+    ///
+    /// This lets older clients decode contracts for newer backends.
+    Unknown(String),
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct UnknownBackend;
 
+/// How well a backend protects its keeps.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    /// Keeps run as ordinary processes; for testing only.
+    Unprotected,
+
+    /// Keeps are isolated from each other, but not from the host.
+    Isolated,
+
+    /// Keeps' memory is encrypted, so not even the host can read it.
+    Confidential,
+}
+
+/// Other names accepted for the backends, mapped to their canonical variant.
+///
+/// These keep older clients working as backend names evolve:
+///
+/// | Alias     | Backend |
+/// |-----------|---------|
+/// | `none`    | `nil`   |
+/// | `sev-es`  | `sev`   |
+/// | `sev-snp` | `sev`   |
+/// | `snp`     | `sev`   |
+/// | `kvm-x86` | `kvm`   |
+///
+/// Aliases are only accepted on input; `as_str()` always gives the
+/// canonical name.
+pub const ALIASES: &[(&str, Backend)] = &[
+    ("none", Backend::Nil),
+    ("sev-es", Backend::Sev),
+    ("sev-snp", Backend::Sev),
+    ("snp", Backend::Sev),
+    ("kvm-x86", Backend::Kvm),
+];
+
 impl std::str::FromStr for Backend {
     type Err = UnknownBackend;
 
@@ -23,24 +63,117 @@ impl std::str::FromStr for Backend {
             "sev" => Ok(Self::Sev),
             "sgx" => Ok(Self::Sgx),
             "kvm" => Ok(Self::Kvm),
-            _ => Err(UnknownBackend),
+            _ => ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == string)
+                .map(|(_, backend)| backend.clone())
+                .ok_or(UnknownBackend),
         }
     }
 }
 
+impl Serialize for Backend {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Backend {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(name.parse().unwrap_or(Self::Unknown(name)))
+    }
+}
+
 impl std::fmt::Display for Backend {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.as_str())
     }
 }
 
+/// Every backend known to this version.
+const ALL: &[Backend] = &[Backend::Nil, Backend::Sev, Backend::Sgx, Backend::Kvm];
+
 impl Backend {
-    pub const fn as_str(&self) -> &'static str {
+    /// Lists every backend known to this version, leaving out `Unknown`.
+    pub fn all() -> &'static [Backend] {
+        ALL
+    }
+
+    pub fn as_str(&self) -> &str {
         match *self {
             Backend::Nil => "nil",
             Backend::Sev => "sev",
             Backend::Sgx => "sgx",
             Backend::Kvm => "kvm",
+            Backend::Unknown(ref name) => name,
+        }
+    }
+
+    /// Parses a comma-separated list of backends, dropping duplicates.
+    pub fn parse_list(string: &str) -> Result<Vec<Self>, UnknownBackend> {
+        let mut backends = Vec::new();
+
+        for name in string.split(',') {
+            let backend = name.trim().parse()?;
+            if !backends.contains(&backend) {
+                backends.push(backend);
+            }
+        }
+
+        Ok(backends)
+    }
+
+    /// Lists the other names accepted for the backend.
+    pub fn aliases(&self) -> impl Iterator<Item = &'static str> + '_ {
+        ALIASES
+            .iter()
+            .filter(move |(_, backend)| backend == self)
+            .map(|(alias, _)| *alias)
+    }
+
+    /// The device the host needs to run keeps of the backend, if any.
+    pub const fn device(&self) -> Option<&'static str> {
+        match *self {
+            Backend::Kvm => Some("/dev/kvm"),
+            Backend::Sev => Some("/dev/sev"),
+            Backend::Sgx => Some("/dev/sgx_enclave"),
+            Backend::Nil | Backend::Unknown(..) => None,
+        }
+    }
+
+    /// How well the backend protects its keeps, if it is known.
+    pub const fn tier(&self) -> Option<Tier> {
+        match *self {
+            Backend::Nil => Some(Tier::Unprotected),
+            Backend::Kvm => Some(Tier::Isolated),
+            Backend::Sev => Some(Tier::Confidential),
+            Backend::Sgx => Some(Tier::Confidential),
+            Backend::Unknown(..) => None,
+        }
+    }
+
+    /// A short symbol hinting at the backend in human-oriented listings.
+    ///
+    /// Confidential backends are marked with a lock.
+    pub const fn display_hint(&self) -> &'static str {
+        match *self {
+            Backend::Nil => "\u{00b7}",
+            Backend::Sev => "\u{1f512}",
+            Backend::Sgx => "\u{1f512}",
+            Backend::Kvm => "\u{25a2}",
+            Backend::Unknown(..) => "?",
+        }
+    }
+
+    /// Like `display_hint()`, but safe for terminals without Unicode.
+    pub const fn ascii_hint(&self) -> &'static str {
+        match *self {
+            Backend::Nil => ".",
+            Backend::Sev => "#",
+            Backend::Sgx => "#",
+            Backend::Kvm => "o",
+            Backend::Unknown(..) => "?",
         }
     }
 }
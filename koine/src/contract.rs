@@ -2,11 +2,192 @@
 
 use super::backend::Backend;
 
+use chrono::{DateTime, Utc};
+use ciborium::value::Value;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Contract {
     pub uuid: Uuid,
     pub backend: Backend,
+
+    /// The contract cannot be claimed before this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<DateTime<Utc>>,
+
+    /// The contract cannot be claimed after this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<DateTime<Utc>>,
+
+    /// The relative cost of running a keep under the contract.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<u32>,
+
+    /// A policy that keeps must satisfy at attestation, for confidential
+    /// backends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation_policy: Option<Vec<u8>>,
+
+    /// The region where keeps of the contract run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+
+    /// Launch parameters for the backend, such as SEV policy bits or SGX
+    /// attributes, in whatever shape the backend expects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+
+    /// The least number of seconds between claims of the contract, to
+    /// smooth bursts of claims on a scarce backend.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claim_cooldown: Option<u64>,
+
+    /// Whether the contract is offered at all. Unlike expiry, disabling a
+    /// contract is meant to be temporary.
+    #[serde(default = "enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+/// A mistake which means a contract can never be claimed as written.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Invalid {
+    /// The UUID is the nil UUID.
+    NilUuid,
+
+    /// The backend is not one this version knows.
+    UnknownBackend(String),
+
+    /// The contract expires before it can first be claimed.
+    EmptyWindow,
+
+    /// The region is given but empty.
+    EmptyRegion,
+}
+
+impl std::fmt::Display for Invalid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Invalid::NilUuid => write!(f, "the uuid is nil"),
+            Invalid::UnknownBackend(name) => write!(f, "unknown backend: {}", name),
+            Invalid::EmptyWindow => write!(f, "not_after is before not_before"),
+            Invalid::EmptyRegion => write!(f, "the region is empty"),
+        }
+    }
+}
+
+/// The size of a blob, shown in its place.
+struct Size(usize);
+
+impl std::fmt::Debug for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes", self.0)
+    }
+}
+
+// Policies are opaque and can be large, so only their size is shown.
+impl std::fmt::Debug for Contract {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let policy = self.attestation_policy.as_ref().map(|p| Size(p.len()));
+
+        f.debug_struct("Contract")
+            .field("uuid", &self.uuid)
+            .field("backend", &self.backend)
+            .field("not_before", &self.not_before)
+            .field("not_after", &self.not_after)
+            .field("cost", &self.cost)
+            .field("attestation_policy", &policy)
+            .field("region", &self.region)
+            .field("params", &self.params)
+            .field("claim_cooldown", &self.claim_cooldown)
+            .field("enabled", &self.enabled)
+            .finish()
+    }
+}
+
+impl Contract {
+    /// A contract for keeps of `backend`, offered without any of the
+    /// optional terms.
+    pub const fn new(uuid: Uuid, backend: Backend) -> Self {
+        Self {
+            uuid,
+            backend,
+            not_before: None,
+            not_after: None,
+            cost: None,
+            attestation_policy: None,
+            region: None,
+            params: None,
+            claim_cooldown: None,
+            enabled: true,
+        }
+    }
+
+    /// Whether both are the same contract, ignoring the terms which can
+    /// change over its life, such as its cost or validity.
+    ///
+    /// Use `==` to compare every field.
+    pub fn core_eq(&self, other: &Contract) -> bool {
+        self.uuid == other.uuid && self.backend == other.backend
+    }
+
+    /// Orders contracts cheapest first, with those of unknown cost last.
+    pub fn cost_order(&self) -> (bool, Option<u32>) {
+        (self.cost.is_none(), self.cost)
+    }
+
+    /// Checks the contract for mistakes, returning every one found.
+    pub fn validate(&self) -> Result<(), Vec<Invalid>> {
+        let mut invalid = Vec::new();
+
+        if self.uuid.is_nil() {
+            invalid.push(Invalid::NilUuid);
+        }
+
+        if let Backend::Unknown(ref name) = self.backend {
+            invalid.push(Invalid::UnknownBackend(name.clone()));
+        }
+
+        if let (Some(not_before), Some(not_after)) = (self.not_before, self.not_after) {
+            if not_after < not_before {
+                invalid.push(Invalid::EmptyWindow);
+            }
+        }
+
+        if self.region.as_deref() == Some("") {
+            invalid.push(Invalid::EmptyRegion);
+        }
+
+        match invalid.is_empty() {
+            true => Ok(()),
+            false => Err(invalid),
+        }
+    }
+
+    /// Whether the contract can be claimed at the given time.
+    pub fn is_valid_at(&self, time: DateTime<Utc>) -> bool {
+        if let Some(not_before) = self.not_before {
+            if time < not_before {
+                return false;
+            }
+        }
+
+        !self.is_expired_at(time)
+    }
+
+    /// Whether the contract can no longer be claimed at the given time.
+    pub fn is_expired_at(&self, time: DateTime<Utc>) -> bool {
+        match self.not_after {
+            Some(not_after) => not_after < time,
+            None => false,
+        }
+    }
 }
@@ -4,6 +4,10 @@
 
 mod backend;
 mod contract;
+mod probe;
+pub mod timestamp;
 
-pub use backend::Backend;
-pub use contract::Contract;
+pub use backend::{Backend, Tier, ALIASES};
+pub use contract::{Contract, Invalid};
+pub use probe::{Host, Probe};
+pub use timestamp::Timestamp;
@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use super::backend::Backend;
+
+use std::path::Path;
+
+/// Decides which backends can run on a host.
+pub trait Probe: std::fmt::Debug + Send + Sync {
+    fn supports(&self, backend: &Backend) -> bool;
+
+    /// The most keeps of the backend the hardware can run, if it says.
+    fn capacity(&self, _backend: &Backend) -> Option<usize> {
+        None
+    }
+
+    /// Lists the capacity of each supported backend whose hardware has one.
+    fn capacities(&self) -> Vec<(Backend, usize)> {
+        Backend::all()
+            .iter()
+            .filter(|backend| self.supports(backend))
+            .filter_map(|backend| Some((backend.clone(), self.capacity(backend)?)))
+            .collect()
+    }
+}
+
+/// Probes the local host for the devices each backend needs.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Host;
+
+impl Probe for Host {
+    fn supports(&self, backend: &Backend) -> bool {
+        match (backend, backend.device()) {
+            (Backend::Nil, _) => true,
+            (_, Some(device)) => Path::new(device).exists(),
+            (_, None) => false,
+        }
+    }
+
+    fn capacity(&self, backend: &Backend) -> Option<usize> {
+        backend.detect_capacity()
+    }
+}
+
+impl Backend {
+    /// Asks the processor how many keeps of the backend it can run at once.
+    ///
+    /// SEV reports the number of guests it can encrypt at once, one per
+    /// ASID. SGX reports the size of its enclave page cache, and as every
+    /// enclave needs at least one page, the number of pages is an upper
+    /// bound. Other backends are only limited by memory, so have no answer.
+    pub fn detect_capacity(&self) -> Option<usize> {
+        match *self {
+            Backend::Sev => cpuid::sev_asids(),
+            Backend::Sgx => cpuid::sgx_epc_pages(),
+            _ => None,
+        }
+    }
+}
+
+// Newer toolchains no longer need `unsafe` to read CPUID.
+#[cfg(target_arch = "x86_64")]
+#[allow(unused_unsafe)]
+mod cpuid {
+    use std::arch::x86_64::{__cpuid_count, __get_cpuid_max};
+
+    pub fn sev_asids() -> Option<usize> {
+        let (max, _) = unsafe { __get_cpuid_max(0x8000_0000) };
+        if max < 0x8000_001f {
+            return None;
+        }
+
+        let asids = unsafe { __cpuid_count(0x8000_001f, 0) }.ecx as usize;
+        Some(asids).filter(|n| *n > 0)
+    }
+
+    pub fn sgx_epc_pages() -> Option<usize> {
+        let (max, _) = unsafe { __get_cpuid_max(0) };
+        if max < 0x12 {
+            return None;
+        }
+
+        // Each valid sub-leaf from 2 onwards describes an EPC section.
+        let mut bytes = 0u64;
+        for section in 2.. {
+            let leaf = unsafe { __cpuid_count(0x12, section) };
+            if leaf.eax & 0xf != 1 {
+                break;
+            }
+
+            let low = u64::from(leaf.ecx & 0xffff_f000);
+            let high = u64::from(leaf.edx & 0x000f_ffff);
+            bytes += low | high << 32;
+        }
+
+        Some((bytes / 4096) as usize).filter(|n| *n > 0)
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod cpuid {
+    pub fn sev_asids() -> Option<usize> {
+        None
+    }
+
+    pub fn sgx_epc_pages() -> Option<usize> {
+        None
+    }
+}
@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A point in time, written as suits the format.
+//!
+//! By default a [`Timestamp`] is an RFC 3339 string in human-readable formats,
+//! such as JSON, and an integer count of seconds since the Unix epoch in
+//! compact ones, such as CBOR. Either is accepted when reading, whatever the
+//! format. A field can insist on one representation with `#[serde(with =
+//! "koine::timestamp::rfc3339")]` or `#[serde(with = "koine::timestamp::epoch")]`.
+
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::de::{Error, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A point in time, to the second, no earlier than the Unix epoch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    /// The timestamp `secs` seconds after the Unix epoch.
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(secs)
+    }
+
+    /// The number of seconds since the Unix epoch.
+    pub const fn secs(&self) -> u64 {
+        self.0
+    }
+
+    /// The current time.
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    /// Writes the timestamp as an RFC 3339 string in UTC.
+    ///
+    /// Times too far off to be written are written as the latest that can.
+    pub fn to_rfc3339(&self) -> String {
+        i64::try_from(self.0)
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self(since.as_secs())
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_rfc3339())
+    }
+}
+
+impl std::str::FromStr for Timestamp {
+    type Err = chrono::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let time = DateTime::parse_from_rfc3339(s)?;
+        Ok(Self(time.timestamp().max(0) as u64))
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match serializer.is_human_readable() {
+            true => rfc3339::serialize(self, serializer),
+            false => epoch::serialize(self, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(Either)
+    }
+}
+
+/// Reads a timestamp in either representation.
+struct Either;
+
+impl<'de> Visitor<'de> for Either {
+    type Value = Timestamp;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("an RFC 3339 timestamp or seconds since the Unix epoch")
+    }
+
+    fn visit_u64<E: Error>(self, secs: u64) -> Result<Self::Value, E> {
+        Ok(Timestamp(secs))
+    }
+
+    fn visit_i64<E: Error>(self, secs: i64) -> Result<Self::Value, E> {
+        match secs < 0 {
+            true => Err(E::invalid_value(Unexpected::Signed(secs), &self)),
+            false => Ok(Timestamp(secs as u64)),
+        }
+    }
+
+    fn visit_str<E: Error>(self, s: &str) -> Result<Self::Value, E> {
+        s.parse()
+            .map_err(|_| E::invalid_value(Unexpected::Str(s), &self))
+    }
+}
+
+/// Always writes a timestamp as an RFC 3339 string.
+pub mod rfc3339 {
+    use super::{Either, Timestamp};
+
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &Timestamp, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Timestamp, D::Error> {
+        deserializer.deserialize_any(Either)
+    }
+}
+
+/// Always writes a timestamp as seconds since the Unix epoch.
+pub mod epoch {
+    use super::{Either, Timestamp};
+
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &Timestamp, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(time.secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Timestamp, D::Error> {
+        deserializer.deserialize_any(Either)
+    }
+}
@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use koine::{Backend, Contract, Tier, ALIASES};
+
+use serde::Serialize;
+
+#[test]
+fn display_hint() {
+    assert_eq!(Backend::Nil.display_hint(), "\u{00b7}");
+    assert_eq!(Backend::Sev.display_hint(), "\u{1f512}");
+    assert_eq!(Backend::Sgx.display_hint(), "\u{1f512}");
+    assert_eq!(Backend::Kvm.display_hint(), "\u{25a2}");
+}
+
+#[test]
+fn ascii_hint() {
+    for backend in &[Backend::Nil, Backend::Sev, Backend::Sgx, Backend::Kvm] {
+        assert!(backend.ascii_hint().is_ascii());
+    }
+
+    assert_eq!(Backend::Nil.ascii_hint(), ".");
+    assert_eq!(Backend::Sev.ascii_hint(), "#");
+    assert_eq!(Backend::Sgx.ascii_hint(), "#");
+    assert_eq!(Backend::Kvm.ascii_hint(), "o");
+}
+
+#[test]
+fn all() {
+    for backend in Backend::all() {
+        assert_eq!(&backend.as_str().parse::<Backend>().unwrap(), backend);
+    }
+
+    assert_eq!(Backend::all().len(), 4);
+}
+
+#[test]
+fn parse_list() {
+    let backends = Backend::parse_list("sev,sgx").unwrap();
+    assert_eq!(backends, vec![Backend::Sev, Backend::Sgx]);
+
+    let backends = Backend::parse_list(" kvm , nil,kvm ").unwrap();
+    assert_eq!(backends, vec![Backend::Kvm, Backend::Nil]);
+
+    assert!(Backend::parse_list("sev,tdx").is_err());
+    assert!(Backend::parse_list("sev,,sgx").is_err());
+}
+
+#[test]
+fn aliases() {
+    let expected = [
+        ("none", Backend::Nil),
+        ("sev-es", Backend::Sev),
+        ("sev-snp", Backend::Sev),
+        ("snp", Backend::Sev),
+        ("kvm-x86", Backend::Kvm),
+    ];
+
+    assert_eq!(ALIASES.len(), expected.len());
+    for (alias, backend) in &expected {
+        let parsed: Backend = alias.parse().unwrap();
+        assert_eq!(&parsed, backend);
+        assert_eq!(parsed.as_str(), backend.as_str());
+        assert_ne!(parsed.as_str(), *alias);
+    }
+
+    // Aliases decode to the canonical name and are re-encoded as such
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(&"sev-es", &mut bytes).unwrap();
+    let backend: Backend = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(backend, Backend::Sev);
+    assert_eq!(backend.to_string(), "sev");
+
+    let backends = Backend::parse_list("snp,sev").unwrap();
+    assert_eq!(backends, vec![Backend::Sev]);
+}
+
+#[test]
+fn metadata() {
+    let aliases: Vec<_> = Backend::Sev.aliases().collect();
+    assert_eq!(aliases, vec!["sev-es", "sev-snp", "snp"]);
+    assert_eq!(Backend::Sgx.aliases().count(), 0);
+
+    assert_eq!(Backend::Nil.device(), None);
+    assert_eq!(Backend::Kvm.device(), Some("/dev/kvm"));
+    assert_eq!(Backend::Sgx.device(), Some("/dev/sgx_enclave"));
+
+    assert_eq!(Backend::Nil.tier(), Some(Tier::Unprotected));
+    assert_eq!(Backend::Kvm.tier(), Some(Tier::Isolated));
+    assert_eq!(Backend::Sev.tier(), Some(Tier::Confidential));
+
+    let unknown = Backend::Unknown("tdx".into());
+    assert_eq!(unknown.aliases().count(), 0);
+    assert_eq!(unknown.device(), None);
+    assert_eq!(unknown.tier(), None);
+}
+
+#[test]
+fn unknown() {
+    #[derive(Serialize)]
+    struct Future {
+        uuid: uuid::Uuid,
+        backend: &'static str,
+    }
+
+    let future = Future {
+        uuid: uuid::Uuid::nil(),
+        backend: "tdx",
+    };
+
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(&future, &mut bytes).unwrap();
+
+    // Unknown backends are preserved rather than rejected
+    let contract: Contract = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(contract.backend, Backend::Unknown("tdx".into()));
+    assert_eq!(contract.backend.as_str(), "tdx");
+    assert_eq!(contract.backend.ascii_hint(), "?");
+
+    // ... and are encoded just as they were received
+    let mut again = Vec::new();
+    ciborium::ser::into_writer(&contract, &mut again).unwrap();
+    assert_eq!(again, bytes);
+
+    // Known backends still decode as themselves
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(&"sgx", &mut bytes).unwrap();
+    let backend: Backend = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(backend, Backend::Sgx);
+}
@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use koine::{Backend, Contract};
+
+use ciborium::value::Value;
+use uuid::Uuid;
+
+#[test]
+fn core_eq() {
+    let contract = Contract {
+        cost: Some(1),
+        ..Contract::new(
+            Uuid::from_u128(0x7d2c4e1a_5b3f_4c8d_9e6a_1f0b2c3d4e5f),
+            Backend::Sev,
+        )
+    };
+
+    // Changing the terms leaves the same contract, but not an equal one
+    let repriced = Contract {
+        cost: Some(2),
+        region: Some("eu".into()),
+        ..contract.clone()
+    };
+    assert!(contract.core_eq(&repriced));
+    assert_ne!(contract, repriced);
+
+    // Changing its identity makes it another contract
+    let moved = Contract {
+        backend: Backend::Sgx,
+        ..contract.clone()
+    };
+    assert!(!contract.core_eq(&moved));
+
+    let renamed = Contract {
+        uuid: Uuid::from_u128(1),
+        ..contract.clone()
+    };
+    assert!(!contract.core_eq(&renamed));
+
+    assert!(contract.core_eq(&contract));
+    assert_eq!(contract, contract.clone());
+}
+
+#[test]
+fn params() {
+    let params = Value::Map(vec![
+        (Value::Text("policy".into()), Value::Integer(0x30.into())),
+        (Value::Text("debug".into()), Value::Bool(false)),
+        (
+            Value::Text("measurements".into()),
+            Value::Array(vec![Value::Text("a1".into()), Value::Text("b2".into())]),
+        ),
+    ]);
+
+    let contract = Contract {
+        params: Some(params),
+        ..Contract::new(
+            Uuid::from_u128(0x7d2c4e1a_5b3f_4c8d_9e6a_1f0b2c3d4e5f),
+            Backend::Sev,
+        )
+    };
+
+    let mut cbor = Vec::new();
+    ciborium::ser::into_writer(&contract, &mut cbor).unwrap();
+    let decoded: Contract = ciborium::de::from_reader(&cbor[..]).unwrap();
+    assert_eq!(decoded, contract);
+
+    let json = serde_json::to_string(&contract).unwrap();
+    let decoded: Contract = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, contract);
+
+    // Contracts without params leave the field out entirely
+    let plain = Contract {
+        params: None,
+        ..contract
+    };
+    let json = serde_json::to_value(&plain).unwrap();
+    assert!(json.get("params").is_none());
+}
@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use koine::{Backend, Host, Probe};
+
+/// A host with fixed hardware, which supports sev and sgx.
+#[derive(Debug)]
+struct Fixed;
+
+impl Probe for Fixed {
+    fn supports(&self, backend: &Backend) -> bool {
+        matches!(backend, Backend::Nil | Backend::Sev | Backend::Sgx)
+    }
+
+    fn capacity(&self, backend: &Backend) -> Option<usize> {
+        match backend {
+            Backend::Sev => Some(509),
+            Backend::Sgx => Some(24064),
+            Backend::Kvm => Some(1),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn capacities() {
+    // Only supported backends whose hardware has a capacity are listed
+    assert_eq!(
+        Fixed.capacities(),
+        vec![(Backend::Sev, 509), (Backend::Sgx, 24064)]
+    );
+}
+
+#[test]
+fn detect_capacity() {
+    // Nothing limits these but memory
+    assert_eq!(Backend::Nil.detect_capacity(), None);
+    assert_eq!(Backend::Kvm.detect_capacity(), None);
+    assert_eq!(Host.capacity(&Backend::Nil), None);
+
+    // The host never lists a backend it cannot run
+    for (backend, count) in Host.capacities() {
+        assert!(Host.supports(&backend));
+        assert!(count > 0);
+    }
+}
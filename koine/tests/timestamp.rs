@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(clippy::all)]
+
+use koine::Timestamp;
+
+use ciborium::value::Value;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Stamped {
+    at: Timestamp,
+
+    #[serde(with = "koine::timestamp::epoch")]
+    epoch: Timestamp,
+
+    #[serde(with = "koine::timestamp::rfc3339")]
+    rfc3339: Timestamp,
+}
+
+const AT: Timestamp = Timestamp::from_secs(1_609_459_200);
+
+#[test]
+fn json() {
+    let stamped = Stamped {
+        at: AT,
+        epoch: AT,
+        rfc3339: AT,
+    };
+
+    let json = serde_json::to_value(&stamped).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "at": "2021-01-01T00:00:00Z",
+            "epoch": 1_609_459_200,
+            "rfc3339": "2021-01-01T00:00:00Z",
+        })
+    );
+
+    let decoded: Stamped = serde_json::from_value(json).unwrap();
+    assert_eq!(decoded, stamped);
+}
+
+#[test]
+fn cbor() {
+    let stamped = Stamped {
+        at: AT,
+        epoch: AT,
+        rfc3339: AT,
+    };
+
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(&stamped, &mut bytes).unwrap();
+
+    let value: Value = ciborium::de::from_reader(&bytes[..]).unwrap();
+    let fields = match value {
+        Value::Map(fields) => fields,
+        other => panic!("not a map: {:?}", other),
+    };
+    assert_eq!(fields[0].1, Value::Integer(1_609_459_200.into()));
+    assert_eq!(fields[1].1, Value::Integer(1_609_459_200.into()));
+    assert_eq!(fields[2].1, Value::Text("2021-01-01T00:00:00Z".into()));
+
+    let decoded: Stamped = ciborium::de::from_reader(&bytes[..]).unwrap();
+    assert_eq!(decoded, stamped);
+}
+
+#[test]
+fn either_is_read() {
+    // Keeps saved before timestamps were strings are still read
+    let at: Timestamp = serde_json::from_str("1609459200").unwrap();
+    assert_eq!(at, AT);
+
+    let at: Timestamp = serde_json::from_str("\"2021-01-01T01:00:00+01:00\"").unwrap();
+    assert_eq!(at, AT);
+
+    assert!(serde_json::from_str::<Timestamp>("-1").is_err());
+    assert!(serde_json::from_str::<Timestamp>("\"yesterday\"").is_err());
+    assert_eq!(AT.to_string(), "2021-01-01T00:00:00Z");
+}